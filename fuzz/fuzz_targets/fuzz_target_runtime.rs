@@ -1,11 +1,11 @@
 #![no_main]
 
-use std::{cell::RefCell, collections::HashMap};
+use std::collections::HashMap;
 
 use graph::{
     graph::{
         GraphBLAS::{GrB_Mode, GrB_init},
-        graph::{Graph, Plan},
+        graph::{Graph, GraphCell, Plan},
     },
     runtime::{
         functions::init_functions,
@@ -20,11 +20,11 @@ fuzz_target!(init: {
         }
         init_functions().expect("Failed to init functions");
     },|data: &[u8]| -> Corpus {
-        let g = RefCell::new(Graph::new(1024, 1024));
+        let g = GraphCell::new(Graph::new(1024, 1024));
     std::str::from_utf8(data).map_or(Corpus::Reject, |query| {
         let Ok(Plan {
             plan, parameters, ..
-        }) = g.borrow().get_plan(query)
+        }) = g.borrow().get_plan(query, 0)
         else {
             return Corpus::Reject;
         };
@@ -35,7 +35,7 @@ fuzz_target!(init: {
         else {
             return Corpus::Reject;
         };
-        let mut runtime = Runtime::new(&g, parameters, true, plan, false, String::new());
+        let mut runtime = Runtime::new(&g, parameters, true, plan, false, false, String::new());
         match runtime.query() {
             Ok(_) => Corpus::Keep,
             _ => Corpus::Reject,
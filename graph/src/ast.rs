@@ -1,4 +1,4 @@
-use std::{collections::HashSet, fmt::Display, hash::Hash, rc::Rc};
+use std::{collections::HashSet, fmt::Display, hash::Hash, sync::Arc};
 
 use itertools::Itertools;
 use ordermap::{OrderMap, OrderSet};
@@ -8,7 +8,7 @@ use crate::runtime::functions::{GraphFn, Type};
 
 #[derive(Clone, Debug)]
 pub struct Variable {
-    pub name: Option<Rc<String>>,
+    pub name: Option<Arc<String>>,
     pub id: u32,
     pub ty: Type,
 }
@@ -46,7 +46,7 @@ pub enum ExprIR {
     Bool(bool),
     Integer(i64),
     Float(f64),
-    String(Rc<String>),
+    String(Arc<String>),
     List,
     Map,
     Variable(Variable),
@@ -75,7 +75,7 @@ pub enum ExprIR {
     Pow,
     Modulo,
     Distinct,
-    FuncInvocation(Rc<GraphFn>),
+    FuncInvocation(Arc<GraphFn>),
     Quantifier(QuantifierType, Variable),
     ListComprehension(Variable),
     Paren,
@@ -296,8 +296,8 @@ impl SupportAggregation for DynTree<ExprIR> {
 #[derive(Debug)]
 pub struct QueryNode {
     pub alias: Variable,
-    pub labels: OrderSet<Rc<String>>,
-    pub attrs: Rc<DynTree<ExprIR>>,
+    pub labels: OrderSet<Arc<String>>,
+    pub attrs: Arc<DynTree<ExprIR>>,
 }
 
 #[cfg_attr(tarpaulin, skip)]
@@ -322,8 +322,8 @@ impl QueryNode {
     #[must_use]
     pub const fn new(
         alias: Variable,
-        labels: OrderSet<Rc<String>>,
-        attrs: Rc<DynTree<ExprIR>>,
+        labels: OrderSet<Arc<String>>,
+        attrs: Arc<DynTree<ExprIR>>,
     ) -> Self {
         Self {
             alias,
@@ -336,11 +336,25 @@ impl QueryNode {
 #[derive(Debug)]
 pub struct QueryRelationship {
     pub alias: Variable,
-    pub types: Vec<Rc<String>>,
-    pub attrs: Rc<DynTree<ExprIR>>,
-    pub from: Rc<QueryNode>,
-    pub to: Rc<QueryNode>,
+    pub types: Vec<Arc<String>>,
+    pub attrs: Arc<DynTree<ExprIR>>,
+    pub from: Arc<QueryNode>,
+    pub to: Arc<QueryNode>,
     pub bidirectional: bool,
+    /// The minimum and maximum number of hops of a variable-length relationship `*min..max`,
+    /// no maximum for an unbounded relationship.
+    pub var_length: Option<(u64, Option<u64>)>,
+    /// Set for the relationship of a `shortestPath` or `allShortestPaths` pattern.
+    pub shortest: Option<ShortestPaths>,
+}
+
+/// The paths matched by a shortest path pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShortestPaths {
+    /// One of the shortest paths, `shortestPath`.
+    One,
+    /// Every shortest path, `allShortestPaths`.
+    All,
 }
 
 #[cfg_attr(tarpaulin, skip)]
@@ -350,22 +364,29 @@ impl Display for QueryRelationship {
         f: &mut std::fmt::Formatter<'_>,
     ) -> std::fmt::Result {
         let direction = if self.bidirectional { "" } else { ">" };
+        let var_length = match self.var_length {
+            None => String::new(),
+            Some((min, None)) => format!("*{min}.."),
+            Some((min, Some(max))) => format!("*{min}..{max}"),
+        };
         if self.types.is_empty() {
             return write!(
                 f,
-                "({})-[{}]-{}({})",
+                "({})-[{}{}]-{}({})",
                 self.from.alias.as_str(),
                 self.alias.as_str(),
+                var_length,
                 direction,
                 self.to.alias.as_str()
             );
         }
         write!(
             f,
-            "({})-[{}:{}]-{}({})",
+            "({})-[{}:{}{}]-{}({})",
             self.from.alias.as_str(),
             self.alias.as_str(),
             self.types.iter().map(|label| label.as_str()).join("|"),
+            var_length,
             direction,
             self.to.alias.as_str()
         )
@@ -376,11 +397,12 @@ impl QueryRelationship {
     #[must_use]
     pub const fn new(
         alias: Variable,
-        types: Vec<Rc<String>>,
-        attrs: Rc<DynTree<ExprIR>>,
-        from: Rc<QueryNode>,
-        to: Rc<QueryNode>,
+        types: Vec<Arc<String>>,
+        attrs: Arc<DynTree<ExprIR>>,
+        from: Arc<QueryNode>,
+        to: Arc<QueryNode>,
         bidirectional: bool,
+        var_length: Option<(u64, Option<u64>)>,
     ) -> Self {
         Self {
             alias,
@@ -389,6 +411,8 @@ impl QueryRelationship {
             from,
             to,
             bidirectional,
+            var_length,
+            shortest: None,
         }
     }
 }
@@ -411,9 +435,9 @@ impl QueryPath {
 
 #[derive(Clone, Debug, Default)]
 pub struct QueryGraph {
-    nodes: OrderMap<Variable, Rc<QueryNode>>,
-    relationships: OrderMap<Variable, Rc<QueryRelationship>>,
-    paths: OrderMap<Variable, Rc<QueryPath>>,
+    nodes: OrderMap<Variable, Arc<QueryNode>>,
+    relationships: OrderMap<Variable, Arc<QueryRelationship>>,
+    paths: OrderMap<Variable, Arc<QueryPath>>,
 }
 
 #[cfg_attr(tarpaulin, skip)]
@@ -438,14 +462,14 @@ impl Display for QueryGraph {
 impl QueryGraph {
     pub fn add_node(
         &mut self,
-        node: Rc<QueryNode>,
+        node: Arc<QueryNode>,
     ) -> bool {
         self.nodes.insert(node.alias.clone(), node).is_none()
     }
 
     pub fn add_relationship(
         &mut self,
-        relationship: Rc<QueryRelationship>,
+        relationship: Arc<QueryRelationship>,
     ) -> bool {
         self.relationships
             .insert(relationship.alias.clone(), relationship)
@@ -454,7 +478,7 @@ impl QueryGraph {
 
     pub fn add_path(
         &mut self,
-        path: Rc<QueryPath>,
+        path: Arc<QueryPath>,
     ) -> bool {
         self.paths.insert(path.var.clone(), path).is_none()
     }
@@ -470,17 +494,17 @@ impl QueryGraph {
     }
 
     #[must_use]
-    pub fn nodes(&self) -> Vec<Rc<QueryNode>> {
+    pub fn nodes(&self) -> Vec<Arc<QueryNode>> {
         self.nodes.values().cloned().collect()
     }
 
     #[must_use]
-    pub fn relationships(&self) -> Vec<Rc<QueryRelationship>> {
+    pub fn relationships(&self) -> Vec<Arc<QueryRelationship>> {
         self.relationships.values().cloned().collect()
     }
 
     #[must_use]
-    pub fn paths(&self) -> Vec<Rc<QueryPath>> {
+    pub fn paths(&self) -> Vec<Arc<QueryPath>> {
         self.paths.values().cloned().collect()
     }
 
@@ -528,7 +552,7 @@ impl QueryGraph {
 
     fn dfs(
         &self,
-        node: &Rc<QueryNode>,
+        node: &Arc<QueryNode>,
         visited: &mut HashSet<u32>,
         component: &mut Self,
     ) {
@@ -563,7 +587,7 @@ impl QueryGraph {
 
 #[derive(Debug)]
 pub enum QueryIR {
-    Call(Rc<String>, Vec<DynTree<ExprIR>>),
+    Call(Arc<String>, Vec<DynTree<ExprIR>>),
     Match {
         pattern: QueryGraph,
         filter: Option<DynTree<ExprIR>>,
@@ -575,6 +599,11 @@ pub enum QueryIR {
     Delete(Vec<DynTree<ExprIR>>, bool),
     Set(Vec<(DynTree<ExprIR>, DynTree<ExprIR>, bool)>),
     Remove(Vec<DynTree<ExprIR>>),
+    /// Runs the updating clauses once for each element of the list bound to the variable.
+    Foreach(DynTree<ExprIR>, Variable, Vec<QueryIR>),
+    /// Runs the query once for each row, committing its changes every batch size rows
+    /// when called `IN TRANSACTIONS`.
+    CallSubquery(Box<QueryIR>, Option<DynTree<ExprIR>>),
     LoadCsv {
         file_path: DynTree<ExprIR>,
         headers: bool,
@@ -599,14 +628,16 @@ pub enum QueryIR {
         write: bool,
     },
     CreateIndex {
-        label: Rc<String>,
-        attrs: Vec<Rc<String>>,
+        label: Arc<String>,
+        attrs: Vec<Arc<String>>,
     },
     DropIndex {
-        label: Rc<String>,
-        attrs: Vec<Rc<String>>,
+        label: Arc<String>,
+        attrs: Vec<Arc<String>>,
     },
     Query(Vec<QueryIR>, bool),
+    /// Queries returning the same columns, keeping duplicate rows when `UNION ALL`.
+    Union(Vec<QueryIR>, bool),
 }
 
 #[cfg_attr(tarpaulin, skip)]
@@ -674,17 +705,55 @@ impl Display for QueryIR {
             Self::DropIndex { label, attrs } => {
                 writeln!(f, "DROP NODE INDEX ON :{label}({attrs:?})")
             }
+            Self::Foreach(list, var, clauses) => {
+                writeln!(f, "FOREACH {}:", var.as_str())?;
+                write!(f, "{list}")?;
+                for clause in clauses {
+                    write!(f, "{clause}")?;
+                }
+                Ok(())
+            }
+            Self::CallSubquery(q, _) => {
+                writeln!(f, "CALL {{")?;
+                write!(f, "{q}")?;
+                writeln!(f, "}}")
+            }
             Self::Query(qs, _) => {
                 for q in qs {
                     write!(f, "{q}")?;
                 }
                 Ok(())
             }
+            Self::Union(qs, all) => {
+                for (i, q) in qs.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f, "{}", if *all { "UNION ALL" } else { "UNION" })?;
+                    }
+                    write!(f, "{q}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
 impl QueryIR {
+    /// Returns whether the clause, or any clause of the query, changes the graph.
+    #[must_use]
+    pub fn is_write(&self) -> bool {
+        match self {
+            Self::Create(_)
+            | Self::Merge(_)
+            | Self::Delete(_, _)
+            | Self::Set(_)
+            | Self::Remove(_)
+            | Self::Foreach(_, _, _) => true,
+            Self::Query(clauses, _) | Self::Union(clauses, _) => clauses.iter().any(Self::is_write),
+            Self::CallSubquery(query, _) => query.is_write(),
+            _ => false,
+        }
+    }
+
     pub fn validate(&mut self) -> Result<(), String> {
         let mut env = HashSet::new();
         self.inner_validate(std::iter::empty(), &mut env)
@@ -827,6 +896,43 @@ impl QueryIR {
                 iter.next()
                     .map_or(Ok(()), |first| first.inner_validate(iter, env))
             }
+            Self::CallSubquery(query, batch_size) => {
+                if let Some(batch_size) = batch_size {
+                    batch_size.validate(false, env)?;
+                }
+                let Self::Query(clauses, _) = &**query else {
+                    unreachable!();
+                };
+                // only the variables imported by a leading WITH are visible in the subquery
+                let mut body_env = if matches!(clauses.first(), Some(Self::With { .. })) {
+                    env.clone()
+                } else {
+                    HashSet::new()
+                };
+                let mut body = clauses.iter();
+                let first = body.next().ok_or("Empty query")?;
+                first.inner_validate(body, &mut body_env)?;
+                if let Some(Self::Return { exprs, .. }) = clauses.last() {
+                    env.extend(exprs.iter().map(|(name, _)| name.id));
+                    iter.next().map_or_else(|| Err(String::from(
+                        "Query cannot conclude with CALL (must be a RETURN clause, an update clause, a procedure call or a non-returning subquery)",
+                    )), |first| first.inner_validate(iter, env))
+                } else {
+                    iter.next()
+                        .map_or(Ok(()), |first| first.inner_validate(iter, env))
+                }
+            }
+            Self::Foreach(list, var, clauses) => {
+                list.validate(false, env)?;
+                let mut body_env = env.clone();
+                body_env.insert(var.id);
+                let mut body = clauses.iter();
+                if let Some(first) = body.next() {
+                    first.inner_validate(body, &mut body_env)?;
+                }
+                iter.next()
+                    .map_or(Ok(()), |first| first.inner_validate(iter, env))
+            }
             Self::LoadCsv {
                 file_path,
                 delimiter,
@@ -874,6 +980,12 @@ impl QueryIR {
                 let first = iter.next().ok_or("Empty query")?;
                 first.inner_validate(iter, env)
             }
+            Self::Union(qs, _) => {
+                for q in qs {
+                    q.inner_validate(std::iter::empty(), &mut HashSet::new())?;
+                }
+                Ok(())
+            }
         }
     }
 }
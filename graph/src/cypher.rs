@@ -1,5 +1,6 @@
 use crate::ast::{
-    ExprIR, QuantifierType, QueryGraph, QueryIR, QueryNode, QueryPath, QueryRelationship, Variable,
+    ExprIR, QuantifierType, QueryGraph, QueryIR, QueryNode, QueryPath, QueryRelationship,
+    ShortestPaths, Variable,
 };
 use crate::{
     cypher::Token::RParen,
@@ -15,8 +16,8 @@ use orx_tree::{DynTree, NodeRef};
 use std::{
     collections::{HashMap, HashSet},
     num::IntErrorKind,
-    rc::Rc,
     str::Chars,
+    sync::Arc,
 };
 use unescaper::unescape;
 
@@ -75,16 +76,18 @@ enum Keyword {
     Index,
     For,
     On,
+    Union,
+    Foreach,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 enum Token {
-    Ident(Rc<String>),
-    Keyword(Keyword, Rc<String>),
+    Ident(Arc<String>),
+    Keyword(Keyword, Arc<String>),
     Parameter(String),
     Integer(i64),
     Float(f64),
-    String(Rc<String>),
+    String(Arc<String>),
     LBrace,
     RBrace,
     LBracket,
@@ -168,8 +171,13 @@ const KEYWORDS: &[(&str, Keyword)] = &[
     ("INDEX", Keyword::Index),
     ("FOR", Keyword::For),
     ("ON", Keyword::On),
+    ("UNION", Keyword::Union),
+    ("FOREACH", Keyword::Foreach),
 ];
 
+/// The rows per transaction of `CALL { ... } IN TRANSACTIONS` without `OF n ROWS`.
+const DEFAULT_TRANSACTION_BATCH_SIZE: i64 = 1000;
+
 const MIN_I64: [&str; 5] = [
     "0b1000000000000000000000000000000000000000000000000000000000000000", // binary
     "0o1000000000000000000000",                                           // octal
@@ -309,7 +317,7 @@ impl<'a> Lexer<'a> {
                     unescape(&str[pos + 1..pos + len]).map_or_else(
                         |e| match e {
                             unescaper::Error::InvalidChar { .. } => (
-                                Token::String(Rc::new(String::from(&str[pos + 1..pos + len]))),
+                                Token::String(Arc::new(String::from(&str[pos + 1..pos + len]))),
                                 len + 1,
                             ),
                             _ => (
@@ -317,7 +325,7 @@ impl<'a> Lexer<'a> {
                                 len + 1,
                             ),
                         },
-                        |unescaped| (Token::String(Rc::new(unescaped)), len + 1),
+                        |unescaped| (Token::String(Arc::new(unescaped)), len + 1),
                     )
                 }
                 '\"' => {
@@ -351,7 +359,7 @@ impl<'a> Lexer<'a> {
                     unescape(&str[pos + 1..pos + len]).map_or_else(
                         |e| match e {
                             unescaper::Error::InvalidChar { .. } => (
-                                Token::String(Rc::new(String::from(&str[pos + 1..pos + len]))),
+                                Token::String(Arc::new(String::from(&str[pos + 1..pos + len]))),
                                 len + 1,
                             ),
                             _ => (
@@ -359,7 +367,7 @@ impl<'a> Lexer<'a> {
                                 len + 1,
                             ),
                         },
-                        |unescaped| (Token::String(Rc::new(unescaped)), len + 1),
+                        |unescaped| (Token::String(Arc::new(unescaped)), len + 1),
                     )
                 }
                 '0'..='9' => Self::lex_numeric(str, chars, pos, 1),
@@ -381,11 +389,11 @@ impl<'a> Lexer<'a> {
                         .iter()
                         .find(|&other| str[pos..pos + len].eq_ignore_ascii_case(other.0))
                         .map_or_else(
-                            || Token::Ident(Rc::new(String::from(&str[pos..pos + len]))),
+                            || Token::Ident(Arc::new(String::from(&str[pos..pos + len]))),
                             |o| {
                                 Token::Keyword(
                                     o.1.clone(),
-                                    Rc::new(String::from(&str[pos..pos + len])),
+                                    Arc::new(String::from(&str[pos..pos + len])),
                                 )
                             },
                         );
@@ -408,7 +416,7 @@ impl<'a> Lexer<'a> {
                         );
                     }
                     (
-                        Token::Ident(Rc::new(String::from(&str[pos + 1..pos + len]))),
+                        Token::Ident(Arc::new(String::from(&str[pos + 1..pos + len]))),
                         len + 1,
                     )
                 }
@@ -620,7 +628,7 @@ macro_rules! parse_operators {
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     var_id: u32,
-    vars: HashMap<Rc<String>, Variable>,
+    vars: HashMap<Arc<String>, Variable>,
 }
 
 impl<'a> Parser<'a> {
@@ -635,7 +643,7 @@ impl<'a> Parser<'a> {
 
     fn create_var(
         &mut self,
-        name: Option<Rc<String>>,
+        name: Option<Arc<String>>,
         ty: Type,
     ) -> Result<Variable, String> {
         if let Some(name) = &name {
@@ -773,6 +781,51 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_query(&mut self) -> Result<QueryIR, String> {
+        let query = self.parse_single_query()?;
+        if !matches!(self.lexer.current(), Token::Keyword(Keyword::Union, _)) {
+            match_token!(self.lexer, EndOfFile);
+            return Ok(query);
+        }
+        let columns = Self::union_columns(&query)?;
+        let mut queries = vec![query];
+        let mut all = None;
+        while optional_match_token!(self.lexer => Union) {
+            let union_all = optional_match_token!(self.lexer => All);
+            if all.replace(union_all).is_some_and(|all| all != union_all) {
+                return Err(self
+                    .lexer
+                    .format_error("Invalid combination of UNION and UNION ALL."));
+            }
+            // each query binds its own variables
+            self.vars.clear();
+            let query = self.parse_single_query()?;
+            if Self::union_columns(&query)? != columns {
+                return Err(String::from(
+                    "All sub queries in an UNION must have the same column names.",
+                ));
+            }
+            queries.push(query);
+        }
+        match_token!(self.lexer, EndOfFile);
+        Ok(QueryIR::Union(queries, all.unwrap_or_default()))
+    }
+
+    /// Returns the names of the columns returned by a query combined with `UNION`.
+    fn union_columns(query: &QueryIR) -> Result<Vec<String>, String> {
+        if let QueryIR::Query(clauses, _) = query
+            && let Some(QueryIR::Return { exprs, .. }) = clauses.last()
+        {
+            return Ok(exprs
+                .iter()
+                .map(|(name, _)| String::from(name.as_str()))
+                .collect());
+        }
+        Err(String::from(
+            "All sub queries in an UNION must end with a RETURN clause.",
+        ))
+    }
+
+    fn parse_single_query(&mut self) -> Result<QueryIR, String> {
         let mut clauses = Vec::new();
         let mut write = false;
         loop {
@@ -786,6 +839,9 @@ impl<'a> Parser<'a> {
             ) = self.lexer.current()
             {
                 clauses.push(self.parse_reading_clasue()?);
+                if clauses.last().is_some_and(QueryIR::is_write) {
+                    write = true;
+                }
             }
             while let Token::Keyword(
                 Keyword::Create
@@ -793,7 +849,8 @@ impl<'a> Parser<'a> {
                 | Keyword::Delete
                 | Keyword::Detach
                 | Keyword::Set
-                | Keyword::Remove,
+                | Keyword::Remove
+                | Keyword::Foreach,
                 _,
             ) = self.lexer.current()
             {
@@ -811,7 +868,10 @@ impl<'a> Parser<'a> {
             clauses.push(self.parse_return_clause(write)?);
             write = false;
         }
-        if self.lexer.current() != Token::EndOfFile {
+        if !matches!(
+            self.lexer.current(),
+            Token::EndOfFile | Token::RBrace | Token::Keyword(Keyword::Union, _)
+        ) {
             return Err(self
                 .lexer
                 .format_error(&format!("Invalid input '{:?}'", self.lexer.current())));
@@ -836,6 +896,9 @@ impl<'a> Parser<'a> {
             }
             Token::Keyword(Keyword::Call, _) => {
                 self.lexer.next();
+                if optional_match_token!(self.lexer, LBrace) {
+                    return self.parse_call_subquery();
+                }
                 self.parse_call_clause()
             }
             Token::Keyword(Keyword::Load, _) => {
@@ -846,12 +909,12 @@ impl<'a> Parser<'a> {
                 let delimiter = if optional_match_token!(self.lexer => Delimiter) {
                     self.parse_expr()?
                 } else {
-                    tree!(ExprIR::String(Rc::new(String::from(','))))
+                    tree!(ExprIR::String(Arc::new(String::from(','))))
                 };
                 match_token!(self.lexer => From);
                 let file_path = self.parse_expr()?;
                 match_token!(self.lexer => As);
-                let ident: Rc<String> = self.parse_ident()?;
+                let ident: Arc<String> = self.parse_ident()?;
                 Ok(QueryIR::LoadCsv {
                     file_path,
                     headers,
@@ -886,10 +949,48 @@ impl<'a> Parser<'a> {
                 self.lexer.next();
                 self.parse_remove_clause()
             }
+            Token::Keyword(Keyword::Foreach, _) => {
+                self.lexer.next();
+                self.parse_foreach_clause()
+            }
             token => Err(self.lexer.format_error(&format!("Invalid input {token:?}"))),
         }
     }
 
+    fn parse_foreach_clause(&mut self) -> Result<QueryIR, String> {
+        match_token!(self.lexer, LParen);
+        let ident = self.parse_ident()?;
+        match_token!(self.lexer => In);
+        let list = self.parse_expr()?;
+        match_token!(self.lexer, Pipe);
+        // the variables bound in FOREACH are not visible after it
+        let vars = self.vars.clone();
+        self.vars.remove(&ident);
+        let var = self.create_var(Some(ident), Type::Any)?;
+        let mut clauses = vec![];
+        while let Token::Keyword(
+            Keyword::Create
+            | Keyword::Merge
+            | Keyword::Delete
+            | Keyword::Detach
+            | Keyword::Set
+            | Keyword::Remove
+            | Keyword::Foreach,
+            _,
+        ) = self.lexer.current()
+        {
+            clauses.push(self.parse_writing_clause()?);
+        }
+        if clauses.is_empty() {
+            return Err(self
+                .lexer
+                .format_error("FOREACH requires at least one updating clause."));
+        }
+        match_token!(self.lexer, RParen);
+        self.vars = vars;
+        Ok(QueryIR::Foreach(list, var, clauses))
+    }
+
     fn parse_call_clause(&mut self) -> Result<QueryIR, String> {
         let ident = self.parse_dotted_ident()?;
         match_token!(self.lexer, LParen);
@@ -899,13 +1000,125 @@ impl<'a> Parser<'a> {
         ))
     }
 
-    fn parse_dotted_ident(&mut self) -> Result<Rc<String>, String> {
+    fn parse_call_subquery(&mut self) -> Result<QueryIR, String> {
+        let outer_vars = self.vars.clone();
+        let mut clauses = vec![];
+        // the subquery only sees the variables imported by its leading WITH
+        if optional_match_token!(self.lexer => With) {
+            let with = self.parse_with_clause(false)?;
+            let QueryIR::With {
+                distinct,
+                exprs,
+                orderby,
+                skip,
+                limit,
+                filter,
+                ..
+            } = &with
+            else {
+                unreachable!();
+            };
+            if *distinct
+                || !orderby.is_empty()
+                || skip.is_some()
+                || limit.is_some()
+                || filter.is_some()
+                || exprs.iter().any(|(name, expr)| {
+                    !matches!(expr.root().data(), ExprIR::Variable(var) if var.id == name.id)
+                })
+            {
+                return Err(String::from(
+                    "Importing WITH should consist only of simple references to outside variables.",
+                ));
+            }
+            self.vars = exprs
+                .iter()
+                .filter_map(|(var, _)| Some((var.name.clone()?, var.clone())))
+                .collect();
+            clauses.push(with);
+        } else {
+            self.vars.clear();
+        }
+        let QueryIR::Query(body, write) = self.parse_single_query()? else {
+            unreachable!();
+        };
+        clauses.extend(body);
+        match_token!(self.lexer, RBrace);
+        let batch_size = if optional_match_token!(self.lexer => In) {
+            Some(self.parse_in_transactions()?)
+        } else {
+            None
+        };
+        self.vars = outer_vars;
+        if let Some(QueryIR::Return { exprs, .. }) = clauses.last() {
+            for (var, _) in exprs {
+                let Some(name) = &var.name else {
+                    continue;
+                };
+                if self.vars.insert(name.clone(), var.clone()).is_some() {
+                    return Err(format!(
+                        "Variable `{}` already declared in outer scope",
+                        name.as_str()
+                    ));
+                }
+            }
+        }
+        Ok(QueryIR::CallSubquery(
+            Box::new(QueryIR::Query(clauses, write)),
+            batch_size,
+        ))
+    }
+
+    /// Parses `TRANSACTIONS [OF n ROWS]` following `CALL { ... } IN` and returns the batch size.
+    fn parse_in_transactions(&mut self) -> Result<DynTree<ExprIR>, String> {
+        if !self.optional_match_ident("TRANSACTIONS") {
+            return Err(self
+                .lexer
+                .format_error(&format!("Invalid input {:?}", self.lexer.current())));
+        }
+        if !self.optional_match_ident("OF") {
+            return Ok(tree!(ExprIR::Integer(DEFAULT_TRANSACTION_BATCH_SIZE)));
+        }
+        let batch_size = self.parse_expr()?;
+        match batch_size.root().data() {
+            ExprIR::Integer(1..) | ExprIR::Parameter(_) => {}
+            _ => {
+                return Err(self
+                    .lexer
+                    .format_error("Transaction batch size must be a positive integer"));
+            }
+        }
+        if !self.optional_match_ident("ROWS") && !self.optional_match_ident("ROW") {
+            return Err(self
+                .lexer
+                .format_error(&format!("Invalid input {:?}", self.lexer.current())));
+        }
+        Ok(batch_size)
+    }
+
+    /// Consumes the current token when it is the identifier `name`, compared case-insensitively.
+    fn optional_match_ident(
+        &mut self,
+        name: &str,
+    ) -> bool {
+        match self.lexer.current() {
+            Token::Ident(id) if id.eq_ignore_ascii_case(name) => {
+                self.lexer.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_dotted_ident(&mut self) -> Result<Arc<String>, String> {
         let mut idents = vec![self.parse_ident()?];
         while self.lexer.current() == Token::Dot {
             self.lexer.next();
             idents.push(self.parse_ident()?);
         }
-        Ok(Rc::new(idents.iter().map(|label| label.as_str()).join(".")))
+        Ok(Arc::new(
+            idents.iter().map(|label| label.as_str()).join("."),
+        ))
     }
 
     fn parse_match_clause(
@@ -1112,7 +1325,25 @@ impl<'a> Parser<'a> {
         let mut nodes_alias = HashSet::new();
         loop {
             if let Ok(ident) = self.parse_ident() {
-                match_token!(self.lexer, Equal);
+                // `shortestPath(...)` may also match a path without naming it
+                let (ident, shortest) = if let Token::LParen = self.lexer.current() {
+                    let shortest = self.shortest_paths_kind(&ident)?;
+                    self.lexer.next();
+                    (None, Some(shortest))
+                } else {
+                    match_token!(self.lexer, Equal);
+                    (Some(ident), self.parse_shortest_paths()?)
+                };
+                if shortest.is_some() && clause != &Keyword::Match {
+                    let clause = if clause == &Keyword::Create {
+                        "CREATE"
+                    } else {
+                        "MERGE"
+                    };
+                    return Err(self
+                        .lexer
+                        .format_error(&format!("shortestPath cannot be used in {clause}.")));
+                }
                 let mut vars = vec![];
                 let mut left = self.parse_node_pattern(clause)?;
                 vars.push(left.alias.clone());
@@ -1122,7 +1353,7 @@ impl<'a> Parser<'a> {
                 loop {
                     if let Token::Dash | Token::LessThan = self.lexer.current() {
                         let (relationship, right) =
-                            self.parse_relationship_pattern(left, clause)?;
+                            self.parse_relationship_pattern(left, clause, shortest)?;
                         vars.push(relationship.alias.clone());
                         vars.push(right.alias.clone());
                         left = right.clone();
@@ -1138,8 +1369,16 @@ impl<'a> Parser<'a> {
                             query_graph.add_node(right);
                         }
                     } else {
-                        query_graph.add_path(Rc::new(QueryPath::new(
-                            self.create_var(Some(ident), Type::Path)?,
+                        if shortest.is_some() {
+                            if vars.len() != 3 {
+                                return Err(self.lexer.format_error(
+                                    "shortestPath requires a path containing a single relationship.",
+                                ));
+                            }
+                            match_token!(self.lexer, RParen);
+                        }
+                        query_graph.add_path(Arc::new(QueryPath::new(
+                            self.create_var(ident, Type::Path)?,
                             vars,
                         )));
                         break;
@@ -1152,7 +1391,8 @@ impl<'a> Parser<'a> {
                     query_graph.add_node(left.clone());
                 }
                 while let Token::Dash | Token::LessThan = self.lexer.current() {
-                    let (relationship, right) = self.parse_relationship_pattern(left, clause)?;
+                    let (relationship, right) =
+                        self.parse_relationship_pattern(left, clause, None)?;
                     left = right.clone();
                     if !query_graph.add_relationship(relationship.clone())
                         && clause == &Keyword::Match
@@ -1603,7 +1843,7 @@ impl<'a> Parser<'a> {
         unreachable!()
     }
 
-    fn parse_ident(&mut self) -> Result<Rc<String>, String> {
+    fn parse_ident(&mut self) -> Result<Arc<String>, String> {
         match self.lexer.current() {
             Token::Ident(id) | Token::Keyword(_, id) => {
                 self.lexer.next();
@@ -1627,7 +1867,7 @@ impl<'a> Parser<'a> {
             } else {
                 named_exprs.push((
                     self.create_var(
-                        Some(Rc::new(String::from(&self.lexer.str[pos..self.lexer.pos]))),
+                        Some(Arc::new(String::from(&self.lexer.str[pos..self.lexer.pos]))),
                         Type::Any,
                     )?,
                     expr,
@@ -1681,7 +1921,7 @@ impl<'a> Parser<'a> {
 
     fn parse_list_comprehension(
         &mut self,
-        var: Rc<String>,
+        var: Arc<String>,
     ) -> Result<DynTree<ExprIR>, String> {
         // var and 'IN' already parsed
         let list_expr = self.parse_expr()?;
@@ -1716,7 +1956,7 @@ impl<'a> Parser<'a> {
     fn parse_node_pattern(
         &mut self,
         clause: &Keyword,
-    ) -> Result<Rc<QueryNode>, String> {
+    ) -> Result<Arc<QueryNode>, String> {
         match_token!(self.lexer, LParen);
         let alias = if let Ok(id) = self.parse_ident() {
             self.create_var(Some(id), Type::Node)?
@@ -1736,23 +1976,20 @@ impl<'a> Parser<'a> {
             self.parse_map()?
         };
         match_token!(self.lexer, RParen);
-        Ok(Rc::new(QueryNode::new(alias, labels, Rc::new(attrs))))
+        Ok(Arc::new(QueryNode::new(alias, labels, Arc::new(attrs))))
     }
 
     fn parse_relationship_pattern(
         &mut self,
-        src: Rc<QueryNode>,
+        src: Arc<QueryNode>,
         clause: &Keyword,
-    ) -> Result<(Rc<QueryRelationship>, Rc<QueryNode>), String> {
+        shortest: Option<ShortestPaths>,
+    ) -> Result<(Arc<QueryRelationship>, Arc<QueryNode>), String> {
         let is_incoming = optional_match_token!(self.lexer, LessThan);
         match_token!(self.lexer, Dash);
         let has_details = optional_match_token!(self.lexer, LBrace);
-        let (alias, types, attrs) = if has_details {
-            let alias = if let Ok(id) = self.parse_ident() {
-                self.create_var(Some(id), Type::Relationship)?
-            } else {
-                self.create_var(None, Type::Relationship)?
-            };
+        let (alias, types, attrs, var_length) = if has_details {
+            let name = self.parse_ident().ok();
             let mut types = vec![];
             if optional_match_token!(self.lexer, Colon) {
                 loop {
@@ -1765,27 +2002,34 @@ impl<'a> Parser<'a> {
                     break;
                 }
             }
-            let _ = if optional_match_token!(self.lexer, Star) {
-                let start = if let Token::Integer(i) = self.lexer.current() {
-                    self.lexer.next();
-                    Some(i)
-                } else {
-                    None
-                };
-                if optional_match_token!(self.lexer, DotDot) {
-                    let end = if let Token::Integer(i) = self.lexer.current() {
-                        self.lexer.next();
-                        Some(i)
+            let var_length = if optional_match_token!(self.lexer, Star) {
+                if clause != &Keyword::Match {
+                    let clause = if clause == &Keyword::Create {
+                        "CREATE"
                     } else {
-                        None
+                        "MERGE"
                     };
-                    Some((start, end))
+                    return Err(self.lexer.format_error(&format!(
+                        "Variable length relationships cannot be used in {clause}."
+                    )));
+                }
+                let start = self.parse_hops()?;
+                if optional_match_token!(self.lexer, DotDot) {
+                    Some((start.unwrap_or(1), self.parse_hops()?))
                 } else {
-                    Some((start, None))
+                    // `*n` is exactly n hops and `*` any number of hops
+                    start.map_or(Some((1, None)), |start| Some((start, Some(start))))
                 }
             } else {
                 None
             };
+            // a variable-length relationship binds to the list of its relationships
+            let ty = if var_length.is_some() || shortest.is_some() {
+                Type::List(Box::new(Type::Relationship))
+            } else {
+                Type::Relationship
+            };
+            let alias = self.create_var(name, ty)?;
             let attrs = if let Token::Parameter(param) = self.lexer.current() {
                 self.lexer.next();
                 if clause == &Keyword::Match {
@@ -1798,37 +2042,109 @@ impl<'a> Parser<'a> {
                 self.parse_map()?
             };
             match_token!(self.lexer, RBrace);
-            (alias, types, attrs)
+            (alias, types, attrs, var_length)
         } else {
             (
                 self.create_var(None, Type::Relationship)?,
                 vec![],
                 tree!(ExprIR::Map),
+                None,
             )
         };
+        let var_length = if shortest.is_some() {
+            let (min, max) = var_length.unwrap_or((1, Some(1)));
+            if min > 1 {
+                return Err(self.lexer.format_error(
+                    "shortestPath does not support a minimal length different from 0 or 1.",
+                ));
+            }
+            Some((min, max))
+        } else {
+            var_length
+        };
         match_token!(self.lexer, Dash);
         let is_outgoing = optional_match_token!(self.lexer, GreaterThan);
         let dst = self.parse_node_pattern(clause)?;
-        let relationship = match (is_incoming, is_outgoing) {
+        let mut relationship = match (is_incoming, is_outgoing) {
             (true, true) | (false, false) => {
                 if *clause == Keyword::Create {
                     return Err(self
                         .lexer
                         .format_error("Only directed relationships are supported in CREATE"));
                 }
-                QueryRelationship::new(alias, types, Rc::new(attrs), src, dst.clone(), true)
-            }
-            (true, false) => {
-                QueryRelationship::new(alias, types, Rc::new(attrs), dst.clone(), src, false)
-            }
-            (false, true) => {
-                QueryRelationship::new(alias, types, Rc::new(attrs), src, dst.clone(), false)
-            }
+                QueryRelationship::new(
+                    alias,
+                    types,
+                    Arc::new(attrs),
+                    src,
+                    dst.clone(),
+                    true,
+                    var_length,
+                )
+            }
+            (true, false) => QueryRelationship::new(
+                alias,
+                types,
+                Arc::new(attrs),
+                dst.clone(),
+                src,
+                false,
+                var_length,
+            ),
+            (false, true) => QueryRelationship::new(
+                alias,
+                types,
+                Arc::new(attrs),
+                src,
+                dst.clone(),
+                false,
+                var_length,
+            ),
         };
-        Ok((Rc::new(relationship), dst))
+        relationship.shortest = shortest;
+        Ok((Arc::new(relationship), dst))
+    }
+
+    /// Parses the `shortestPath(` or `allShortestPaths(` opening a named path.
+    fn parse_shortest_paths(&mut self) -> Result<Option<ShortestPaths>, String> {
+        let Token::Ident(name) = self.lexer.current() else {
+            return Ok(None);
+        };
+        let shortest = self.shortest_paths_kind(&name)?;
+        self.lexer.next();
+        match_token!(self.lexer, LParen);
+        Ok(Some(shortest))
+    }
+
+    /// Returns the paths matched by the shortest path function `name`.
+    fn shortest_paths_kind(
+        &self,
+        name: &str,
+    ) -> Result<ShortestPaths, String> {
+        if name.eq_ignore_ascii_case("shortestPath") {
+            Ok(ShortestPaths::One)
+        } else if name.eq_ignore_ascii_case("allShortestPaths") {
+            Ok(ShortestPaths::All)
+        } else {
+            Err(self
+                .lexer
+                .format_error(&format!("Unknown function '{name}'")))
+        }
+    }
+
+    /// Parses the optional bound of a variable-length relationship.
+    fn parse_hops(&mut self) -> Result<Option<u64>, String> {
+        if let Token::Integer(i) = self.lexer.current() {
+            self.lexer.next();
+            return u64::try_from(i).map(Some).map_err(|_| {
+                self.lexer
+                    .format_error("Variable length relationship bounds must be positive.")
+            });
+        }
+        Ok(None)
     }
 
-    fn parse_labels(&mut self) -> Result<OrderSet<Rc<String>>, String> {
+    fn parse_labels(&mut self) -> Result<OrderSet<Arc<String>>, String> {
         let mut labels = OrderSet::new();
         while self.lexer.current() == Token::Colon {
             self.lexer.next();
@@ -0,0 +1,345 @@
+use itertools::Itertools;
+use orx_tree::{Dfs, DynNode, DynTree, NodeRef};
+
+use crate::{
+    ast::{ExprIR, QueryNode, QueryRelationship, Variable},
+    graph::graph::Graph,
+    planner::IR,
+    runtime::runtime::OperatorProfile,
+};
+
+/// Renders a plan as one line per operator, children indented under their parent.
+///
+/// Each line holds the operator name, its details and the estimated number of rows it produces.
+#[must_use]
+pub fn explain(
+    plan: &DynTree<IR>,
+    g: &Graph,
+) -> Vec<String> {
+    let mut lines = vec![];
+    explain_node(&plan.root(), g, 0, &mut lines);
+    lines
+}
+
+/// Renders a profiled plan with the records produced and the cumulative execution time
+/// of every operator, `stats` is indexed by operator id.
+#[must_use]
+pub fn profile(
+    plan: &DynTree<IR>,
+    stats: &[OperatorProfile],
+) -> Vec<String> {
+    plan.root()
+        .indices::<Dfs>()
+        .zip(stats)
+        .enumerate()
+        .map(|(id, (idx, stats))| {
+            let node = plan.node(&idx);
+            format!(
+                "{}{} | Operator id: {id} | Records produced: {}, Execution time: {:.6} ms",
+                "    ".repeat(node.depth()),
+                node.data().name(),
+                stats.records,
+                stats.execution_time.as_secs_f64() * 1000.0,
+            )
+        })
+        .collect()
+}
+
+fn explain_node(
+    node: &DynNode<IR>,
+    g: &Graph,
+    depth: usize,
+    lines: &mut Vec<String>,
+) -> u64 {
+    let line = lines.len();
+    lines.push(String::new());
+    let child_rows = node
+        .children()
+        .map(|child| explain_node(&child, g, depth + 1, lines))
+        .collect::<Vec<_>>();
+    let rows = estimate_rows(node.data(), g, &child_rows);
+
+    let mut parts = vec![node.data().name().to_string()];
+    parts.extend(details(node.data(), g));
+    parts.push(format!("Estimated rows: {rows}"));
+    lines[line] = format!("{}{}", "    ".repeat(depth), parts.join(" | "));
+    rows
+}
+
+fn details(
+    ir: &IR,
+    g: &Graph,
+) -> Vec<String> {
+    match ir {
+        IR::Empty | IR::Argument | IR::CartesianProduct | IR::Union | IR::Distinct | IR::Commit => {
+            vec![]
+        }
+        IR::Optional(vars) => vec![vars.iter().map(|v| v.as_str()).join(", ")],
+        IR::Call(name, args) => vec![format!(
+            "{name}({})",
+            args.iter().map(|arg| format_expr(&arg.root())).join(", ")
+        )],
+        IR::Unwind(expr, var) => vec![format!("{} AS {}", format_expr(&expr.root()), var.as_str())],
+        IR::Apply(batch_size) => batch_size
+            .iter()
+            .map(|size| format!("In transactions of {} rows", format_expr(&size.root())))
+            .collect(),
+        IR::Foreach(expr, var) => {
+            vec![format!("{} IN {}", var.as_str(), format_expr(&expr.root()))]
+        }
+        IR::Create(pattern) | IR::Merge(pattern) => {
+            vec![pattern.to_string().trim_end_matches(", ").to_string()]
+        }
+        IR::Delete(exprs, detach) => {
+            let exprs = exprs.iter().map(|e| format_expr(&e.root())).join(", ");
+            vec![if *detach {
+                format!("DETACH {exprs}")
+            } else {
+                exprs
+            }]
+        }
+        IR::Set(items) => vec![
+            items
+                .iter()
+                .map(|(target, value, _)| {
+                    format!(
+                        "{} = {}",
+                        format_expr(&target.root()),
+                        format_expr(&value.root())
+                    )
+                })
+                .join(", "),
+        ],
+        IR::Remove(items) => vec![items.iter().map(|e| format_expr(&e.root())).join(", ")],
+        IR::NodeScan(node) => {
+            let mut res = vec![node.to_string()];
+            if let Some(index) = used_index(node, g) {
+                res.push(format!("Index: {index}"));
+            }
+            res
+        }
+        IR::RelationshipScan(relationship)
+        | IR::ExpandInto(relationship)
+        | IR::VarLengthTraverse(relationship)
+        | IR::ShortestPath(relationship) => {
+            vec![relationship.to_string()]
+        }
+        IR::PathBuilder(paths) => vec![paths.iter().map(|p| p.var.as_str()).join(", ")],
+        IR::Filter(expr) | IR::Skip(expr) | IR::Limit(expr) => vec![format_expr(&expr.root())],
+        IR::LoadCsv { file_path, var, .. } => vec![format!(
+            "{} AS {}",
+            format_expr(&file_path.root()),
+            var.as_str()
+        )],
+        IR::Sort(items) => vec![
+            items
+                .iter()
+                .map(|(expr, desc)| {
+                    format!(
+                        "{} {}",
+                        format_expr(&expr.root()),
+                        if *desc { "DESC" } else { "ASC" }
+                    )
+                })
+                .join(", "),
+        ],
+        IR::Aggregate(_, keys, aggregations) => {
+            let mut res = vec![];
+            if !keys.is_empty() {
+                res.push(format!("Keys: {}", format_projection(keys)));
+            }
+            res.push(format!("Aggregations: {}", format_projection(aggregations)));
+            res
+        }
+        IR::Project(exprs) => vec![format_projection(exprs)],
+        IR::CreateIndex { label, attrs } | IR::DropIndex { label, attrs } => {
+            vec![format!(":{label}({})", attrs.iter().join(", "))]
+        }
+    }
+}
+
+fn format_projection(exprs: &[(Variable, DynTree<ExprIR>)]) -> String {
+    exprs
+        .iter()
+        .map(|(name, expr)| format!("{} AS {}", format_expr(&expr.root()), name.as_str()))
+        .join(", ")
+}
+
+/// Mirrors the index lookup done by the runtime node scan.
+fn used_index(
+    node: &QueryNode,
+    g: &Graph,
+) -> Option<String> {
+    let attrs = node.attrs.root();
+    if !matches!(attrs.data(), ExprIR::Map) {
+        return None;
+    }
+    for label in &node.labels {
+        for attr in attrs.children() {
+            if let ExprIR::String(key) = attr.data()
+                && g.is_indexed(label, key)
+            {
+                return Some(format!(":{label}({key})"));
+            }
+        }
+    }
+    None
+}
+
+fn relationships_per_row(
+    relationship: &QueryRelationship,
+    g: &Graph,
+) -> u64 {
+    let count = if relationship.types.is_empty() {
+        g.relationship_count()
+    } else {
+        relationship
+            .types
+            .iter()
+            .map(|t| g.type_relationship_count(t))
+            .sum()
+    };
+    if relationship.bidirectional {
+        count.saturating_mul(2)
+    } else {
+        count
+    }
+}
+
+/// A simple cardinality estimate based on the label and relationship type counts.
+fn estimate_rows(
+    ir: &IR,
+    g: &Graph,
+    child_rows: &[u64],
+) -> u64 {
+    let input = child_rows.first().copied().unwrap_or(1);
+    match ir {
+        IR::NodeScan(node) => {
+            let per_row = if used_index(node, g).is_some() {
+                1
+            } else if node.labels.is_empty() {
+                g.node_count()
+            } else {
+                node.labels
+                    .iter()
+                    .map(|label| g.label_node_count(label))
+                    .min()
+                    .unwrap_or(0)
+            };
+            input.saturating_mul(per_row)
+        }
+        IR::RelationshipScan(relationship) => {
+            input.saturating_mul(relationships_per_row(relationship, g))
+        }
+        IR::Unwind(expr, _) if matches!(expr.root().data(), ExprIR::List) => {
+            input.saturating_mul(expr.root().num_children() as u64)
+        }
+        IR::Filter(_) => input.div_ceil(2),
+        IR::CartesianProduct => child_rows
+            .iter()
+            .fold(1, |acc, rows| acc.saturating_mul(*rows)),
+        IR::Foreach(_, _) | IR::Apply(_) => child_rows.get(1).copied().unwrap_or(1),
+        IR::Union => child_rows
+            .iter()
+            .fold(0, |acc, rows| acc.saturating_add(*rows)),
+        IR::Limit(expr) => match expr.root().data() {
+            ExprIR::Integer(limit) => input.min(u64::try_from(*limit).unwrap_or(0)),
+            _ => input,
+        },
+        IR::Skip(expr) => match expr.root().data() {
+            ExprIR::Integer(skip) => input.saturating_sub(u64::try_from(*skip).unwrap_or(0)),
+            _ => input,
+        },
+        IR::Aggregate(_, keys, _) if keys.is_empty() => 1,
+        _ => input,
+    }
+}
+
+/// Formats an expression tree back to a Cypher like string.
+#[must_use]
+pub fn format_expr(node: &DynNode<ExprIR>) -> String {
+    let children = || node.children().map(|child| format_expr(&child));
+    let binary = |op: &str| children().join(&format!(" {op} "));
+    match node.data() {
+        ExprIR::Null => String::from("null"),
+        ExprIR::Bool(b) => b.to_string(),
+        ExprIR::Integer(i) => i.to_string(),
+        ExprIR::Float(f) => f.to_string(),
+        ExprIR::String(s) => format!("'{s}'"),
+        ExprIR::List => format!("[{}]", children().join(", ")),
+        ExprIR::Map => format!(
+            "{{{}}}",
+            node.children()
+                .map(|child| {
+                    let ExprIR::String(key) = child.data() else {
+                        unreachable!()
+                    };
+                    format!("{key}: {}", format_expr(&child.child(0)))
+                })
+                .join(", ")
+        ),
+        ExprIR::Variable(var) => var.as_str().to_string(),
+        ExprIR::Parameter(p) => format!("${p}"),
+        ExprIR::Length => format!("length({})", children().join(", ")),
+        ExprIR::GetElement => {
+            let args = children().collect::<Vec<_>>();
+            format!("{}[{}]", args[0], args[1])
+        }
+        ExprIR::GetElements => {
+            let args = children().collect::<Vec<_>>();
+            format!("{}[{}..{}]", args[0], args[1], args[2])
+        }
+        ExprIR::IsNode => format!("is_node({})", children().join(", ")),
+        ExprIR::IsRelationship => format!("is_relationship({})", children().join(", ")),
+        ExprIR::Or => binary("OR"),
+        ExprIR::Xor => binary("XOR"),
+        ExprIR::And => binary("AND"),
+        ExprIR::Not => format!("NOT {}", children().join("")),
+        ExprIR::Negate => format!("-{}", children().join("")),
+        ExprIR::Eq => binary("="),
+        ExprIR::Neq => binary("<>"),
+        ExprIR::Lt => binary("<"),
+        ExprIR::Gt => binary(">"),
+        ExprIR::Le => binary("<="),
+        ExprIR::Ge => binary(">="),
+        ExprIR::In => binary("IN"),
+        ExprIR::Add => binary("+"),
+        ExprIR::Sub => binary("-"),
+        ExprIR::Mul => binary("*"),
+        ExprIR::Div => binary("/"),
+        ExprIR::Pow => binary("^"),
+        ExprIR::Modulo => binary("%"),
+        ExprIR::Distinct => format!("DISTINCT {}", children().join(", ")),
+        ExprIR::FuncInvocation(func) => {
+            let mut args = children().collect::<Vec<_>>();
+            if func.name == "property" && args.len() == 2 {
+                return format!("{}.{}", args[0], args[1].trim_matches('\''));
+            }
+            if func.is_aggregate() {
+                // the last argument is the accumulator
+                args.pop();
+            }
+            format!("{}({})", func.name, args.join(", "))
+        }
+        ExprIR::Quantifier(quantifier_type, var) => {
+            let args = children().collect::<Vec<_>>();
+            format!(
+                "{quantifier_type}({} IN {} WHERE {})",
+                var.as_str(),
+                args[0],
+                args[1]
+            )
+        }
+        ExprIR::ListComprehension(var) => {
+            let mut args = children();
+            let list = args.next().unwrap_or_default();
+            let rest = args.join(" | ");
+            if rest.is_empty() {
+                format!("[{} IN {list}]", var.as_str())
+            } else {
+                format!("[{} IN {list} | {rest}]", var.as_str())
+            }
+        }
+        ExprIR::Paren => format!("({})", children().join("")),
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use crate::{
+    graph::{
+        encoding::{Decoder, decode_names, decode_value},
+        graph::NodeId,
+    },
+    runtime::value::Value,
+};
+
+/// Nodes of a bulk load sharing their labels and property names,
+/// inserted by [`crate::graph::graph::Graph::bulk_insert`].
+///
+/// Encoded as the labels and the property names, each a count followed by the names,
+/// the number of nodes and for each node a value per property, `Null` when it has none.
+pub struct NodeBatch {
+    pub labels: Vec<Arc<String>>,
+    pub attrs: Vec<Arc<String>>,
+    pub nodes: Vec<Vec<Value>>,
+}
+
+/// Relationships of a bulk load sharing their type and property names,
+/// inserted by [`crate::graph::graph::Graph::bulk_insert`].
+///
+/// Encoded as the type, the property names, the number of relationships and
+/// for each relationship its source and destination node ids followed by a value per property.
+pub struct RelationshipBatch {
+    pub type_name: Arc<String>,
+    pub attrs: Vec<Arc<String>>,
+    pub relationships: Vec<(NodeId, NodeId, Vec<Value>)>,
+}
+
+impl NodeBatch {
+    /// # Errors
+    /// Fails if the batch is malformed or a property value can not be stored.
+    pub fn decode(dec: &mut impl Decoder) -> Result<Self, String> {
+        let labels = decode_names(dec)?;
+        let attrs = decode_names(dec)?;
+        let mut nodes = Vec::new();
+        for _ in 0..dec.read_u64()? {
+            nodes.push(decode_properties(dec, attrs.len())?);
+        }
+        Ok(Self {
+            labels,
+            attrs,
+            nodes,
+        })
+    }
+}
+
+impl RelationshipBatch {
+    /// # Errors
+    /// Fails if the batch is malformed or a property value can not be stored.
+    pub fn decode(dec: &mut impl Decoder) -> Result<Self, String> {
+        let type_name = Arc::new(dec.read_string()?);
+        let attrs = decode_names(dec)?;
+        let mut relationships = Vec::new();
+        for _ in 0..dec.read_u64()? {
+            let from = NodeId::from(dec.read_u64()?);
+            let to = NodeId::from(dec.read_u64()?);
+            relationships.push((from, to, decode_properties(dec, attrs.len())?));
+        }
+        Ok(Self {
+            type_name,
+            attrs,
+            relationships,
+        })
+    }
+}
+
+fn decode_properties(
+    dec: &mut impl Decoder,
+    len: usize,
+) -> Result<Vec<Value>, String> {
+    let mut values = Vec::with_capacity(len);
+    for _ in 0..len {
+        let value = decode_value(dec)?;
+        if matches!(
+            value,
+            Value::Map(_) | Value::Node(_) | Value::Relationship(_, _, _) | Value::Path(_)
+        ) {
+            return Err(String::from(
+                "Property values can only be of primitive types or arrays of primitive types",
+            ));
+        }
+        values.push(value);
+    }
+    Ok(values)
+}
@@ -0,0 +1,81 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use crate::{
+    graph::graph::{NodeId, RelationshipId},
+    runtime::value::Value,
+};
+
+/// A change committed to a graph, see [`ChangeLog`].
+#[derive(Clone, Debug)]
+pub enum Change {
+    NodeCreated(NodeId),
+    NodeDeleted(NodeId),
+    RelationshipCreated {
+        id: RelationshipId,
+        type_name: Arc<String>,
+        from: NodeId,
+        to: NodeId,
+    },
+    RelationshipDeleted {
+        id: RelationshipId,
+        from: NodeId,
+        to: NodeId,
+    },
+    LabelAdded(NodeId, Arc<String>),
+    LabelRemoved(NodeId, Arc<String>),
+    /// A property was set, a `Null` value means it was absent before or removed.
+    NodePropertySet {
+        id: NodeId,
+        key: Arc<String>,
+        old: Value,
+        new: Value,
+    },
+    RelationshipPropertySet {
+        id: RelationshipId,
+        key: Arc<String>,
+        old: Value,
+        new: Value,
+    },
+}
+
+/// The latest changes committed to a graph with change capture enabled.
+///
+/// Each change gets a sequence number one greater than the previous change,
+/// once `capacity` changes are kept the oldest ones are dropped.
+pub struct ChangeLog {
+    capacity: usize,
+    next_seq: u64,
+    changes: VecDeque<(u64, Change)>,
+}
+
+impl ChangeLog {
+    #[must_use]
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_seq: 1,
+            changes: VecDeque::new(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        change: Change,
+    ) {
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        self.changes.push_back((self.next_seq, change));
+        self.next_seq += 1;
+    }
+
+    /// Returns up to `count` changes following the change numbered `after`, oldest first.
+    pub fn read(
+        &self,
+        after: u64,
+        count: usize,
+    ) -> impl Iterator<Item = &(u64, Change)> {
+        let start = self.changes.partition_point(|(seq, _)| *seq <= after);
+        self.changes.range(start..).take(count)
+    }
+}
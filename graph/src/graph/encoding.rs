@@ -0,0 +1,332 @@
+#![allow(clippy::cast_possible_truncation)]
+
+use std::sync::Arc;
+
+use ordermap::OrderMap;
+use roaring::RoaringTreemap;
+
+use crate::{
+    graph::graph::{NodeId, RelationshipId},
+    runtime::value::Value,
+};
+
+/// Version of the binary encoding produced by [`crate::graph::graph::Graph::encode`].
+///
+/// Must be bumped whenever the layout written by the encoder changes.
+pub const ENCODING_VERSION: u64 = 1;
+
+/// Upper bound on the capacity reserved up front for a decoded collection.
+///
+/// Lengths read from an RDB can not be checked against the remaining input,
+/// so larger collections grow as their elements are decoded.
+const MAX_PREALLOCATED_LEN: usize = 1024;
+
+/// A sink for the primitive values that make up an encoded graph.
+pub trait Encoder {
+    fn write_u64(
+        &mut self,
+        value: u64,
+    );
+
+    fn write_i64(
+        &mut self,
+        value: i64,
+    );
+
+    fn write_f64(
+        &mut self,
+        value: f64,
+    );
+
+    fn write_bytes(
+        &mut self,
+        value: &[u8],
+    );
+
+    fn write_str(
+        &mut self,
+        value: &str,
+    ) {
+        self.write_bytes(value.as_bytes());
+    }
+}
+
+/// A source of the primitive values that make up an encoded graph.
+pub trait Decoder {
+    fn read_u64(&mut self) -> Result<u64, String>;
+
+    fn read_i64(&mut self) -> Result<i64, String>;
+
+    fn read_f64(&mut self) -> Result<f64, String>;
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>, String>;
+
+    fn read_string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.read_bytes()?).map_err(|e| e.to_string())
+    }
+
+    /// Returns the number of bytes left to decode, if the input size is known.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+
+    /// Reads the length of a collection whose elements take at least `min_size` bytes each.
+    ///
+    /// Fails when the input is too short to hold that many elements.
+    fn read_len(
+        &mut self,
+        min_size: usize,
+    ) -> Result<usize, String> {
+        let len = usize::try_from(self.read_u64()?).map_err(|e| e.to_string())?;
+        match self.remaining() {
+            Some(remaining) if len > remaining / min_size => Err(format!(
+                "Encoded length {len} exceeds the remaining {remaining} bytes"
+            )),
+            _ => Ok(len),
+        }
+    }
+}
+
+/// Returns the capacity to reserve for a collection of `len` decoded elements.
+#[must_use]
+pub fn preallocated(len: usize) -> usize {
+    len.min(MAX_PREALLOCATED_LEN)
+}
+
+const VALUE_NULL: u64 = 0;
+const VALUE_BOOL: u64 = 1;
+const VALUE_INT: u64 = 2;
+const VALUE_FLOAT: u64 = 3;
+const VALUE_STRING: u64 = 4;
+const VALUE_LIST: u64 = 5;
+const VALUE_MAP: u64 = 6;
+const VALUE_NODE: u64 = 7;
+const VALUE_RELATIONSHIP: u64 = 8;
+const VALUE_PATH: u64 = 9;
+
+pub fn encode_value(
+    enc: &mut impl Encoder,
+    value: &Value,
+) {
+    match value {
+        Value::Null => enc.write_u64(VALUE_NULL),
+        Value::Bool(b) => {
+            enc.write_u64(VALUE_BOOL);
+            enc.write_u64(u64::from(*b));
+        }
+        Value::Int(i) => {
+            enc.write_u64(VALUE_INT);
+            enc.write_i64(*i);
+        }
+        Value::Float(f) => {
+            enc.write_u64(VALUE_FLOAT);
+            enc.write_f64(*f);
+        }
+        Value::String(s) => {
+            enc.write_u64(VALUE_STRING);
+            enc.write_str(s);
+        }
+        Value::List(values) => {
+            enc.write_u64(VALUE_LIST);
+            enc.write_u64(values.len() as u64);
+            for v in values {
+                encode_value(enc, v);
+            }
+        }
+        Value::Map(map) => {
+            enc.write_u64(VALUE_MAP);
+            enc.write_u64(map.len() as u64);
+            for (k, v) in map.iter() {
+                enc.write_str(k);
+                encode_value(enc, v);
+            }
+        }
+        Value::Node(id) => {
+            enc.write_u64(VALUE_NODE);
+            enc.write_u64(u64::from(*id));
+        }
+        Value::Relationship(id, src, dest) => {
+            enc.write_u64(VALUE_RELATIONSHIP);
+            enc.write_u64(u64::from(*id));
+            enc.write_u64(u64::from(*src));
+            enc.write_u64(u64::from(*dest));
+        }
+        Value::Path(values) => {
+            enc.write_u64(VALUE_PATH);
+            enc.write_u64(values.len() as u64);
+            for v in values {
+                encode_value(enc, v);
+            }
+        }
+        Value::Arc(inner) => encode_value(enc, inner),
+    }
+}
+
+pub fn decode_value(dec: &mut impl Decoder) -> Result<Value, String> {
+    match dec.read_u64()? {
+        VALUE_NULL => Ok(Value::Null),
+        VALUE_BOOL => Ok(Value::Bool(dec.read_u64()? != 0)),
+        VALUE_INT => Ok(Value::Int(dec.read_i64()?)),
+        VALUE_FLOAT => Ok(Value::Float(dec.read_f64()?)),
+        VALUE_STRING => Ok(Value::String(Arc::new(dec.read_string()?))),
+        VALUE_LIST => {
+            let len = dec.read_len(8)?;
+            let mut values = Vec::with_capacity(preallocated(len));
+            for _ in 0..len {
+                values.push(decode_value(dec)?);
+            }
+            Ok(Value::List(values))
+        }
+        VALUE_MAP => {
+            let len = dec.read_len(16)?;
+            let mut map = OrderMap::with_capacity(preallocated(len));
+            for _ in 0..len {
+                let key = Arc::new(dec.read_string()?);
+                map.insert(key, decode_value(dec)?);
+            }
+            Ok(Value::Map(Arc::new(map)))
+        }
+        VALUE_NODE => Ok(Value::Node(NodeId::from(dec.read_u64()?))),
+        VALUE_RELATIONSHIP => Ok(Value::Relationship(
+            RelationshipId::from(dec.read_u64()?),
+            NodeId::from(dec.read_u64()?),
+            NodeId::from(dec.read_u64()?),
+        )),
+        VALUE_PATH => {
+            let len = dec.read_len(8)?;
+            let mut values = Vec::with_capacity(preallocated(len));
+            for _ in 0..len {
+                values.push(decode_value(dec)?);
+            }
+            Ok(Value::Path(values))
+        }
+        tag => Err(format!("Unknown value tag {tag}")),
+    }
+}
+
+pub fn encode_treemap(
+    enc: &mut impl Encoder,
+    treemap: &RoaringTreemap,
+) {
+    let mut buf = Vec::with_capacity(treemap.serialized_size());
+    treemap
+        .serialize_into(&mut buf)
+        .expect("writing to a Vec can not fail");
+    enc.write_bytes(&buf);
+}
+
+pub fn decode_treemap(dec: &mut impl Decoder) -> Result<RoaringTreemap, String> {
+    let buf = dec.read_bytes()?;
+    RoaringTreemap::deserialize_from(buf.as_slice()).map_err(|e| e.to_string())
+}
+
+pub fn encode_names(
+    enc: &mut impl Encoder,
+    names: &[Arc<String>],
+) {
+    enc.write_u64(names.len() as u64);
+    for name in names {
+        enc.write_str(name);
+    }
+}
+
+pub fn decode_names(dec: &mut impl Decoder) -> Result<Vec<Arc<String>>, String> {
+    let len = dec.read_len(8)?;
+    let mut names = Vec::with_capacity(preallocated(len));
+    for _ in 0..len {
+        names.push(Arc::new(dec.read_string()?));
+    }
+    Ok(names)
+}
+
+/// Encodes into an in-memory buffer, used where the encoded graph
+/// is shipped as a command payload rather than written to an RDB.
+impl Encoder for Vec<u8> {
+    fn write_u64(
+        &mut self,
+        value: u64,
+    ) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_i64(
+        &mut self,
+        value: i64,
+    ) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_f64(
+        &mut self,
+        value: f64,
+    ) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_bytes(
+        &mut self,
+        value: &[u8],
+    ) {
+        self.write_u64(value.len() as u64);
+        self.extend_from_slice(value);
+    }
+}
+
+/// Decodes a buffer written by the [`Encoder`] implementation of `Vec<u8>`.
+pub struct SliceDecoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> SliceDecoder<'a> {
+    #[must_use]
+    pub const fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// Returns whether the whole buffer was decoded.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn take(
+        &mut self,
+        len: usize,
+    ) -> Result<&'a [u8], String> {
+        if self.buf.len() < len {
+            return Err("Unexpected end of encoded data".to_string());
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn take_array(&mut self) -> Result<[u8; 8], String> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(bytes)
+    }
+}
+
+impl Decoder for SliceDecoder<'_> {
+    fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take_array()?))
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.read_len(1)?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.buf.len())
+    }
+}
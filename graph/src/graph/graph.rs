@@ -1,7 +1,6 @@
 use std::{
     collections::{HashMap, HashSet},
-    rc::Rc,
-    sync::Mutex,
+    sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError},
     time::{Duration, Instant},
 };
 
@@ -14,16 +13,22 @@ use crate::{
     ast::ExprIR,
     cypher::Parser,
     graph::{
+        bulk::{NodeBatch, RelationshipBatch},
+        changes::{Change, ChangeLog},
+        encoding::{
+            Decoder, ENCODING_VERSION, Encoder, decode_names, decode_treemap, decode_value,
+            encode_names, encode_treemap, encode_value, preallocated,
+        },
         matrix::{Dup, ElementWiseAdd, ElementWiseMultiply, Matrix, MxM, New, Remove, Set, Size},
         tensor::Tensor,
     },
     indexer::{Document, IndexQuery, Indexer},
     planner::{IR, Planner},
-    runtime::{pending::PendingRelationship, value::Value},
+    runtime::{pending::PendingRelationship, runtime::QueryStatistics, value::Value},
 };
 
 pub struct Plan {
-    pub plan: Rc<DynTree<IR>>,
+    pub plan: Arc<DynTree<IR>>,
     pub parameters: HashMap<String, DynTree<ExprIR>>,
     pub parse_duration: Duration,
     pub plan_duration: Duration,
@@ -70,6 +75,12 @@ impl From<NodeId> for u64 {
     }
 }
 
+impl From<u64> for RelationshipId {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
 impl From<RelationshipId> for u64 {
     fn from(value: RelationshipId) -> Self {
         value.0
@@ -79,7 +90,7 @@ impl From<RelationshipId> for u64 {
 impl Plan {
     #[must_use]
     pub const fn new(
-        plan: Rc<DynTree<IR>>,
+        plan: Arc<DynTree<IR>>,
         parameters: HashMap<String, DynTree<ExprIR>>,
         parse_duration: Duration,
         plan_duration: Duration,
@@ -113,11 +124,13 @@ pub struct Graph {
     node_attrs: HashMap<NodeId, OrderMap<AttrId, Value>>,
     relationship_attrs: HashMap<RelationshipId, OrderMap<AttrId, Value>>,
     node_indexer: Indexer,
-    node_labels: Vec<Rc<String>>,
-    relationship_types: Vec<Rc<String>>,
-    node_attrs_name: Vec<Rc<String>>,
-    relationship_attrs_name: Vec<Rc<String>>,
-    cache: Mutex<HashMap<String, Rc<DynTree<IR>>>>,
+    node_labels: Vec<Arc<String>>,
+    relationship_types: Vec<Arc<String>>,
+    node_attrs_name: Vec<Arc<String>>,
+    relationship_attrs_name: Vec<Arc<String>>,
+    cache: Mutex<OrderMap<String, Arc<DynTree<IR>>>>,
+    /// The committed changes, kept while change capture is enabled.
+    change_log: Option<ChangeLog>,
 }
 
 impl Graph {
@@ -150,37 +163,61 @@ impl Graph {
             relationship_types: Vec::new(),
             node_attrs_name: Vec::new(),
             relationship_attrs_name: Vec::new(),
-            cache: Mutex::new(HashMap::new()),
+            cache: Mutex::new(OrderMap::new()),
+            change_log: None,
         }
     }
 
+    pub const fn node_count(&self) -> u64 {
+        self.node_count
+    }
+
+    pub const fn relationship_count(&self) -> u64 {
+        self.relationship_count
+    }
+
+    pub fn label_node_count(
+        &self,
+        label: &str,
+    ) -> u64 {
+        self.get_label_matrix(label).map_or(0, |m| m.nvals())
+    }
+
+    pub fn type_relationship_count(
+        &self,
+        relationship_type: &Arc<String>,
+    ) -> u64 {
+        self.get_relationship_matrix(relationship_type)
+            .map_or(0, Tensor::nvals)
+    }
+
     pub const fn get_labels_count(&self) -> usize {
         self.node_labels.len()
     }
 
-    pub fn get_labels(&self) -> Vec<Rc<String>> {
+    pub fn get_labels(&self) -> Vec<Arc<String>> {
         self.node_labels.clone()
     }
 
     pub fn get_label_by_id(
         &self,
         id: LabelId,
-    ) -> Rc<String> {
+    ) -> Arc<String> {
         self.node_labels[id.0].clone()
     }
 
-    pub fn get_types(&self) -> Vec<Rc<String>> {
+    pub fn get_types(&self) -> Vec<Arc<String>> {
         self.relationship_types.clone()
     }
 
     pub fn get_type(
         &self,
         id: TypeId,
-    ) -> Option<Rc<String>> {
+    ) -> Option<Arc<String>> {
         self.relationship_types.get(id.0).cloned()
     }
 
-    pub fn get_attrs(&self) -> Vec<Rc<String>> {
+    pub fn get_attrs(&self) -> Vec<Arc<String>> {
         self.node_attrs_name
             .iter()
             .chain(self.relationship_attrs_name.iter())
@@ -208,9 +245,13 @@ impl Graph {
             .map(TypeId)
     }
 
+    /// Parses and plans `query`, reusing the cached plan of an identical query.
+    ///
+    /// The cache keeps the `cache_size` most recently used plans.
     pub fn get_plan(
         &self,
         query: &str,
+        cache_size: usize,
     ) -> Result<Plan, String> {
         let mut parse_duration = Duration::ZERO;
         let mut plan_duration = Duration::ZERO;
@@ -220,9 +261,11 @@ impl Graph {
 
         match self.cache.lock() {
             Ok(mut cache) => {
-                if let Some(f) = cache.get(query) {
+                if let Some(index) = cache.get_index_of(query) {
+                    let last = cache.len() - 1;
+                    cache.move_index(index, last);
                     Ok(Plan::new(
-                        f.clone(),
+                        cache[last].clone(),
                         parameters,
                         parse_duration,
                         plan_duration,
@@ -234,10 +277,15 @@ impl Graph {
 
                     let mut planner = Planner::default();
                     let start = Instant::now();
-                    let value = Rc::new(planner.plan(ir));
+                    let value = Arc::new(planner.plan(ir));
                     plan_duration = start.elapsed();
 
-                    cache.insert(query.to_string(), value.clone());
+                    if cache_size > 0 {
+                        while cache.len() >= cache_size {
+                            cache.remove_index(0);
+                        }
+                        cache.insert(query.to_string(), value.clone());
+                    }
                     Ok(Plan::new(value, parameters, parse_duration, plan_duration))
                 }
             }
@@ -257,7 +305,7 @@ impl Graph {
 
     fn get_label_matrix_mut(
         &mut self,
-        label: &Rc<String>,
+        label: &Arc<String>,
     ) -> &mut Matrix<bool> {
         if !self.node_labels.contains(label) {
             self.node_labels.push(label.clone());
@@ -281,7 +329,7 @@ impl Graph {
 
     fn get_relationship_matrix_mut(
         &mut self,
-        relationship_type: &Rc<String>,
+        relationship_type: &Arc<String>,
     ) -> &mut Tensor {
         if !self.relationship_types.contains(relationship_type) {
             self.relationship_types.push(relationship_type.clone());
@@ -305,7 +353,7 @@ impl Graph {
 
     fn get_relationship_matrix(
         &self,
-        relationship_type: &Rc<String>,
+        relationship_type: &Arc<String>,
     ) -> Option<&Tensor> {
         if !self.relationship_types.contains(relationship_type) {
             return None;
@@ -333,13 +381,13 @@ impl Graph {
     pub fn get_node_attribute_string(
         &self,
         id: AttrId,
-    ) -> Option<Rc<String>> {
+    ) -> Option<Arc<String>> {
         self.node_attrs_name.get(id.0).cloned()
     }
 
     pub fn get_or_add_node_attribute_id(
         &mut self,
-        key: &Rc<String>,
+        key: &Arc<String>,
     ) -> AttrId {
         AttrId(
             self.node_attrs_name
@@ -363,7 +411,7 @@ impl Graph {
                 .position(|p| p.as_str() == key)
                 .unwrap_or_else(|| {
                     let len = self.relationship_attrs_name.len();
-                    self.relationship_attrs_name.push(Rc::new(key.clone()));
+                    self.relationship_attrs_name.push(Arc::new(key.clone()));
                     len
                 }),
         )
@@ -382,10 +430,49 @@ impl Graph {
     pub fn get_relationship_attribute_string(
         &self,
         id: AttrId,
-    ) -> Option<Rc<String>> {
+    ) -> Option<Arc<String>> {
         self.relationship_attrs_name.get(id.0).cloned()
     }
 
+    /// Returns whether `id` is a node of the graph, as opposed to a deleted or unused id.
+    #[must_use]
+    pub fn contains_node(
+        &self,
+        id: NodeId,
+    ) -> bool {
+        id.0 < self.node_count + self.deleted_nodes.len() && !self.deleted_nodes.contains(id.0)
+    }
+
+    /// Returns whether `id` is a relationship of the graph, as opposed to a deleted or unused id.
+    #[must_use]
+    pub fn contains_relationship(
+        &self,
+        id: RelationshipId,
+    ) -> bool {
+        id.0 < self.relationship_count + self.deleted_relationships.len()
+            && !self.deleted_relationships.contains(id.0)
+    }
+
+    /// Returns the ids the next created nodes get, in order, see [`Graph::reserve_node`].
+    pub fn next_node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        let high_water = self.node_count + self.deleted_nodes.len();
+        self.deleted_nodes
+            .iter()
+            .chain(high_water..)
+            .skip(self.reserved_node_count as usize)
+            .map(NodeId)
+    }
+
+    /// Returns the ids the next created relationships get, in order, see [`Graph::reserve_relationship`].
+    pub fn next_relationship_ids(&self) -> impl Iterator<Item = RelationshipId> + '_ {
+        let high_water = self.relationship_count + self.deleted_relationships.len();
+        self.deleted_relationships
+            .iter()
+            .chain(high_water..)
+            .skip(self.reserved_relationship_count as usize)
+            .map(RelationshipId)
+    }
+
     pub fn reserve_node(&mut self) -> NodeId {
         if self.reserved_node_count < self.deleted_nodes.len() {
             let id = self.deleted_nodes.select(self.reserved_node_count).unwrap();
@@ -455,7 +542,7 @@ impl Graph {
     pub fn set_node_labels(
         &mut self,
         id: NodeId,
-        labels: &OrderSet<Rc<String>>,
+        labels: &OrderSet<Arc<String>>,
     ) {
         for label in labels {
             let label_matrix = self.get_label_matrix_mut(label);
@@ -479,7 +566,7 @@ impl Graph {
     pub fn remove_node_labels(
         &mut self,
         id: NodeId,
-        labels: &OrderSet<Rc<String>>,
+        labels: &OrderSet<Arc<String>>,
     ) {
         for label in labels {
             if !self.node_labels.contains(label) {
@@ -545,26 +632,53 @@ impl Graph {
             })
     }
 
+    /// Returns the relationships of `types`, of any type when empty, leaving `id`
+    /// or entering it when `incoming`, as their source, destination and id.
+    pub fn get_adjacent_relationships(
+        &self,
+        id: NodeId,
+        types: &[Arc<String>],
+        incoming: bool,
+    ) -> Vec<(NodeId, NodeId, RelationshipId)> {
+        let mut vec = vec![];
+        for relationship_type in if types.is_empty() {
+            &self.relationship_types
+        } else {
+            types
+        } {
+            if let Some(relationship_matrix) = self.get_relationship_matrix(relationship_type) {
+                for (src, dest, id) in relationship_matrix.iter(id.0, id.0, incoming) {
+                    vec.push((NodeId(src), NodeId(dest), RelationshipId(id)));
+                }
+            }
+        }
+        vec
+    }
+
+    /// Returns the nodes having all `labels`, in the `part`-th of `parts` slices
+    /// of the scanned matrix.
     pub fn get_nodes(
         &self,
-        labels: &OrderSet<Rc<String>>,
+        labels: &OrderSet<Arc<String>>,
+        part: u64,
+        parts: u64,
     ) -> impl Iterator<Item = NodeId> + use<> {
         let iter = if labels.is_empty() {
-            self.all_nodes_matrix.iter(0, u64::MAX)
+            self.all_nodes_matrix.iter_part(part, parts)
         } else {
             let matrices = labels
                 .iter()
                 .map(|label| self.get_label_matrix(label))
                 .collect::<Option<Vec<_>>>();
             matrices.map_or_else(
-                || self.zero_matrix.iter(0, u64::MAX),
+                || self.zero_matrix.iter_part(part, parts),
                 |matrices| {
                     let mut iter = matrices.iter();
                     let mut m = iter.next().unwrap().dup();
                     for label_matrix in iter {
                         m.element_wise_multiply(label_matrix);
                     }
-                    m.iter(0, u64::MAX)
+                    m.iter_part(part, parts)
                 },
             )
         };
@@ -584,7 +698,7 @@ impl Graph {
     pub fn get_node_labels(
         &self,
         id: NodeId,
-    ) -> impl Iterator<Item = Rc<String>> {
+    ) -> impl Iterator<Item = Arc<String>> {
         self.get_node_label_ids(id)
             .map(move |label_id| self.node_labels[label_id.0].clone())
     }
@@ -612,9 +726,257 @@ impl Graph {
         RelationshipId(self.relationship_count + self.reserved_relationship_count - 1)
     }
 
+    /// Starts keeping the latest `capacity` committed changes,
+    /// the changes kept so far are dropped if it was already enabled.
+    pub fn enable_change_capture(
+        &mut self,
+        capacity: usize,
+    ) {
+        self.change_log = Some(ChangeLog::new(capacity));
+    }
+
+    pub fn disable_change_capture(&mut self) {
+        self.change_log = None;
+    }
+
+    #[must_use]
+    pub const fn change_log(&self) -> Option<&ChangeLog> {
+        self.change_log.as_ref()
+    }
+
+    pub fn log_changes(
+        &mut self,
+        changes: Vec<Change>,
+    ) {
+        if let Some(change_log) = &mut self.change_log {
+            for change in changes {
+                change_log.push(change);
+            }
+        }
+    }
+
+    /// Creates the nodes and relationships of a bulk load resizing the matrices once,
+    /// the nodes get the ids `CREATE` would give them in the order of the batches.
+    ///
+    /// # Errors
+    /// Fails without changing the graph if a relationship connects a missing node.
+    pub fn bulk_insert(
+        &mut self,
+        node_batches: &[NodeBatch],
+        relationship_batches: &[RelationshipBatch],
+    ) -> Result<QueryStatistics, String> {
+        let node_ids = node_batches
+            .iter()
+            .map(|batch| {
+                batch
+                    .nodes
+                    .iter()
+                    .map(|_| self.reserve_node())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let created_nodes = node_ids
+            .iter()
+            .flatten()
+            .map(|id| id.0)
+            .collect::<RoaringTreemap>();
+        let high_water = self.node_count + self.deleted_nodes.len();
+        let missing = relationship_batches
+            .iter()
+            .flat_map(|batch| &batch.relationships)
+            .flat_map(|(from, to, _)| [from, to])
+            .find(|id| {
+                !created_nodes.contains(id.0)
+                    && (id.0 >= high_water || self.deleted_nodes.contains(id.0))
+            });
+        if let Some(id) = missing {
+            self.release_reservations();
+            return Err(format!("Relationship connects missing node {}", id.0));
+        }
+        let relationship_ids = relationship_batches
+            .iter()
+            .map(|batch| {
+                batch
+                    .relationships
+                    .iter()
+                    .map(|_| self.reserve_relationship())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut stats = QueryStatistics::default();
+        let labels_count = self.node_labels.len();
+        for batch in node_batches {
+            for label in &batch.labels {
+                self.get_label_matrix_mut(label);
+            }
+        }
+        for batch in relationship_batches {
+            self.get_relationship_matrix_mut(&batch.type_name);
+        }
+        stats.labels_added = self.node_labels.len() - labels_count;
+        stats.nodes_created = created_nodes.len();
+        stats.relationships_created = relationship_ids.iter().map(Vec::len).sum();
+        self.node_count += created_nodes.len();
+        self.reserved_node_count -= created_nodes.len();
+        self.deleted_nodes -= &created_nodes;
+        self.relationship_count += stats.relationships_created as u64;
+        self.reserved_relationship_count -= stats.relationships_created as u64;
+        for id in relationship_ids.iter().flatten() {
+            self.deleted_relationships.remove(id.0);
+        }
+        self.resize();
+
+        let mut changes = self.change_log.is_some().then(Vec::new);
+        for (batch, ids) in node_batches.iter().zip(&node_ids) {
+            let label_ids = batch
+                .labels
+                .iter()
+                .map(|label| self.get_label_id(label).unwrap().0)
+                .collect::<Vec<_>>();
+            let attr_ids = batch
+                .attrs
+                .iter()
+                .map(|attr| self.get_or_add_node_attribute_id(attr))
+                .collect::<Vec<_>>();
+            for (id, values) in ids.iter().zip(&batch.nodes) {
+                self.all_nodes_matrix.set(id.0, id.0, true);
+                for label_id in &label_ids {
+                    self.labels_matices
+                        .get_mut(label_id)
+                        .unwrap()
+                        .set(id.0, id.0, true);
+                    self.node_labels_matrix.set(id.0, *label_id as u64, true);
+                }
+                let attrs = attr_ids
+                    .iter()
+                    .zip(values)
+                    .filter(|(_, value)| **value != Value::Null)
+                    .map(|(attr_id, value)| (*attr_id, value.clone()))
+                    .collect::<OrderMap<_, _>>();
+                stats.properties_set += attrs.len();
+                for label_id in &label_ids {
+                    let mut doc = Document::new(id.0);
+                    for (attr_id, value) in &attrs {
+                        if self
+                            .node_indexer
+                            .is_indexed(*label_id as u64, attr_id.0 as u64)
+                        {
+                            doc.set(attr_id.0 as u64, value.clone());
+                        }
+                    }
+                    self.node_indexer.add(*label_id as u64, doc);
+                }
+                if let Some(changes) = &mut changes {
+                    changes.push(Change::NodeCreated(*id));
+                    for label in &batch.labels {
+                        changes.push(Change::LabelAdded(*id, label.clone()));
+                    }
+                    for (attr_id, value) in &attrs {
+                        changes.push(Change::NodePropertySet {
+                            id: *id,
+                            key: self.node_attrs_name[attr_id.0].clone(),
+                            old: Value::Null,
+                            new: value.clone(),
+                        });
+                    }
+                }
+                if !attrs.is_empty() {
+                    self.node_attrs.insert(*id, attrs);
+                }
+            }
+        }
+
+        for (batch, ids) in relationship_batches.iter().zip(&relationship_ids) {
+            let type_id = self.get_type_id(&batch.type_name).unwrap().0;
+            let attr_ids = batch
+                .attrs
+                .iter()
+                .map(|attr| self.get_or_add_relationship_attribute_id(attr))
+                .collect::<Vec<_>>();
+            for (id, (from, to, values)) in ids.iter().zip(&batch.relationships) {
+                self.relationship_matrices
+                    .get_mut(&type_id)
+                    .unwrap()
+                    .set(from.0, to.0, id.0);
+                self.adjacancy_matrix.set(from.0, to.0, true);
+                self.relationship_type_matrix
+                    .set(id.0, type_id as u64, true);
+                let attrs = attr_ids
+                    .iter()
+                    .zip(values)
+                    .filter(|(_, value)| **value != Value::Null)
+                    .map(|(attr_id, value)| (*attr_id, value.clone()))
+                    .collect::<OrderMap<_, _>>();
+                stats.properties_set += attrs.len();
+                if let Some(changes) = &mut changes {
+                    changes.push(Change::RelationshipCreated {
+                        id: *id,
+                        type_name: batch.type_name.clone(),
+                        from: *from,
+                        to: *to,
+                    });
+                    for (attr_id, value) in &attrs {
+                        changes.push(Change::RelationshipPropertySet {
+                            id: *id,
+                            key: self.relationship_attrs_name[attr_id.0].clone(),
+                            old: Value::Null,
+                            new: value.clone(),
+                        });
+                    }
+                }
+                if !attrs.is_empty() {
+                    self.relationship_attrs.insert(*id, attrs);
+                }
+            }
+        }
+
+        self.flush();
+        if let Some(changes) = changes {
+            self.log_changes(changes);
+        }
+        Ok(stats)
+    }
+
+    /// Reserves ids picked by another copy of the graph,
+    /// before creating the entities of a query replicated from it.
+    pub const fn reserve_replicated(
+        &mut self,
+        nodes: u64,
+        relationships: u64,
+    ) {
+        self.reserved_node_count += nodes;
+        self.reserved_relationship_count += relationships;
+    }
+
+    /// Forgets the node and relationship ids reserved by a query that failed before committing.
+    pub const fn release_reservations(&mut self) {
+        self.reserved_node_count = 0;
+        self.reserved_relationship_count = 0;
+    }
+
+    /// Finishes the pending GraphBLAS work of every matrix.
+    ///
+    /// Reading a matrix with pending work modifies it, so this runs after every
+    /// write to let the graph be read from several threads at once.
+    pub fn flush(&mut self) {
+        self.zero_matrix.wait();
+        self.adjacancy_matrix.wait();
+        self.node_labels_matrix.wait();
+        self.relationship_type_matrix.wait();
+        self.all_nodes_matrix.wait();
+        for m in self.labels_matices.values() {
+            m.wait();
+        }
+        for t in self.relationship_matrices.values() {
+            t.wait();
+        }
+        self.node_indexer.wait();
+    }
+
     pub fn create_relationships(
         &mut self,
-        relationships: &HashMap<RelationshipId, PendingRelationship>,
+        relationships: &OrderMap<RelationshipId, PendingRelationship>,
     ) {
         self.relationship_count += relationships.len() as u64;
         self.reserved_relationship_count -= relationships.len() as u64;
@@ -707,7 +1069,7 @@ impl Graph {
         &self,
         src: NodeId,
         dest: NodeId,
-        types: &[Rc<String>],
+        types: &[Arc<String>],
     ) -> Vec<RelationshipId> {
         let mut vec = vec![];
         for relationship_type in if types.is_empty() {
@@ -724,11 +1086,15 @@ impl Graph {
         vec
     }
 
+    /// Returns the source and destination of the relationships matching the pattern,
+    /// in the `part`-th of `parts` slices of the scanned matrix.
     pub fn get_relationships(
         &self,
-        types: &[Rc<String>],
-        src_lables: &OrderSet<Rc<String>>,
-        dest_labels: &OrderSet<Rc<String>>,
+        types: &[Arc<String>],
+        src_lables: &OrderSet<Arc<String>>,
+        dest_labels: &OrderSet<Arc<String>>,
+        part: u64,
+        parts: u64,
     ) -> impl Iterator<Item = (NodeId, NodeId)> + use<> {
         let matrices = types
             .iter()
@@ -770,9 +1136,9 @@ impl Graph {
                 }
                 m.lmxm(&dest_matrix);
             }
-            m.iter(0, u64::MAX)
+            m.iter_part(part, parts)
         } else {
-            self.zero_matrix.iter(0, u64::MAX)
+            self.zero_matrix.iter_part(part, parts)
         };
 
         iter.map(|(src, dest)| (NodeId(src), NodeId(dest)))
@@ -853,8 +1219,8 @@ impl Graph {
 
     pub fn create_node_index(
         &mut self,
-        label: &Rc<String>,
-        attrs: &Vec<Rc<String>>,
+        label: &Arc<String>,
+        attrs: &Vec<Arc<String>>,
     ) {
         self.get_label_matrix_mut(label);
         let label_id = self.get_label_id(label).unwrap();
@@ -874,12 +1240,13 @@ impl Graph {
             }
             self.node_indexer.add(label_id.0 as u64, doc);
         }
+        self.flush();
     }
 
     pub fn drop_node_index(
         &mut self,
-        label: &Rc<String>,
-        attrs: &Vec<Rc<String>>,
+        label: &Arc<String>,
+        attrs: &Vec<Arc<String>>,
     ) {
         if let Some(label_id) = self.get_label_id(label) {
             for attr in attrs {
@@ -888,12 +1255,13 @@ impl Graph {
                     .drop_index(label_id.0 as u64, prop_id.0 as u64);
             }
         }
+        self.flush();
     }
 
     pub fn is_indexed(
         &self,
-        label: &Rc<String>,
-        key: &Rc<String>,
+        label: &Arc<String>,
+        key: &Arc<String>,
     ) -> bool {
         if let Some(label_id) = self.get_label_id(label)
             && let Some(prop_id) = self.get_node_attribute_id(key)
@@ -907,8 +1275,8 @@ impl Graph {
 
     pub fn get_indexed_nodes(
         &self,
-        label: &Rc<String>,
-        key: &Rc<String>,
+        label: &Arc<String>,
+        key: &Arc<String>,
         value: Value,
     ) -> Vec<NodeId> {
         if let Some(label_id) = self.get_label_id(label)
@@ -926,4 +1294,358 @@ impl Graph {
             Vec::new()
         }
     }
+
+    /// Deep copy of the graph, matrices are duplicated in GraphBLAS.
+    ///
+    /// # Panics
+    /// Panics if the plan cache lock is poisoned.
+    #[must_use]
+    pub fn dup(&self) -> Self {
+        Self {
+            node_cap: self.node_cap,
+            relationship_cap: self.relationship_cap,
+            reserved_node_count: self.reserved_node_count,
+            reserved_relationship_count: self.reserved_relationship_count,
+            node_count: self.node_count,
+            relationship_count: self.relationship_count,
+            deleted_nodes: self.deleted_nodes.clone(),
+            deleted_relationships: self.deleted_relationships.clone(),
+            zero_matrix: self.zero_matrix.dup(),
+            adjacancy_matrix: self.adjacancy_matrix.dup(),
+            node_labels_matrix: self.node_labels_matrix.dup(),
+            relationship_type_matrix: self.relationship_type_matrix.dup(),
+            all_nodes_matrix: self.all_nodes_matrix.dup(),
+            labels_matices: self
+                .labels_matices
+                .iter()
+                .map(|(id, m)| (*id, m.dup()))
+                .collect(),
+            relationship_matrices: self
+                .relationship_matrices
+                .iter()
+                .map(|(id, t)| (*id, t.dup()))
+                .collect(),
+            empty_map: OrderMap::new(),
+            node_attrs: self.node_attrs.clone(),
+            relationship_attrs: self.relationship_attrs.clone(),
+            node_indexer: self.node_indexer.dup(),
+            node_labels: self.node_labels.clone(),
+            relationship_types: self.relationship_types.clone(),
+            node_attrs_name: self.node_attrs_name.clone(),
+            relationship_attrs_name: self.relationship_attrs_name.clone(),
+            cache: Mutex::new(self.cache.lock().unwrap().clone()),
+            change_log: None,
+        }
+    }
+
+    /// Approximate number of bytes held by the graph,
+    /// covering the matrices, the property maps and the indexer.
+    #[must_use]
+    pub fn memory_usage(&self) -> usize {
+        let matrices = [
+            &self.zero_matrix,
+            &self.adjacancy_matrix,
+            &self.node_labels_matrix,
+            &self.relationship_type_matrix,
+            &self.all_nodes_matrix,
+        ]
+        .into_iter()
+        .chain(self.labels_matices.values())
+        .map(Matrix::memory_usage)
+        .sum::<usize>()
+            + self
+                .relationship_matrices
+                .values()
+                .map(Tensor::memory_usage)
+                .sum::<usize>();
+
+        let attrs_usage = |attrs: &OrderMap<AttrId, Value>| {
+            size_of::<OrderMap<AttrId, Value>>()
+                + attrs
+                    .values()
+                    .map(|v| size_of::<AttrId>() + v.memory_usage())
+                    .sum::<usize>()
+        };
+        let attrs = self
+            .node_attrs
+            .values()
+            .map(|attrs| size_of::<NodeId>() + attrs_usage(attrs))
+            .sum::<usize>()
+            + self
+                .relationship_attrs
+                .values()
+                .map(|attrs| size_of::<RelationshipId>() + attrs_usage(attrs))
+                .sum::<usize>();
+
+        let names = self
+            .node_labels
+            .iter()
+            .chain(&self.relationship_types)
+            .chain(&self.node_attrs_name)
+            .chain(&self.relationship_attrs_name)
+            .map(|name| size_of::<Arc<String>>() + name.capacity())
+            .sum::<usize>();
+
+        size_of::<Self>() + matrices + attrs + names + self.node_indexer.memory_usage()
+    }
+
+    /// Rough number of allocations released when the graph is dropped,
+    /// used by Redis to decide whether to free the graph in the background.
+    #[must_use]
+    pub fn free_effort(&self) -> usize {
+        self.labels_matices.len()
+            + self.relationship_matrices.len()
+            + self.node_attrs.len()
+            + self.relationship_attrs.len()
+    }
+
+    /// Writes the full content of the graph to `enc`.
+    ///
+    /// Only the data needed to rebuild the graph is written,
+    /// derived matrices and indexes are rebuilt by [`Self::decode`].
+    pub fn encode(
+        &self,
+        enc: &mut impl Encoder,
+    ) {
+        enc.write_u64(self.node_cap);
+        enc.write_u64(self.relationship_cap);
+        enc.write_u64(self.node_count);
+        enc.write_u64(self.relationship_count);
+        encode_treemap(enc, &self.deleted_nodes);
+        encode_treemap(enc, &self.deleted_relationships);
+
+        encode_names(enc, &self.node_labels);
+        encode_names(enc, &self.relationship_types);
+        encode_names(enc, &self.node_attrs_name);
+        encode_names(enc, &self.relationship_attrs_name);
+
+        enc.write_u64(self.all_nodes_matrix.nvals());
+        for (id, _) in self.all_nodes_matrix.iter(0, u64::MAX) {
+            enc.write_u64(id);
+        }
+
+        for label_id in 0..self.node_labels.len() {
+            let label_matrix = &self.labels_matices[&label_id];
+            enc.write_u64(label_matrix.nvals());
+            for (id, _) in label_matrix.iter(0, u64::MAX) {
+                enc.write_u64(id);
+            }
+        }
+
+        for type_id in 0..self.relationship_types.len() {
+            let relationships = self.relationship_matrices[&type_id]
+                .iter(0, u64::MAX, false)
+                .collect::<Vec<_>>();
+            enc.write_u64(relationships.len() as u64);
+            for (src, dest, id) in relationships {
+                enc.write_u64(id);
+                enc.write_u64(src);
+                enc.write_u64(dest);
+            }
+        }
+
+        enc.write_u64(self.node_attrs.len() as u64);
+        for (id, attrs) in &self.node_attrs {
+            enc.write_u64(id.0);
+            enc.write_u64(attrs.len() as u64);
+            for (attr_id, value) in attrs {
+                enc.write_u64(attr_id.0 as u64);
+                encode_value(enc, value);
+            }
+        }
+
+        enc.write_u64(self.relationship_attrs.len() as u64);
+        for (id, attrs) in &self.relationship_attrs {
+            enc.write_u64(id.0);
+            enc.write_u64(attrs.len() as u64);
+            for (attr_id, value) in attrs {
+                enc.write_u64(attr_id.0 as u64);
+                encode_value(enc, value);
+            }
+        }
+
+        let indexes = self
+            .node_indexer
+            .indexed_keys()
+            .sorted()
+            .collect::<Vec<_>>();
+        enc.write_u64(indexes.len() as u64);
+        for (label_id, attr_id) in indexes {
+            enc.write_u64(label_id);
+            enc.write_u64(attr_id);
+        }
+    }
+
+    /// Reads a graph written by [`Self::encode`] and rebuilds its indexes.
+    ///
+    /// # Errors
+    /// Fails if `version` is unknown or the encoded data is malformed.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn decode(
+        dec: &mut impl Decoder,
+        version: u64,
+    ) -> Result<Self, String> {
+        if version != ENCODING_VERSION {
+            return Err(format!("Unsupported graph encoding version {version}"));
+        }
+
+        let node_cap = dec.read_u64()?;
+        let relationship_cap = dec.read_u64()?;
+        if node_cap == 0 || relationship_cap == 0 {
+            return Err(String::from("Invalid graph capacity 0"));
+        }
+        let mut g = Self::new(node_cap, relationship_cap);
+        g.node_count = dec.read_u64()?;
+        g.relationship_count = dec.read_u64()?;
+        g.deleted_nodes = decode_treemap(dec)?;
+        g.deleted_relationships = decode_treemap(dec)?;
+        // the ids in use, live or deleted, are all below the capacity
+        if g.node_count.saturating_add(g.deleted_nodes.len()) > node_cap
+            || g.relationship_count
+                .saturating_add(g.deleted_relationships.len())
+                > relationship_cap
+        {
+            return Err(String::from("Entity count exceeds the graph capacity"));
+        }
+        let node_id = |id: u64| {
+            if id < node_cap {
+                Ok(id)
+            } else {
+                Err(format!("Invalid node id {id}"))
+            }
+        };
+        let relationship_id = |id: u64| {
+            if id < relationship_cap {
+                Ok(id)
+            } else {
+                Err(format!("Invalid relationship id {id}"))
+            }
+        };
+        if let Some(id) = g.deleted_nodes.max() {
+            node_id(id)?;
+        }
+        if let Some(id) = g.deleted_relationships.max() {
+            relationship_id(id)?;
+        }
+
+        g.node_labels = decode_names(dec)?;
+        g.relationship_types = decode_names(dec)?;
+        g.node_attrs_name = decode_names(dec)?;
+        g.relationship_attrs_name = decode_names(dec)?;
+
+        g.node_labels_matrix
+            .resize(node_cap, g.node_labels.len() as u64);
+        g.relationship_type_matrix
+            .resize(relationship_cap, g.relationship_types.len() as u64);
+
+        for _ in 0..dec.read_len(8)? {
+            let id = node_id(dec.read_u64()?)?;
+            g.all_nodes_matrix.set(id, id, true);
+        }
+
+        for label_id in 0..g.node_labels.len() {
+            let mut label_matrix = Matrix::<bool>::new(node_cap, node_cap);
+            for _ in 0..dec.read_len(8)? {
+                let id = node_id(dec.read_u64()?)?;
+                label_matrix.set(id, id, true);
+                g.node_labels_matrix.set(id, label_id as u64, true);
+            }
+            g.labels_matices.insert(label_id, label_matrix);
+        }
+
+        for type_id in 0..g.relationship_types.len() {
+            let mut relationship_matrix = Tensor::new(node_cap, node_cap);
+            for _ in 0..dec.read_len(24)? {
+                let id = relationship_id(dec.read_u64()?)?;
+                let src = node_id(dec.read_u64()?)?;
+                let dest = node_id(dec.read_u64()?)?;
+                relationship_matrix.set(src, dest, id);
+                g.adjacancy_matrix.set(src, dest, true);
+                g.relationship_type_matrix.set(id, type_id as u64, true);
+            }
+            g.relationship_matrices.insert(type_id, relationship_matrix);
+        }
+
+        for _ in 0..dec.read_len(16)? {
+            let id = NodeId(node_id(dec.read_u64()?)?);
+            let len = dec.read_len(16)?;
+            let mut attrs = OrderMap::with_capacity(preallocated(len));
+            for _ in 0..len {
+                let attr_id = AttrId(dec.read_u64()? as usize);
+                attrs.insert(attr_id, decode_value(dec)?);
+            }
+            g.node_attrs.insert(id, attrs);
+        }
+
+        for _ in 0..dec.read_len(16)? {
+            let id = RelationshipId(relationship_id(dec.read_u64()?)?);
+            let len = dec.read_len(16)?;
+            let mut attrs = OrderMap::with_capacity(preallocated(len));
+            for _ in 0..len {
+                let attr_id = AttrId(dec.read_u64()? as usize);
+                attrs.insert(attr_id, decode_value(dec)?);
+            }
+            g.relationship_attrs.insert(id, attrs);
+        }
+
+        for _ in 0..dec.read_len(16)? {
+            let label_id = dec.read_u64()?;
+            let attr_id = dec.read_u64()?;
+            let Some(label_matrix) = g.labels_matices.get(&(label_id as usize)) else {
+                return Err(format!("Index on unknown label {label_id}"));
+            };
+            if attr_id >= g.node_attrs_name.len() as u64 {
+                return Err(format!("Index on unknown attribute {attr_id}"));
+            }
+            g.node_indexer.create_index(label_id, attr_id);
+            for (n, _) in label_matrix.iter(0, u64::MAX) {
+                if let Some(value) = g
+                    .node_attrs
+                    .get(&NodeId(n))
+                    .and_then(|attrs| attrs.get(&AttrId(attr_id as usize)))
+                {
+                    let mut doc = Document::new(n);
+                    doc.set(attr_id, value.clone());
+                    g.node_indexer.add(label_id, doc);
+                }
+            }
+        }
+
+        g.flush();
+        Ok(g)
+    }
+}
+
+/// A graph shared between threads, borrowed like a `RefCell`.
+///
+/// Borrows never wait, like a `RefCell` they panic when the graph is already
+/// mutably borrowed, or borrowed at all for a mutable borrow.
+/// Queries borrowing it on several threads are serialized against writers by the caller.
+pub struct GraphCell(RwLock<Graph>);
+
+impl GraphCell {
+    #[must_use]
+    pub const fn new(graph: Graph) -> Self {
+        Self(RwLock::new(graph))
+    }
+
+    /// # Panics
+    /// Panics if the graph is currently mutably borrowed.
+    pub fn borrow(&self) -> RwLockReadGuard<'_, Graph> {
+        match self.0.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => panic!("graph already mutably borrowed"),
+        }
+    }
+
+    /// # Panics
+    /// Panics if the graph is currently borrowed.
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, Graph> {
+        match self.0.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => panic!("graph already borrowed"),
+        }
+    }
 }
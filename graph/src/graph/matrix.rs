@@ -1,6 +1,6 @@
 #![allow(clippy::doc_markdown)]
 
-use std::{marker::PhantomData, mem::MaybeUninit, os::raw::c_void, ptr::null_mut, rc::Rc};
+use std::{marker::PhantomData, mem::MaybeUninit, os::raw::c_void, ptr::null_mut, sync::Arc};
 
 use crate::graph::GraphBLAS::{
     GrB_BOOL, GrB_DESC_ST0, GrB_Info, GrB_Matrix, GrB_Matrix_apply, GrB_Matrix_dup,
@@ -11,8 +11,9 @@ use crate::graph::GraphBLAS::{
     GrB_UINT64, GrB_UnaryOp, GrB_UnaryOp_free, GrB_UnaryOp_new, GrB_WaitMode, GrB_finalize,
     GrB_mxm, GrB_transpose, GxB_ANY_PAIR_BOOL, GxB_Iterator, GxB_Iterator_free,
     GxB_Iterator_get_UINT64, GxB_Iterator_new, GxB_Matrix_Iterator_attach,
-    GxB_Matrix_Iterator_getIndex, GxB_Matrix_Iterator_next, GxB_Matrix_fprint, GxB_Print_Level,
-    GxB_init, GxB_rowIterator_seekRow, GxB_unary_function,
+    GxB_Matrix_Iterator_getIndex, GxB_Matrix_Iterator_getp, GxB_Matrix_Iterator_getpmax,
+    GxB_Matrix_Iterator_next, GxB_Matrix_Iterator_seek, GxB_Matrix_fprint, GxB_Matrix_memoryUsage,
+    GxB_Print_Level, GxB_init, GxB_rowIterator_seekRow, GxB_unary_function,
 };
 
 /// Initializes the GraphBLAS library in non-blocking mode.
@@ -252,14 +253,20 @@ impl MxM<bool> for Matrix<bool> {
 /// A wrapper around a GraphBLAS matrix with type safety for elements.
 pub struct Matrix<T> {
     /// The underlying GraphBLAS matrix.
-    m: Rc<GrB_Matrix>,
+    m: Arc<GrB_Matrix>,
     /// Phantom data to associate the matrix with a specific type.
     phantom: PhantomData<T>,
 }
 
+// SAFETY: GraphBLAS matrices can be read from several threads at once as long as
+// they have no pending work, every mutation goes through `&mut self` and the graph
+// materializes its matrices with `wait` before sharing them.
+unsafe impl<T: Send> Send for Matrix<T> {}
+unsafe impl<T: Sync> Sync for Matrix<T> {}
+
 impl<T> Drop for Matrix<T> {
     fn drop(&mut self) {
-        if let Some(m) = Rc::get_mut(&mut self.m) {
+        if let Some(m) = Arc::get_mut(&mut self.m) {
             unsafe {
                 let info = GrB_Matrix_free(m);
                 debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
@@ -275,6 +282,17 @@ impl<T> Matrix<T> {
             debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
         }
     }
+
+    /// Returns the number of bytes allocated by GraphBLAS for the matrix.
+    #[must_use]
+    pub fn memory_usage(&self) -> usize {
+        unsafe {
+            let mut size = 0usize;
+            let info = GxB_Matrix_memoryUsage(&raw mut size, *self.m);
+            debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
+            size
+        }
+    }
 }
 
 impl<T> Size<T> for Matrix<T> {
@@ -334,7 +352,7 @@ impl New for Matrix<bool> {
             let info = GrB_Matrix_new(m.as_mut_ptr(), GrB_BOOL, nrows, ncols);
             debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
             Self {
-                m: Rc::new(m.assume_init()),
+                m: Arc::new(m.assume_init()),
                 phantom: PhantomData,
             }
         }
@@ -351,7 +369,7 @@ impl New for Matrix<u64> {
             let info = GrB_Matrix_new(m.as_mut_ptr(), GrB_UINT64, nrows, ncols);
             debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
             Self {
-                m: Rc::new(m.assume_init()),
+                m: Arc::new(m.assume_init()),
                 phantom: PhantomData,
             }
         }
@@ -365,7 +383,7 @@ pub trait Dup<T> {
 impl<T> Dup<Self> for Matrix<T> {
     fn dup(&self) -> Self {
         Self {
-            m: Rc::new(unsafe {
+            m: Arc::new(unsafe {
                 let mut m: MaybeUninit<GrB_Matrix> = MaybeUninit::uninit();
                 let info = GrB_Matrix_dup(m.as_mut_ptr(), *self.m);
                 debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
@@ -383,7 +401,7 @@ pub trait DupBool {
 impl DupBool for Matrix<u64> {
     fn dup_bool(&self) -> Matrix<bool> {
         Matrix::<bool> {
-            m: Rc::new(unsafe {
+            m: Arc::new(unsafe {
                 let mut m: MaybeUninit<GrB_Matrix> = MaybeUninit::uninit();
                 let info = GrB_Matrix_new(m.as_mut_ptr(), GrB_BOOL, self.nrows(), self.ncols());
                 debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
@@ -408,6 +426,20 @@ impl Matrix<bool> {
         Iter::new(self, min_row, max_row)
     }
 
+    /// Iterates the `part`-th of `parts` slices of the matrix entries,
+    /// a single part iterates the whole matrix.
+    #[must_use]
+    pub fn iter_part(
+        &self,
+        part: u64,
+        parts: u64,
+    ) -> Iter<bool> {
+        if parts == 1 {
+            return Iter::new(self, 0, u64::MAX);
+        }
+        Iter::part(self, part, parts)
+    }
+
     pub fn print(&self) {
         unsafe {
             let info = GrB_Matrix_wait(*self.m, GrB_WaitMode::GrB_MATERIALIZE as _);
@@ -590,22 +622,27 @@ where
 }
 
 pub struct Iter<T> {
-    m: Rc<GrB_Matrix>,
+    m: Arc<GrB_Matrix>,
     /// The underlying GraphBLAS iterator.
     inner: GxB_Iterator,
     /// Indicates whether the iterator is depleted.
     depleted: bool,
     /// The maximum row index for the iterator.
     max_row: u64,
+    /// The position of the entry the iterator stops at.
+    max_entry: u64,
     /// Phantom data to associate the iterator with a specific type.
     phantom: PhantomData<T>,
 }
 
+// SAFETY: the iterator is owned by a single thread and keeps the matrix alive.
+unsafe impl<T: Send> Send for Iter<T> {}
+
 impl<T> Drop for Iter<T> {
     /// Frees the GraphBLAS iterator when the `Iter` is dropped.
     fn drop(&mut self) {
         unsafe {
-            if let Some(m) = Rc::get_mut(&mut self.m) {
+            if let Some(m) = Arc::get_mut(&mut self.m) {
                 let info = GrB_Matrix_free(m);
                 debug_assert_eq!(info, GrB_Info::GrB_SUCCESS);
             }
@@ -638,6 +675,41 @@ impl<T> Iter<T> {
                 inner: iter,
                 depleted: info == GrB_Info::GxB_EXHAUSTED,
                 max_row,
+                max_entry: u64::MAX,
+                phantom: PhantomData,
+            }
+        }
+    }
+
+    /// Creates an iterator over the `part`-th of `parts` slices of the matrix entries.
+    ///
+    /// The slices hold about the same number of entries and
+    /// iterating them in order yields the entries of a full iteration.
+    #[must_use]
+    pub fn part(
+        m: &Matrix<T>,
+        part: u64,
+        parts: u64,
+    ) -> Self {
+        unsafe {
+            let mut iter = MaybeUninit::uninit();
+            GxB_Iterator_new(iter.as_mut_ptr());
+            let iter = iter.assume_init();
+            GxB_Matrix_Iterator_attach(iter, *m.m, null_mut());
+            let entries = GxB_Matrix_Iterator_getpmax(iter);
+            let min_entry = entries / parts * part;
+            let max_entry = if part + 1 == parts {
+                entries
+            } else {
+                entries / parts * (part + 1)
+            };
+            let info = GxB_Matrix_Iterator_seek(iter, min_entry);
+            Self {
+                m: m.m.clone(),
+                inner: iter,
+                depleted: info == GrB_Info::GxB_EXHAUSTED || min_entry >= max_entry,
+                max_row: u64::MAX,
+                max_entry,
                 phantom: PhantomData,
             }
         }
@@ -660,7 +732,10 @@ impl Iterator for Iter<bool> {
             let mut row = 0u64;
             let mut col = 0u64;
             GxB_Matrix_Iterator_getIndex(self.inner, &raw mut row, &raw mut col);
-            if row > self.max_row {
+            if row > self.max_row
+                || (self.max_entry != u64::MAX
+                    && GxB_Matrix_Iterator_getp(self.inner) >= self.max_entry)
+            {
                 self.depleted = true;
                 return None;
             }
@@ -1,4 +1,7 @@
 pub mod GraphBLAS;
+pub mod bulk;
+pub mod changes;
+pub mod encoding;
 pub mod graph;
 pub mod matrix;
 pub mod tensor;
@@ -118,6 +118,17 @@ impl Tensor {
         self.mt.wait();
         self.me.wait();
     }
+
+    /// Returns the number of relationships stored in the tensor.
+    #[must_use]
+    pub fn nvals(&self) -> u64 {
+        self.me.nvals()
+    }
+
+    #[must_use]
+    pub fn memory_usage(&self) -> usize {
+        self.m.memory_usage() + self.mt.memory_usage() + self.me.memory_usage()
+    }
 }
 
 pub struct Iter<'a> {
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use crate::{
     graph::{
-        matrix::{Matrix, New, Remove, Set, Size},
+        matrix::{Dup, Matrix, New, Remove, Set, Size},
         tensor::GrB_INDEX_MAX,
     },
     runtime::value::Value,
@@ -79,6 +79,11 @@ impl Indexer {
         self.int_indexer.contains_key(&(label, key))
     }
 
+    /// Returns the (label, key) pairs that have an index.
+    pub fn indexed_keys(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.int_indexer.keys().copied()
+    }
+
     pub fn add(
         &mut self,
         label: u64,
@@ -127,6 +132,33 @@ impl Indexer {
         }
     }
 
+    /// Finishes the pending GraphBLAS work of every index, see `Graph::flush`.
+    pub fn wait(&self) {
+        for index in self.int_indexer.values() {
+            index.wait();
+        }
+    }
+
+    #[must_use]
+    pub fn dup(&self) -> Self {
+        Self {
+            ndocs: self.ndocs,
+            int_indexer: self
+                .int_indexer
+                .iter()
+                .map(|(key, index)| (*key, index.dup()))
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn memory_usage(&self) -> usize {
+        self.int_indexer
+            .values()
+            .map(|index| size_of::<(u64, u64)>() + index.memory_usage())
+            .sum()
+    }
+
     pub fn resize(
         &mut self,
         ndocs: u64,
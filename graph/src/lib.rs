@@ -1,5 +1,6 @@
 pub mod ast;
 pub mod cypher;
+pub mod explain;
 pub mod graph;
 pub mod indexer;
 pub mod planner;
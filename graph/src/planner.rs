@@ -1,6 +1,6 @@
-use std::{collections::HashSet, fmt::Display, rc::Rc};
+use std::{collections::HashSet, fmt::Display, sync::Arc};
 
-use orx_tree::{DynTree, NodeRef};
+use orx_tree::{Dfs, Dyn, DynTree, NodeIdx, NodeRef};
 
 use crate::{
     ast::{
@@ -14,19 +14,31 @@ use crate::{
 pub enum IR {
     Empty,
     Optional(Vec<Variable>),
-    Call(Rc<String>, Vec<DynTree<ExprIR>>),
+    Call(Arc<String>, Vec<DynTree<ExprIR>>),
     Unwind(DynTree<ExprIR>, Variable),
     Create(QueryGraph),
     Merge(QueryGraph),
     Delete(Vec<DynTree<ExprIR>>, bool),
     Set(Vec<(DynTree<ExprIR>, DynTree<ExprIR>, bool)>),
     Remove(Vec<DynTree<ExprIR>>),
-    NodeScan(Rc<QueryNode>),
-    RelationshipScan(Rc<QueryRelationship>),
-    ExpandInto(Rc<QueryRelationship>),
-    PathBuilder(Vec<Rc<QueryPath>>),
+    /// Runs its first child once for each element of the list, on rows of its second child.
+    Foreach(DynTree<ExprIR>, Variable),
+    /// Runs its first child once for each row of its second child, see [`QueryIR::CallSubquery`].
+    Apply(Option<DynTree<ExprIR>>),
+    /// Produces the row a `Foreach` or an `Apply` runs its first child with.
+    Argument,
+    NodeScan(Arc<QueryNode>),
+    RelationshipScan(Arc<QueryRelationship>),
+    ExpandInto(Arc<QueryRelationship>),
+    /// Follows a variable-length relationship from its source node, bound or scanned.
+    VarLengthTraverse(Arc<QueryRelationship>),
+    /// Finds the shortest paths between the bound endpoints of a relationship.
+    ShortestPath(Arc<QueryRelationship>),
+    PathBuilder(Vec<Arc<QueryPath>>),
     Filter(DynTree<ExprIR>),
     CartesianProduct,
+    /// Concatenates the rows of its children bound to the columns of the first one.
+    Union,
     LoadCsv {
         file_path: DynTree<ExprIR>,
         headers: bool,
@@ -45,15 +57,54 @@ pub enum IR {
     Distinct,
     Commit,
     CreateIndex {
-        label: Rc<String>,
-        attrs: Vec<Rc<String>>,
+        label: Arc<String>,
+        attrs: Vec<Arc<String>>,
     },
     DropIndex {
-        label: Rc<String>,
-        attrs: Vec<Rc<String>>,
+        label: Arc<String>,
+        attrs: Vec<Arc<String>>,
     },
 }
 
+impl IR {
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Empty => "Empty",
+            Self::Optional(_) => "Optional",
+            Self::Call(_, _) => "Call",
+            Self::Unwind(_, _) => "Unwind",
+            Self::Create(_) => "Create",
+            Self::Merge(_) => "Merge",
+            Self::Delete(_, _) => "Delete",
+            Self::Set(_) => "Set",
+            Self::Remove(_) => "Remove",
+            Self::Foreach(_, _) => "Foreach",
+            Self::Apply(_) => "Apply",
+            Self::Argument => "Argument",
+            Self::NodeScan(_) => "NodeScan",
+            Self::RelationshipScan(_) => "RelationshipScan",
+            Self::ExpandInto(_) => "ExpandInto",
+            Self::VarLengthTraverse(_) => "VarLengthTraverse",
+            Self::ShortestPath(_) => "ShortestPath",
+            Self::PathBuilder(_) => "PathBuilder",
+            Self::Filter(_) => "Filter",
+            Self::CartesianProduct => "CartesianProduct",
+            Self::Union => "Union",
+            Self::LoadCsv { .. } => "LoadCsv",
+            Self::Sort(_) => "Sort",
+            Self::Skip(_) => "Skip",
+            Self::Limit(_) => "Limit",
+            Self::Aggregate(_, _, _) => "Aggregate",
+            Self::Project(_) => "Project",
+            Self::Distinct => "Distinct",
+            Self::Commit => "Commit",
+            Self::CreateIndex { .. } => "CreateIndex",
+            Self::DropIndex { .. } => "DropIndex",
+        }
+    }
+}
+
 #[cfg_attr(tarpaulin, skip)]
 impl Display for IR {
     fn fmt(
@@ -72,12 +123,18 @@ impl Display for IR {
             Self::Delete(_, _) => write!(f, "Delete"),
             Self::Set(_) => write!(f, "Set"),
             Self::Remove(_) => write!(f, "Remove"),
+            Self::Foreach(_, var) => write!(f, "Foreach {}", var.as_str()),
+            Self::Apply(_) => write!(f, "Apply"),
+            Self::Argument => write!(f, "Argument"),
             Self::NodeScan(node) => write!(f, "NodeScan {node}"),
             Self::RelationshipScan(rel) => write!(f, "RelationshipScan {rel}"),
             Self::ExpandInto(rel) => write!(f, "ExpandInto {rel}"),
+            Self::VarLengthTraverse(rel) => write!(f, "VarLengthTraverse {rel}"),
+            Self::ShortestPath(rel) => write!(f, "ShortestPath {rel}"),
             Self::PathBuilder(_) => write!(f, "PathBuilder"),
             Self::Filter(_) => write!(f, "Filter"),
             Self::CartesianProduct => write!(f, "CartesianProduct"),
+            Self::Union => write!(f, "Union"),
             Self::LoadCsv { .. } => write!(f, "LoadCsv"),
             Self::Sort(_) => write!(f, "Sort"),
             Self::Skip(_) => write!(f, "Skip"),
@@ -96,6 +153,15 @@ impl Display for IR {
     }
 }
 
+/// Returns the plan operators in pre-order,
+/// the position of an operator in the result is its operator id.
+///
+/// Ids only depend on the plan shape so they are stable across executions of the same query.
+#[must_use]
+pub fn operator_ids(plan: &DynTree<IR>) -> Vec<NodeIdx<Dyn<IR>>> {
+    plan.root().indices::<Dfs>().collect()
+}
+
 #[derive(Default)]
 pub struct Planner {
     visited: HashSet<u32>,
@@ -125,7 +191,11 @@ impl Planner {
             }
             let mut iter = relationships.into_iter();
             let relationship = iter.next().unwrap();
-            let mut res = if relationship.from.alias.id == relationship.to.alias.id {
+            let mut res = if relationship.shortest.is_some() {
+                self.plan_shortest_path(&relationship, None)
+            } else if relationship.var_length.is_some() {
+                tree!(IR::VarLengthTraverse(relationship.clone()))
+            } else if relationship.from.alias.id == relationship.to.alias.id {
                 tree!(
                     IR::ExpandInto(relationship.clone()),
                     tree!(IR::NodeScan(relationship.from.clone()))
@@ -137,7 +207,11 @@ impl Planner {
             self.visited.insert(relationship.to.alias.id);
             self.visited.insert(relationship.alias.id);
             for relationship in iter {
-                res = if relationship.from.alias.id == relationship.to.alias.id {
+                res = if relationship.shortest.is_some() {
+                    self.plan_shortest_path(&relationship, Some(res))
+                } else if relationship.var_length.is_some() {
+                    tree!(IR::VarLengthTraverse(relationship.clone()), res)
+                } else if relationship.from.alias.id == relationship.to.alias.id {
                     tree!(
                         IR::ExpandInto(relationship.clone()),
                         tree!(IR::NodeScan(relationship.from.clone()), res)
@@ -166,6 +240,26 @@ impl Planner {
         res
     }
 
+    /// Scans the endpoints of `relationship` not bound yet, then finds the shortest paths between them.
+    fn plan_shortest_path(
+        &mut self,
+        relationship: &Arc<QueryRelationship>,
+        mut res: Option<DynTree<IR>>,
+    ) -> DynTree<IR> {
+        for node in [&relationship.from, &relationship.to] {
+            if self.visited.insert(node.alias.id) {
+                res = Some(match res {
+                    Some(res) => tree!(IR::NodeScan(node.clone()), res),
+                    None => tree!(IR::NodeScan(node.clone())),
+                });
+            }
+        }
+        match res {
+            Some(res) => tree!(IR::ShortestPath(relationship.clone()), res),
+            None => tree!(IR::ShortestPath(relationship.clone())),
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn plan_project(
         &mut self,
@@ -226,6 +320,15 @@ impl Planner {
         for ir in q {
             plans.push(self.plan(ir));
         }
+        let mut res = Self::chain(plans);
+        if write {
+            res = tree!(IR::Commit, res);
+        }
+        res
+    }
+
+    /// Feeds each plan with the rows of the plan before it.
+    fn chain(plans: Vec<DynTree<IR>>) -> DynTree<IR> {
         let mut iter = plans.into_iter().rev();
         let mut res = iter.next().unwrap();
         let mut idx = res.root().idx();
@@ -250,9 +353,6 @@ impl Planner {
                 idx = res.node(&idx).child(0).idx();
             }
         }
-        if write {
-            res = tree!(IR::Commit, res);
-        }
         res
     }
 
@@ -295,6 +395,24 @@ impl Planner {
             QueryIR::Delete(exprs, is_detach) => tree!(IR::Delete(exprs, is_detach)),
             QueryIR::Set(items) => tree!(IR::Set(items)),
             QueryIR::Remove(items) => tree!(IR::Remove(items)),
+            QueryIR::Foreach(list, var, clauses) => {
+                let mut plans = vec![tree!(IR::Argument)];
+                for clause in clauses {
+                    plans.push(self.plan(clause));
+                }
+                tree!(IR::Foreach(list, var), Self::chain(plans))
+            }
+            QueryIR::CallSubquery(query, batch_size) => {
+                // the changes of the subquery are committed by the outer query
+                let QueryIR::Query(clauses, _) = *query else {
+                    unreachable!();
+                };
+                let mut plans = vec![tree!(IR::Argument)];
+                for clause in clauses {
+                    plans.push(self.plan(clause));
+                }
+                tree!(IR::Apply(batch_size), Self::chain(plans))
+            }
             QueryIR::LoadCsv {
                 file_path,
                 headers,
@@ -332,6 +450,14 @@ impl Planner {
                 tree!(IR::DropIndex { label, attrs })
             }
             QueryIR::Query(q, write) => self.plan_query(q, write),
+            QueryIR::Union(queries, all) => {
+                let plans = queries
+                    .into_iter()
+                    .map(|q| self.plan(q))
+                    .collect::<Vec<_>>();
+                let res = tree!(IR::Union; plans);
+                if all { res } else { tree!(IR::Distinct, res) }
+            }
         }
     }
 }
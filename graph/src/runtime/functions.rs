@@ -13,8 +13,7 @@ use rand::Rng;
 use std::{
     collections::HashMap,
     fmt::{Debug, Display},
-    rc::Rc,
-    sync::OnceLock,
+    sync::{Arc, OnceLock},
 };
 
 type RuntimeFn = fn(&Runtime, Vec<Value>) -> Result<Value, String>;
@@ -23,7 +22,7 @@ pub enum FnType {
     Function,
     Internal,
     Procedure,
-    Aggregation(Value, Option<Box<dyn Fn(Value) -> Value>>),
+    Aggregation(Value, Option<Box<dyn Fn(Value) -> Value + Send + Sync>>),
 }
 
 #[cfg_attr(tarpaulin, skip)]
@@ -216,13 +215,9 @@ impl GraphFn {
 
 #[derive(Default, Debug)]
 pub struct Functions {
-    functions: HashMap<String, Rc<GraphFn>>,
+    functions: HashMap<String, Arc<GraphFn>>,
 }
 
-#[allow(clippy::non_send_fields_in_send_ty)]
-unsafe impl Send for Functions {}
-unsafe impl Sync for Functions {}
-
 impl Functions {
     #[must_use]
     pub fn new() -> Self {
@@ -242,7 +237,7 @@ impl Functions {
             !self.functions.contains_key(&lower_name),
             "Function '{name}' already exists"
         );
-        let graph_fn = Rc::new(GraphFn::new(
+        let graph_fn = Arc::new(GraphFn::new(
             name,
             func,
             write,
@@ -265,7 +260,7 @@ impl Functions {
             !self.functions.contains_key(&name),
             "Function '{name}' already exists"
         );
-        let graph_fn = Rc::new(GraphFn::new(
+        let graph_fn = Arc::new(GraphFn::new(
             &name,
             func,
             write,
@@ -279,7 +274,7 @@ impl Functions {
         &self,
         name: &str,
         fn_type: &FnType,
-    ) -> Result<Rc<GraphFn>, String> {
+    ) -> Result<Arc<GraphFn>, String> {
         self.functions
             .get(name.to_lowercase().as_str())
             .and_then(|graph_fn| {
@@ -1230,11 +1225,11 @@ fn value_to_float(
     }
 }
 
-fn value_string(value: &Value) -> Result<Rc<String>, String> {
+fn value_string(value: &Value) -> Result<Arc<String>, String> {
     match value {
         Value::String(s) => Ok(s.clone()),
-        Value::Int(i) => Ok(Rc::new(i.to_string())),
-        Value::Bool(b) => Ok(Rc::new(String::from(if *b { "true" } else { "false" }))),
+        Value::Int(i) => Ok(Arc::new(i.to_string())),
+        Value::Bool(b) => Ok(Arc::new(String::from(if *b { "true" } else { "false" }))),
 
         _ => unreachable!(),
     }
@@ -1259,7 +1254,7 @@ fn size(
     match args.into_iter().next() {
         Some(Value::String(s)) => Ok(Value::Int(s.len() as i64)),
         Some(Value::List(v)) => Ok(Value::Int(v.len() as i64)),
-        Some(Value::Rc(v)) => {
+        Some(Value::Arc(v)) => {
             if let Value::List(v) = &*v {
                 Ok(Value::Int(v.len() as i64))
             } else {
@@ -1329,7 +1324,7 @@ fn reverse(
             v.reverse();
             Ok(Value::List(v))
         }
-        Some(Value::String(s)) => Ok(Value::String(Rc::new(s.chars().rev().collect()))),
+        Some(Value::String(s)) => Ok(Value::String(Arc::new(s.chars().rev().collect()))),
         Some(Value::Null) => Ok(Value::Null),
 
         _ => unreachable!(),
@@ -1350,11 +1345,11 @@ fn substring(
                 return Err("start must be a non-negative integer".into());
             }
             if start >= s.len() as _ {
-                return Ok(Value::String(Rc::new(String::new())));
+                return Ok(Value::String(Arc::new(String::new())));
             }
             let start = start as usize;
 
-            Ok(Value::String(Rc::new(String::from(&s[start..]))))
+            Ok(Value::String(Arc::new(String::from(&s[start..]))))
         }
 
         // Three-argument version: (string, start, length)
@@ -1365,7 +1360,7 @@ fn substring(
 
             let start = start as usize;
             if start >= s.len() {
-                return Ok(Value::String(Rc::new(String::new())));
+                return Ok(Value::String(Arc::new(String::new())));
             }
 
             if length < 0 {
@@ -1375,7 +1370,7 @@ fn substring(
             let length = length as usize;
 
             let end = start.saturating_add(length).min(s.len());
-            Ok(Value::String(Rc::new(String::from(&s[start..end]))))
+            Ok(Value::String(Arc::new(String::from(&s[start..end]))))
         }
 
         _ => unreachable!(),
@@ -1390,18 +1385,18 @@ fn split(
     match (iter.next(), iter.next()) {
         (Some(Value::String(string)), Some(Value::String(delimiter))) => {
             if string.is_empty() {
-                Ok(Value::List(vec![Value::String(Rc::new(String::new()))]))
+                Ok(Value::List(vec![Value::String(Arc::new(String::new()))]))
             } else if delimiter.is_empty() {
                 // split string to characters
                 let parts = string
                     .chars()
-                    .map(|c| Value::String(Rc::new(String::from(c))))
+                    .map(|c| Value::String(Arc::new(String::from(c))))
                     .collect();
                 Ok(Value::List(parts))
             } else {
                 let parts = string
                     .split(delimiter.as_str())
-                    .map(|s| Value::String(Rc::new(String::from(s))))
+                    .map(|s| Value::String(Arc::new(String::from(s))))
                     .collect();
                 Ok(Value::List(parts))
             }
@@ -1417,7 +1412,7 @@ fn string_to_lower(
     args: Vec<Value>,
 ) -> Result<Value, String> {
     match args.into_iter().next() {
-        Some(Value::String(s)) => Ok(Value::String(Rc::new(s.to_lowercase()))),
+        Some(Value::String(s)) => Ok(Value::String(Arc::new(s.to_lowercase()))),
         Some(Value::Null) => Ok(Value::Null),
 
         _ => unreachable!(),
@@ -1429,7 +1424,7 @@ fn string_to_upper(
     args: Vec<Value>,
 ) -> Result<Value, String> {
     match args.into_iter().next() {
-        Some(Value::String(s)) => Ok(Value::String(Rc::new(s.to_uppercase()))),
+        Some(Value::String(s)) => Ok(Value::String(Arc::new(s.to_uppercase()))),
         Some(Value::Null) => Ok(Value::Null),
 
         _ => unreachable!(),
@@ -1443,7 +1438,7 @@ fn string_replace(
     let mut iter = args.into_iter();
     match (iter.next(), iter.next(), iter.next()) {
         (Some(Value::String(s)), Some(Value::String(search)), Some(Value::String(replacement))) => {
-            Ok(Value::String(Rc::new(
+            Ok(Value::String(Arc::new(
                 s.replace(search.as_str(), replacement.as_str()),
             )))
         }
@@ -1465,7 +1460,9 @@ fn string_left(
             if n < 0 {
                 Err(String::from("length must be a non-negative integer"))
             } else {
-                Ok(Value::String(Rc::new(s.chars().take(n as usize).collect())))
+                Ok(Value::String(Arc::new(
+                    s.chars().take(n as usize).collect(),
+                )))
             }
         }
         (Some(Value::Null), _) => Ok(Value::Null),
@@ -1480,7 +1477,7 @@ fn string_ltrim(
     args: Vec<Value>,
 ) -> Result<Value, String> {
     match args.into_iter().next() {
-        Some(Value::String(s)) => Ok(Value::String(Rc::new(String::from(
+        Some(Value::String(s)) => Ok(Value::String(Arc::new(String::from(
             s.trim_start_matches(' '),
         )))),
         Some(Value::Null) => Ok(Value::Null),
@@ -1500,7 +1497,7 @@ fn string_right(
                 Err(String::from("length must be a non-negative integer"))
             } else {
                 let start = s.len().saturating_sub(n as usize);
-                Ok(Value::String(Rc::new(s.chars().skip(start).collect())))
+                Ok(Value::String(Arc::new(s.chars().skip(start).collect())))
             }
         }
         (Some(Value::Null), _) => Ok(Value::Null),
@@ -1514,7 +1511,7 @@ fn string_join(
     _: &Runtime,
     args: Vec<Value>,
 ) -> Result<Value, String> {
-    fn to_string_vec(vec: &[Value]) -> Result<Vec<Rc<String>>, String> {
+    fn to_string_vec(vec: &[Value]) -> Result<Vec<Arc<String>>, String> {
         vec.iter()
             .map(|item| {
                 if let Value::String(s) = item {
@@ -1534,7 +1531,7 @@ fn string_join(
         (Value::List(vec), Some(Value::String(s))) => {
             let result = to_string_vec(&vec);
             result.map(|strings| {
-                Value::String(Rc::new(
+                Value::String(Arc::new(
                     strings.iter().map(|label| label.as_str()).join(s.as_str()),
                 ))
             })
@@ -1542,7 +1539,9 @@ fn string_join(
         (Value::List(vec), None) => {
             let result = to_string_vec(&vec);
             result.map(|strings| {
-                Value::String(Rc::new(strings.iter().map(|label| label.as_str()).join("")))
+                Value::String(Arc::new(
+                    strings.iter().map(|label| label.as_str()).join(""),
+                ))
             })
         }
         (Value::Null, _) => Ok(Value::Null),
@@ -1564,7 +1563,7 @@ fn string_match_reg_ex(
                     for caps in re.captures_iter(text.as_str()) {
                         for i in 0..caps.len() {
                             if let Some(m) = caps.get(i) {
-                                all_matches.push(Value::String(Rc::new(String::from(m.as_str()))));
+                                all_matches.push(Value::String(Arc::new(String::from(m.as_str()))));
                             }
                         }
                     }
@@ -1594,7 +1593,7 @@ fn string_replace_reg_ex(
                 let replaced_text = re
                     .replace_all(text.as_str(), replacement.as_str())
                     .into_owned();
-                Ok(Value::String(Rc::new(replaced_text)))
+                Ok(Value::String(Arc::new(replaced_text)))
             }
             Err(e) => Err(format!("Invalid regex, {e}")),
         },
@@ -1803,14 +1802,14 @@ fn range(
                 return Err(String::from("Range too large"));
             }
             if step > 0 {
-                return Ok(Value::Rc(Rc::new(Value::List(
+                return Ok(Value::Arc(Arc::new(Value::List(
                     (start..=end)
                         .step_by(step as usize)
                         .map(Value::Int)
                         .collect(),
                 ))));
             }
-            Ok(Value::Rc(Rc::new(Value::List(
+            Ok(Value::Arc(Arc::new(Value::List(
                 (end..=start)
                     .rev()
                     .step_by((-step) as usize)
@@ -2,6 +2,7 @@ use std::{
     collections::HashMap,
     hash::{DefaultHasher, Hasher},
     iter::once,
+    time::{Duration, Instant},
 };
 
 pub struct AggregateIter<I, K, V, F, G>
@@ -386,3 +387,124 @@ where
         }
     }
 }
+
+pub struct ProfileIter<I, F>
+where
+    I: Iterator,
+    F: FnMut(bool, Duration),
+{
+    iter: I,
+    func: F,
+}
+
+impl<I, F> Iterator for ProfileIter<I, F>
+where
+    I: Iterator,
+    F: FnMut(bool, Duration),
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let item = self.iter.next();
+        (self.func)(item.is_some(), start.elapsed());
+        item
+    }
+}
+
+pub trait Profile
+where
+    Self: Iterator,
+{
+    /// Calls `func` after every `next` with whether an item was produced
+    /// and the time spent producing it, including the time spent in the source iterator.
+    fn profile<F>(
+        self,
+        func: F,
+    ) -> ProfileIter<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(bool, Duration);
+}
+
+impl<I> Profile for I
+where
+    I: Iterator,
+{
+    fn profile<F>(
+        self,
+        func: F,
+    ) -> ProfileIter<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(bool, Duration),
+    {
+        ProfileIter { iter: self, func }
+    }
+}
+
+pub struct GuardIter<I, F, T, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+    F: FnMut() -> Result<(), E>,
+{
+    iter: I,
+    check: F,
+    is_error: bool,
+}
+
+impl<I, F, T, E> Iterator for GuardIter<I, F, T, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+    F: FnMut() -> Result<(), E>,
+{
+    type Item = Result<T, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_error {
+            return None; // If the check already failed, stop iterating
+        }
+
+        if let Err(err) = (self.check)() {
+            self.is_error = true;
+            return Some(Err(err));
+        }
+
+        self.iter.next()
+    }
+}
+
+pub trait Guard<T, E>
+where
+    Self: Iterator<Item = Result<T, E>>,
+{
+    /// Calls `check` before pulling every item, the first failed check
+    /// is yielded as an error and ends the iteration.
+    fn guard<F>(
+        self,
+        check: F,
+    ) -> GuardIter<Self, F, T, E>
+    where
+        Self: Sized,
+        F: FnMut() -> Result<(), E>;
+}
+
+impl<I, T, E> Guard<T, E> for I
+where
+    I: Iterator<Item = Result<T, E>>,
+{
+    fn guard<F>(
+        self,
+        check: F,
+    ) -> GuardIter<Self, F, T, E>
+    where
+        Self: Sized,
+        F: FnMut() -> Result<(), E>,
+    {
+        GuardIter {
+            iter: self,
+            check,
+            is_error: false,
+        }
+    }
+}
@@ -1,21 +1,26 @@
-use std::{
-    cell::RefCell,
-    collections::{HashMap, HashSet},
-    rc::Rc,
-};
+#![allow(clippy::cast_possible_truncation)]
+
+use std::{cell::RefCell, collections::HashSet, sync::Arc};
 
 use ordermap::{OrderMap, OrderSet};
 use roaring::RoaringTreemap;
 
 use crate::{
-    graph::graph::{Graph, NodeId, RelationshipId},
+    graph::{
+        changes::Change,
+        encoding::{
+            Decoder, Encoder, SliceDecoder, decode_names, decode_treemap, decode_value,
+            encode_names, encode_treemap, encode_value, preallocated,
+        },
+        graph::{Graph, GraphCell, NodeId, RelationshipId},
+    },
     runtime::{runtime::QueryStatistics, value::Value},
 };
 
 pub struct PendingRelationship {
     pub from: NodeId,
     pub to: NodeId,
-    pub type_name: Rc<String>,
+    pub type_name: Arc<String>,
 }
 
 impl PendingRelationship {
@@ -23,7 +28,7 @@ impl PendingRelationship {
     pub const fn new(
         from: NodeId,
         to: NodeId,
-        type_name: Rc<String>,
+        type_name: Arc<String>,
     ) -> Self {
         Self {
             from,
@@ -33,16 +38,21 @@ impl PendingRelationship {
     }
 }
 
+/// The changes of a write query, applied to the graph by [`Pending::commit`].
+///
+/// The changes are kept in the order they were made so that replaying
+/// their encoding, see [`Pending::encode`], creates the same labels,
+/// types and attributes in the same order.
 #[derive(Default)]
 pub struct Pending {
     created_nodes: RoaringTreemap,
-    created_relationships: HashMap<RelationshipId, PendingRelationship>,
+    created_relationships: OrderMap<RelationshipId, PendingRelationship>,
     deleted_nodes: RoaringTreemap,
     deleted_relationships: HashSet<(RelationshipId, NodeId, NodeId)>,
-    set_nodes_attrs: HashMap<NodeId, OrderMap<Rc<String>, Value>>,
-    set_relationships_attrs: HashMap<RelationshipId, OrderMap<Rc<String>, Value>>,
-    set_node_labels: HashMap<NodeId, OrderSet<Rc<String>>>,
-    remove_node_labels: HashMap<NodeId, OrderSet<Rc<String>>>,
+    set_nodes_attrs: OrderMap<NodeId, OrderMap<Arc<String>, Value>>,
+    set_relationships_attrs: OrderMap<RelationshipId, OrderMap<Arc<String>, Value>>,
+    set_node_labels: OrderMap<NodeId, OrderSet<Arc<String>>>,
+    remove_node_labels: OrderMap<NodeId, OrderSet<Arc<String>>>,
 }
 
 impl Pending {
@@ -58,7 +68,7 @@ impl Pending {
     pub fn set_node_attributes(
         &mut self,
         id: NodeId,
-        attrs: OrderMap<Rc<String>, Value>,
+        attrs: OrderMap<Arc<String>, Value>,
     ) {
         self.set_nodes_attrs.insert(id, attrs);
     }
@@ -66,7 +76,7 @@ impl Pending {
     pub fn set_node_attribute(
         &mut self,
         id: NodeId,
-        key: Rc<String>,
+        key: Arc<String>,
         value: Value,
     ) {
         self.set_nodes_attrs
@@ -79,7 +89,7 @@ impl Pending {
     pub fn get_node_attribute(
         &self,
         id: NodeId,
-        key: &Rc<String>,
+        key: &Arc<String>,
     ) -> Option<&Value> {
         self.set_nodes_attrs
             .get(&id)
@@ -89,7 +99,7 @@ impl Pending {
     pub fn update_node_attrs(
         &self,
         id: NodeId,
-        attrs: &mut OrderMap<Rc<String>, Value>,
+        attrs: &mut OrderMap<Arc<String>, Value>,
     ) {
         if let Some(added) = self.set_nodes_attrs.get(&id) {
             for (key, value) in added {
@@ -105,7 +115,7 @@ impl Pending {
     pub fn set_node_labels(
         &mut self,
         id: NodeId,
-        labels: OrderSet<Rc<String>>,
+        labels: OrderSet<Arc<String>>,
     ) {
         self.set_node_labels.insert(id, labels);
     }
@@ -113,7 +123,7 @@ impl Pending {
     pub fn remove_node_labels(
         &mut self,
         id: NodeId,
-        labels: OrderSet<Rc<String>>,
+        labels: OrderSet<Arc<String>>,
     ) {
         self.remove_node_labels.insert(id, labels);
     }
@@ -121,7 +131,7 @@ impl Pending {
     pub fn update_node_labels(
         &self,
         id: NodeId,
-        labels: &mut OrderSet<Rc<String>>,
+        labels: &mut OrderSet<Arc<String>>,
     ) {
         if let Some(added) = self.set_node_labels.get(&id) {
             labels.extend(added.iter().cloned());
@@ -145,7 +155,7 @@ impl Pending {
         id: RelationshipId,
         from: NodeId,
         to: NodeId,
-        type_name: Rc<String>,
+        type_name: Arc<String>,
     ) {
         self.created_relationships
             .insert(id, PendingRelationship::new(from, to, type_name));
@@ -154,7 +164,7 @@ impl Pending {
     pub fn set_relationship_attributes(
         &mut self,
         id: RelationshipId,
-        attrs: OrderMap<Rc<String>, Value>,
+        attrs: OrderMap<Arc<String>, Value>,
     ) {
         self.set_relationships_attrs.insert(id, attrs);
     }
@@ -162,7 +172,7 @@ impl Pending {
     pub fn set_relationship_attribute(
         &mut self,
         id: RelationshipId,
-        key: Rc<String>,
+        key: Arc<String>,
         value: Value,
     ) {
         self.set_relationships_attrs
@@ -175,7 +185,7 @@ impl Pending {
     pub fn get_relationship_attribute(
         &self,
         id: RelationshipId,
-        key: &Rc<String>,
+        key: &Arc<String>,
     ) -> Option<&Value> {
         self.set_relationships_attrs
             .get(&id)
@@ -185,7 +195,7 @@ impl Pending {
     pub fn update_relationship_attrs(
         &self,
         id: RelationshipId,
-        attrs: &mut OrderMap<Rc<String>, Value>,
+        attrs: &mut OrderMap<Arc<String>, Value>,
     ) {
         if let Some(added) = self.set_relationships_attrs.get(&id) {
             for (key, value) in added {
@@ -211,7 +221,7 @@ impl Pending {
     pub fn get_relationship_type(
         &self,
         id: RelationshipId,
-    ) -> Option<Rc<String>> {
+    ) -> Option<Arc<String>> {
         self.created_relationships
             .get(&id)
             .map(|r| r.type_name.clone())
@@ -219,41 +229,90 @@ impl Pending {
 
     pub fn commit(
         &mut self,
-        g: &RefCell<Graph>,
+        g: &GraphCell,
         stats: &RefCell<QueryStatistics>,
     ) {
+        // the changes to log when change capture is enabled on the graph
+        let mut changes = g.borrow().change_log().is_some().then(Vec::new);
         if !self.created_nodes.is_empty() {
             stats.borrow_mut().nodes_created += self.created_nodes.len();
             g.borrow_mut().create_nodes(&self.created_nodes);
+            if let Some(changes) = &mut changes {
+                changes.extend(
+                    self.created_nodes
+                        .iter()
+                        .map(|id| Change::NodeCreated(NodeId::from(id))),
+                );
+            }
             self.created_nodes.clear();
         }
         if !self.created_relationships.is_empty() {
             stats.borrow_mut().relationships_created += self.created_relationships.len();
             g.borrow_mut()
                 .create_relationships(&self.created_relationships);
+            if let Some(changes) = &mut changes {
+                changes.extend(self.created_relationships.iter().map(|(id, relationship)| {
+                    Change::RelationshipCreated {
+                        id: *id,
+                        type_name: relationship.type_name.clone(),
+                        from: relationship.from,
+                        to: relationship.to,
+                    }
+                }));
+            }
             self.created_relationships.clear();
         }
         if !self.deleted_relationships.is_empty() {
             stats.borrow_mut().relationships_deleted += self.deleted_relationships.len();
             g.borrow_mut()
                 .delete_relationships(self.deleted_relationships.clone());
+            if let Some(changes) = &mut changes {
+                changes.extend(self.deleted_relationships.iter().map(|(id, from, to)| {
+                    Change::RelationshipDeleted {
+                        id: *id,
+                        from: *from,
+                        to: *to,
+                    }
+                }));
+            }
             self.deleted_relationships.clear();
         }
         if !self.deleted_nodes.is_empty() {
             stats.borrow_mut().nodes_deleted += self.deleted_nodes.len();
             for id in &self.deleted_nodes {
                 g.borrow_mut().delete_node(NodeId::from(id));
+                if let Some(changes) = &mut changes {
+                    changes.push(Change::NodeDeleted(NodeId::from(id)));
+                }
             }
             self.deleted_nodes.clear();
         }
         if !self.set_node_labels.is_empty() {
             for (id, labels) in &self.set_node_labels {
+                if let Some(changes) = &mut changes {
+                    let current = g.borrow().get_node_labels(*id).collect::<Vec<_>>();
+                    changes.extend(
+                        labels
+                            .iter()
+                            .filter(|label| !current.contains(label))
+                            .map(|label| Change::LabelAdded(*id, label.clone())),
+                    );
+                }
                 g.borrow_mut().set_node_labels(*id, labels);
             }
             self.set_node_labels.clear();
         }
         if !self.remove_node_labels.is_empty() {
             for (id, labels) in &self.remove_node_labels {
+                if let Some(changes) = &mut changes {
+                    let current = g.borrow().get_node_labels(*id).collect::<Vec<_>>();
+                    changes.extend(
+                        labels
+                            .iter()
+                            .filter(|label| current.contains(label))
+                            .map(|label| Change::LabelRemoved(*id, label.clone())),
+                    );
+                }
                 g.borrow_mut().remove_node_labels(*id, labels);
             }
             self.remove_node_labels.clear();
@@ -271,6 +330,17 @@ impl Pending {
             for (id, attrs) in &self.set_nodes_attrs {
                 for (key, value) in attrs {
                     let attr_id = g.borrow_mut().get_or_add_node_attribute_id(key);
+                    if let Some(changes) = &mut changes {
+                        let old = g.borrow().get_node_attribute(*id, attr_id);
+                        if old.is_some() || !matches!(value, Value::Null) {
+                            changes.push(Change::NodePropertySet {
+                                id: *id,
+                                key: key.clone(),
+                                old: old.unwrap_or(Value::Null),
+                                new: value.clone(),
+                            });
+                        }
+                    }
                     if g.borrow_mut()
                         .set_node_attribute(*id, attr_id, value.clone())
                     {
@@ -293,6 +363,17 @@ impl Pending {
             for (id, attrs) in &self.set_relationships_attrs {
                 for (key, value) in attrs {
                     let attr_id = g.borrow_mut().get_or_add_relationship_attribute_id(key);
+                    if let Some(changes) = &mut changes {
+                        let old = g.borrow().get_relationship_attribute(*id, attr_id);
+                        if old.is_some() || !matches!(value, Value::Null) {
+                            changes.push(Change::RelationshipPropertySet {
+                                id: *id,
+                                key: key.clone(),
+                                old: old.unwrap_or(Value::Null),
+                                new: value.clone(),
+                            });
+                        }
+                    }
                     if g.borrow_mut()
                         .set_relationship_attribute(*id, attr_id, value.clone())
                     {
@@ -302,5 +383,214 @@ impl Pending {
             }
             self.set_relationships_attrs.clear();
         }
+        g.borrow_mut().flush();
+        if let Some(changes) = changes {
+            g.borrow_mut().log_changes(changes);
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.created_nodes.is_empty()
+            && self.created_relationships.is_empty()
+            && self.deleted_nodes.is_empty()
+            && self.deleted_relationships.is_empty()
+            && self.set_nodes_attrs.is_empty()
+            && self.set_relationships_attrs.is_empty()
+            && self.set_node_labels.is_empty()
+            && self.remove_node_labels.is_empty()
+    }
+
+    /// Encodes the changes with the ids picked for the created entities,
+    /// to be replayed on another copy of the graph by [`Pending::replay`].
+    pub fn encode(
+        &self,
+        enc: &mut impl Encoder,
+    ) {
+        encode_treemap(enc, &self.created_nodes);
+        enc.write_u64(self.created_relationships.len() as u64);
+        for (id, relationship) in &self.created_relationships {
+            enc.write_u64(u64::from(*id));
+            enc.write_u64(u64::from(relationship.from));
+            enc.write_u64(u64::from(relationship.to));
+            enc.write_str(&relationship.type_name);
+        }
+        enc.write_u64(self.deleted_relationships.len() as u64);
+        for (id, from, to) in &self.deleted_relationships {
+            enc.write_u64(u64::from(*id));
+            enc.write_u64(u64::from(*from));
+            enc.write_u64(u64::from(*to));
+        }
+        encode_treemap(enc, &self.deleted_nodes);
+        for labels in [&self.set_node_labels, &self.remove_node_labels] {
+            enc.write_u64(labels.len() as u64);
+            for (id, labels) in labels {
+                enc.write_u64(u64::from(*id));
+                encode_names(enc, &labels.iter().cloned().collect::<Vec<_>>());
+            }
+        }
+        enc.write_u64(self.set_nodes_attrs.len() as u64);
+        for (id, attrs) in &self.set_nodes_attrs {
+            enc.write_u64(u64::from(*id));
+            encode_attrs(enc, attrs);
+        }
+        enc.write_u64(self.set_relationships_attrs.len() as u64);
+        for (id, attrs) in &self.set_relationships_attrs {
+            enc.write_u64(u64::from(*id));
+            encode_attrs(enc, attrs);
+        }
+    }
+
+    /// Decodes changes written by [`Pending::encode`].
+    pub fn decode(dec: &mut impl Decoder) -> Result<Self, String> {
+        let mut pending = Self {
+            created_nodes: decode_treemap(dec)?,
+            ..Self::default()
+        };
+        for _ in 0..dec.read_u64()? {
+            let id = RelationshipId::from(dec.read_u64()?);
+            let from = NodeId::from(dec.read_u64()?);
+            let to = NodeId::from(dec.read_u64()?);
+            let type_name = Arc::new(dec.read_string()?);
+            pending.created_relationship(id, from, to, type_name);
+        }
+        for _ in 0..dec.read_u64()? {
+            let id = RelationshipId::from(dec.read_u64()?);
+            let from = NodeId::from(dec.read_u64()?);
+            let to = NodeId::from(dec.read_u64()?);
+            pending.deleted_relationship(id, from, to);
+        }
+        pending.deleted_nodes = decode_treemap(dec)?;
+        for _ in 0..dec.read_u64()? {
+            let id = NodeId::from(dec.read_u64()?);
+            pending.set_node_labels(id, decode_names(dec)?.into_iter().collect());
+        }
+        for _ in 0..dec.read_u64()? {
+            let id = NodeId::from(dec.read_u64()?);
+            pending.remove_node_labels(id, decode_names(dec)?.into_iter().collect());
+        }
+        for _ in 0..dec.read_u64()? {
+            let id = NodeId::from(dec.read_u64()?);
+            pending.set_node_attributes(id, decode_attrs(dec)?);
+        }
+        for _ in 0..dec.read_u64()? {
+            let id = RelationshipId::from(dec.read_u64()?);
+            pending.set_relationship_attributes(id, decode_attrs(dec)?);
+        }
+        Ok(pending)
+    }
+
+    /// Commits the changes encoded in `buf` by one or more [`Pending::encode`],
+    /// creating the entities with the ids picked when the changes were made.
+    pub fn replay(
+        g: &GraphCell,
+        buf: &[u8],
+    ) -> Result<QueryStatistics, String> {
+        let stats = RefCell::new(QueryStatistics::default());
+        let mut dec = SliceDecoder::new(buf);
+        while !dec.is_empty() {
+            let mut pending = Self::decode(&mut dec)?;
+            pending.validate(&g.borrow())?;
+            g.borrow_mut().reserve_replicated(
+                pending.created_nodes.len(),
+                pending.created_relationships.len() as u64,
+            );
+            pending.commit(g, &stats);
+        }
+        Ok(stats.into_inner())
+    }
+
+    /// Checks that decoded changes only create entities with the ids `g` would pick for them
+    /// and only change its entities or the created ones, so committing them keeps `g` consistent.
+    fn validate(
+        &self,
+        g: &Graph,
+    ) -> Result<(), String> {
+        let invalid =
+            |kind: &str, id: u64| Err(format!("Invalid {kind} id {id} in replicated changes"));
+        let nodes = self.created_nodes.len() as usize;
+        if !self
+            .created_nodes
+            .iter()
+            .map(NodeId::from)
+            .eq(g.next_node_ids().take(nodes))
+        {
+            return Err(String::from(
+                "Unexpected node ids created by replicated changes",
+            ));
+        }
+        let relationships = self.created_relationships.len();
+        if !self
+            .created_relationships
+            .keys()
+            .copied()
+            .eq(g.next_relationship_ids().take(relationships))
+        {
+            return Err(String::from(
+                "Unexpected relationship ids created by replicated changes",
+            ));
+        }
+        let is_node = |id: NodeId| g.contains_node(id) || self.created_nodes.contains(id.into());
+        let is_relationship = |id: RelationshipId| {
+            g.contains_relationship(id) || self.created_relationships.contains_key(&id)
+        };
+        for relationship in self.created_relationships.values() {
+            for id in [relationship.from, relationship.to] {
+                if !is_node(id) {
+                    return invalid("node", id.into());
+                }
+            }
+        }
+        for (id, from, to) in &self.deleted_relationships {
+            if !g.contains_relationship(*id)
+                || !g.contains_node(*from)
+                || !g.contains_node(*to)
+                || !g.get_src_dest_relationships(*from, *to, &[]).contains(id)
+            {
+                return invalid("relationship", (*id).into());
+            }
+        }
+        for id in &self.deleted_nodes {
+            if !is_node(NodeId::from(id)) {
+                return invalid("node", id);
+            }
+        }
+        for id in self
+            .set_node_labels
+            .keys()
+            .chain(self.remove_node_labels.keys())
+            .chain(self.set_nodes_attrs.keys())
+        {
+            if !is_node(*id) {
+                return invalid("node", (*id).into());
+            }
+        }
+        for id in self.set_relationships_attrs.keys() {
+            if !is_relationship(*id) {
+                return invalid("relationship", (*id).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode_attrs(
+    enc: &mut impl Encoder,
+    attrs: &OrderMap<Arc<String>, Value>,
+) {
+    enc.write_u64(attrs.len() as u64);
+    for (key, value) in attrs {
+        enc.write_str(key);
+        encode_value(enc, value);
+    }
+}
+
+fn decode_attrs(dec: &mut impl Decoder) -> Result<OrderMap<Arc<String>, Value>, String> {
+    let len = dec.read_len(16)?;
+    let mut attrs = OrderMap::with_capacity(preallocated(len));
+    for _ in 0..len {
+        let key = Arc::new(dec.read_string()?);
+        attrs.insert(key, decode_value(dec)?);
     }
+    Ok(attrs)
 }
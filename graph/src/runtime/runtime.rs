@@ -4,12 +4,14 @@
 #![allow(clippy::cast_precision_loss)]
 
 use crate::{
-    ast::{ExprIR, QuantifierType, QueryGraph, QueryNode, QueryRelationship, Variable},
-    graph::graph::{Graph, NodeId, RelationshipId},
-    planner::IR,
+    ast::{
+        ExprIR, QuantifierType, QueryGraph, QueryNode, QueryRelationship, ShortestPaths, Variable,
+    },
+    graph::graph::{Graph, GraphCell, NodeId, RelationshipId},
+    planner::{IR, operator_ids},
     runtime::{
         functions::{FnType, Functions, Type, get_functions},
-        iter::{Aggregate, CondInspectIter, LazyReplace, TryFlatMap, TryMap},
+        iter::{Aggregate, CondInspectIter, Guard, LazyReplace, Profile, TryFlatMap, TryMap},
         pending::Pending,
         value::{CompareValue, Contains, DisjointOrNull, Env, Value, ValuesDeduper},
     },
@@ -18,18 +20,30 @@ use once_cell::unsync::Lazy;
 use ordermap::{OrderMap, OrderSet};
 use orx_tree::{Bfs, Dyn, DynNode, DynTree, NodeIdx, NodeRef};
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     cmp::Ordering,
     collections::HashMap,
     fmt::Debug,
     hash::{DefaultHasher, Hash, Hasher},
-    iter::{empty, once},
+    iter::{empty, once, once_with},
+    panic,
     path::Path,
     rc::Rc,
-    time::Instant,
+    sync::{
+        Arc,
+        atomic::{self, AtomicIsize, AtomicUsize},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use tracing::instrument;
 
+/// The minimum number of entries a scan slice must get to be worth a thread.
+const MIN_PARTITION_ROWS: u64 = 10_000;
+
+/// The most partitions a scan is split in, bounding the threads a query spawns.
+const MAX_PARTITIONS: u64 = 8;
+
 pub struct ResultSummary {
     pub stats: QueryStatistics,
     pub result: Vec<Env>,
@@ -45,22 +59,84 @@ pub struct QueryStatistics {
     pub relationships_deleted: usize,
     pub properties_set: usize,
     pub properties_removed: usize,
+    /// Whether rows were left out of the result by the result set size limit.
+    pub resultset_truncated: bool,
     pub execution_time: f64,
 }
 
+#[derive(Clone, Copy, Default)]
+pub struct OperatorProfile {
+    pub records: u64,
+    pub execution_time: Duration,
+}
+
+/// The number of idle workers of a pool, the budget of the threads
+/// the runtimes splitting their scans in partitions spawn.
+///
+/// The count drops below zero when the pool starts jobs
+/// while partitions still run on the threads they took.
+#[derive(Default)]
+pub struct SpareThreads(AtomicIsize);
+
+impl SpareThreads {
+    /// Makes `count` threads spare, like pool workers becoming idle.
+    pub fn release(
+        &self,
+        count: usize,
+    ) {
+        self.0.fetch_add(count as isize, atomic::Ordering::AcqRel);
+    }
+
+    /// Takes a thread whether one is spare or not, like a pool worker starting a job.
+    pub fn take(&self) {
+        self.0.fetch_sub(1, atomic::Ordering::AcqRel);
+    }
+
+    /// Takes up to `count` spare threads and returns how many were taken.
+    pub fn try_take(
+        &self,
+        count: usize,
+    ) -> usize {
+        let count = count as isize;
+        self.0
+            .fetch_update(
+                atomic::Ordering::AcqRel,
+                atomic::Ordering::Acquire,
+                |spare| (spare > 0).then(|| spare - spare.min(count)),
+            )
+            .map_or(0, |spare| spare.min(count) as usize)
+    }
+}
+
 pub struct Runtime<'a> {
     functions: &'static Functions,
     parameters: HashMap<String, Value>,
-    g: &'a RefCell<Graph>,
+    g: &'a GraphCell,
     write: bool,
     pending: Lazy<RefCell<Pending>>,
     stats: RefCell<QueryStatistics>,
-    plan: Rc<DynTree<IR>>,
+    plan: Arc<DynTree<IR>>,
     value_dedupers: RefCell<HashMap<String, ValuesDeduper>>,
     pub return_names: Vec<Variable>,
     inspect: bool,
     pub record: RefCell<Vec<(NodeIdx<Dyn<IR>>, Result<Env, String>)>>,
+    profile: bool,
+    operator_ids: Vec<NodeIdx<Dyn<IR>>>,
+    pub profile_stats: RefCell<Vec<OperatorProfile>>,
     import_folder: String,
+    deadline: Option<Instant>,
+    committed: Cell<bool>,
+    mem_capacity: Option<usize>,
+    /// Shared with the partitions of the query, see [`Runtime::run_partitioned`].
+    mem_usage: Arc<AtomicUsize>,
+    resultset_size: Option<usize>,
+    /// The changes committed by the query, see [`Runtime::take_effects`].
+    effects: RefCell<Vec<u8>>,
+    spare_threads: Option<Arc<SpareThreads>>,
+    /// The scan operator id and the slice of it run by a partition, see [`Runtime::run_partitioned`].
+    partition: Option<(usize, u64, u64)>,
+    /// The rows the innermost running `Foreach` or `Apply` runs its first child with, one per nesting level.
+    arguments: RefCell<Vec<Env>>,
 }
 
 pub trait GetVariables {
@@ -78,7 +154,7 @@ impl GetVariables for DynNode<'_, IR> {
                     id: 0,
                     ty: Type::Any,
                 }),
-                IR::Unwind(_, variable) => vars.push(variable.clone()),
+                IR::Unwind(_, variable) | IR::Foreach(_, variable) => vars.push(variable.clone()),
                 IR::Create(query_graph) | IR::Merge(query_graph) => {
                     for node in query_graph.nodes() {
                         vars.push(node.alias.clone());
@@ -92,10 +168,13 @@ impl GetVariables for DynNode<'_, IR> {
                 }
                 IR::Delete(_, _)
                 | IR::Empty
+                | IR::Argument
+                | IR::Apply(_)
                 | IR::Set(_)
                 | IR::Remove(_)
                 | IR::Filter(_)
                 | IR::CartesianProduct
+                | IR::Union
                 | IR::Sort(_)
                 | IR::Skip(_)
                 | IR::Limit(_)
@@ -107,7 +186,11 @@ impl GetVariables for DynNode<'_, IR> {
                 IR::RelationshipScan(query_relationship) => {
                     vars.push(query_relationship.alias.clone());
                 }
-                IR::ExpandInto(query_relationship) => vars.push(query_relationship.alias.clone()),
+                IR::ExpandInto(query_relationship)
+                | IR::VarLengthTraverse(query_relationship)
+                | IR::ShortestPath(query_relationship) => {
+                    vars.push(query_relationship.alias.clone());
+                }
                 IR::PathBuilder(query_paths) => {
                     for path in query_paths {
                         vars.push(path.var.clone());
@@ -145,7 +228,7 @@ impl ReturnNames for DynNode<'_, IR> {
                 id: 0,
                 ty: Type::Any,
             }],
-            IR::Sort(_) | IR::Skip(_) | IR::Limit(_) | IR::Distinct => {
+            IR::Sort(_) | IR::Skip(_) | IR::Limit(_) | IR::Distinct | IR::Union => {
                 self.child(0).get_return_names()
             }
             IR::Aggregate(names, _, _) => names.clone(),
@@ -166,14 +249,17 @@ impl Debug for Env {
 impl<'a> Runtime<'a> {
     #[must_use]
     pub fn new(
-        g: &'a RefCell<Graph>,
+        g: &'a GraphCell,
         parameters: HashMap<String, Value>,
         write: bool,
-        plan: Rc<DynTree<IR>>,
+        plan: Arc<DynTree<IR>>,
         inspect: bool,
+        profile: bool,
         import_folder: String,
     ) -> Self {
         let return_names = plan.root().get_return_names();
+        let operator_ids = operator_ids(&plan);
+        let profile_stats = RefCell::new(vec![OperatorProfile::default(); operator_ids.len()]);
         Self {
             functions: get_functions(),
             parameters,
@@ -186,21 +272,146 @@ impl<'a> Runtime<'a> {
             value_dedupers: RefCell::new(HashMap::new()),
             inspect,
             record: RefCell::new(vec![]),
+            profile,
+            operator_ids,
+            profile_stats,
             import_folder,
+            deadline: None,
+            committed: Cell::new(false),
+            mem_capacity: None,
+            mem_usage: Arc::new(AtomicUsize::new(0)),
+            resultset_size: None,
+            effects: RefCell::new(vec![]),
+            spare_threads: None,
+            partition: None,
+            arguments: RefCell::new(vec![]),
         }
     }
 
+    /// Aborts the query with an error once `timeout` has passed since now.
+    ///
+    /// The deadline is no longer enforced after a write query started committing
+    /// so its changes are either fully applied or not applied at all.
+    #[must_use]
+    pub fn with_timeout(
+        mut self,
+        timeout: Duration,
+    ) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Aborts the query with an error once the memory held by buffering operators
+    /// and result rows exceeds `capacity` bytes.
+    #[must_use]
+    pub const fn with_mem_capacity(
+        mut self,
+        capacity: usize,
+    ) -> Self {
+        self.mem_capacity = Some(capacity);
+        self
+    }
+
+    /// Stops pulling rows from the plan once `size` rows were returned.
+    #[must_use]
+    pub const fn with_resultset_size(
+        mut self,
+        size: usize,
+    ) -> Self {
+        self.resultset_size = Some(size);
+        self
+    }
+
+    /// Splits the scans feeding `Aggregate`, `Sort` and `Distinct` over threads spawned
+    /// for as many threads as `threads` has spare when they run, see [`Runtime::run_partitions`].
+    #[must_use]
+    pub fn with_spare_threads(
+        mut self,
+        threads: Arc<SpareThreads>,
+    ) -> Self {
+        self.spare_threads = Some(threads);
+        self
+    }
+
+    const fn has_limits(&self) -> bool {
+        self.deadline.is_some() || self.mem_capacity.is_some()
+    }
+
+    fn check_limits(&self) -> Result<(), String> {
+        if let Some(deadline) = self.deadline
+            && !self.committed.get()
+            && Instant::now() >= deadline
+        {
+            return Err(String::from("Query timed out"));
+        }
+        if let Some(capacity) = self.mem_capacity
+            && self.mem_usage.load(atomic::Ordering::Relaxed) > capacity
+        {
+            return Err(String::from("Query's mem consumption exceeded capacity"));
+        }
+        Ok(())
+    }
+
+    /// Accounts the approximate bytes returned by `size` against the memory capacity,
+    /// `size` is only evaluated when a capacity is set.
+    fn track_memory(
+        &self,
+        size: impl FnOnce() -> usize,
+    ) -> Result<(), String> {
+        if self.mem_capacity.is_none() {
+            return Ok(());
+        }
+        self.mem_usage.fetch_add(size(), atomic::Ordering::Relaxed);
+        self.check_limits()
+    }
+
+    /// Returns the stable id of the plan operator at `idx`, see [`operator_ids`].
+    #[must_use]
+    pub fn operator_id(
+        &self,
+        idx: &NodeIdx<Dyn<IR>>,
+    ) -> usize {
+        self.operator_ids
+            .iter()
+            .position(|id| id == idx)
+            .expect("operator must belong to the plan")
+    }
+
     pub fn query(&mut self) -> Result<ResultSummary, String> {
         let labels_count = self.g.borrow().get_labels_count();
         let start = Instant::now();
         let idx = self.plan.root().idx();
-        let mut result = vec![];
-        for env in self.run(&idx)? {
-            let env = env?;
-            result.push(env);
-        }
+        // one more row tells whether the result was truncated
+        let limit = self
+            .resultset_size
+            .map_or(usize::MAX, |size| size.saturating_add(1));
+        let result = self.run(&idx).and_then(|iter| {
+            iter.take(limit)
+                .map(|env| {
+                    let env = env?;
+                    self.track_memory(|| env.memory_usage())?;
+                    Ok(env)
+                })
+                .collect::<Result<Vec<_>, String>>()
+        });
+        let mut result = match result {
+            Ok(result) => result,
+            Err(err) => {
+                // entities reserved by the pending changes will never be committed
+                if self.write {
+                    self.g.borrow_mut().release_reservations();
+                }
+                return Err(err);
+            }
+        };
         let run_duration = start.elapsed();
 
+        if let Some(size) = self.resultset_size
+            && result.len() > size
+        {
+            result.truncate(size);
+            self.stats.borrow_mut().resultset_truncated = true;
+        }
         self.stats.borrow_mut().labels_added = self.g.borrow().get_labels_count() - labels_count;
         self.stats.borrow_mut().execution_time = run_duration.as_secs_f64() * 1000.0;
         Ok(ResultSummary {
@@ -209,6 +420,43 @@ impl<'a> Runtime<'a> {
         })
     }
 
+    /// Starts the query, its rows are produced as the returned iterator is pulled.
+    ///
+    /// The changes of a write query are committed before it returns,
+    /// so its statistics are complete except for the execution time.
+    /// The rows stop at the result set size, without flagging the result as truncated.
+    pub fn query_lazy(
+        &'a self
+    ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + 'a>, String> {
+        let labels_count = self.g.borrow().get_labels_count();
+        let idx = self.plan.root().idx();
+        let iter = match self.run(&idx) {
+            Ok(iter) => iter,
+            Err(err) => {
+                // entities reserved by the pending changes will never be committed
+                if self.write {
+                    self.g.borrow_mut().release_reservations();
+                }
+                return Err(err);
+            }
+        };
+        self.stats.borrow_mut().labels_added = self.g.borrow().get_labels_count() - labels_count;
+        match self.resultset_size {
+            Some(size) => Ok(Box::new(iter.take(size))),
+            None => Ok(iter),
+        }
+    }
+
+    pub fn take_stats(&self) -> QueryStatistics {
+        self.stats.take()
+    }
+
+    /// Returns the encoded changes committed by the query so far,
+    /// to be replayed on replicas with [`Pending::replay`].
+    pub fn take_effects(&self) -> Vec<u8> {
+        self.effects.take()
+    }
+
     fn set_agg_expr_zero(
         ir: &DynNode<ExprIR>,
         env: &mut Env,
@@ -605,7 +853,7 @@ impl<'a> Runtime<'a> {
 
                     res.push((func.func)(self, args)?);
                 }
-                ExprIR::Map => res.push(Value::Map(Rc::new(
+                ExprIR::Map => res.push(Value::Map(Arc::new(
                     node.children()
                         .map(|child| {
                             Ok((
@@ -784,9 +1032,214 @@ impl<'a> Runtime<'a> {
         }
     }
 
+    fn run(
+        &'a self,
+        idx: &NodeIdx<Dyn<IR>>,
+    ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + 'a>, String> {
+        let iter: Box<dyn Iterator<Item = Result<Env, String>> + 'a> = if self.profile {
+            let id = self.operator_id(idx);
+            let start = Instant::now();
+            let iter = self.run_operator(idx)?;
+            self.profile_stats.borrow_mut()[id].execution_time += start.elapsed();
+            Box::new(iter.profile(move |produced, elapsed| {
+                let mut stats = self.profile_stats.borrow_mut();
+                stats[id].execution_time += elapsed;
+                if produced {
+                    stats[id].records += 1;
+                }
+            }))
+        } else {
+            self.run_operator(idx)?
+        };
+
+        if !self.has_limits() {
+            return Ok(iter);
+        }
+        Ok(Box::new(iter.guard(move || self.check_limits())))
+    }
+
+    /// Runs the operators below an `Aggregate`, `Sort` or `Distinct` at `idx`.
+    ///
+    /// When they are a chain of read-only operators fed by a large enough scan,
+    /// the scan is split in slices each run with the chain by its own runtime on a thread.
+    /// The rows of the slices are merged in order so they come as from the serial plan.
+    fn run_partitioned(
+        &'a self,
+        idx: &NodeIdx<Dyn<IR>>,
+    ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + 'a>, String> {
+        let Some((scan, parts)) = self.partitioned_scan(idx) else {
+            return self.run(idx);
+        };
+        let child = self.operator_id(idx);
+        let scan = self.operator_id(&scan);
+        Ok(Box::new(
+            once_with(move || self.run_partitions(child, scan, parts)).flat_map(|res| match res {
+                Ok(envs) => Box::new(envs.into_iter().map(Ok))
+                    as Box<dyn Iterator<Item = Result<Env, String>>>,
+                Err(err) => Box::new(once(Err(err))),
+            }),
+        ))
+    }
+
+    /// Returns the scan driving the operators at `idx` and the number of slices to split it in,
+    /// or `None` when they have to run serially.
+    fn partitioned_scan(
+        &self,
+        idx: &NodeIdx<Dyn<IR>>,
+    ) -> Option<(NodeIdx<Dyn<IR>>, u64)> {
+        // writes earlier in the query are pending in this runtime only
+        if self.spare_threads.is_none()
+            || self.profile
+            || self.inspect
+            || self.partition.is_some()
+            || self.plan.root().walk::<Bfs>().any(|ir| {
+                matches!(
+                    ir,
+                    IR::Call(..)
+                        | IR::Create(_)
+                        | IR::Merge(_)
+                        | IR::Delete(..)
+                        | IR::Set(_)
+                        | IR::Remove(_)
+                        | IR::Commit
+                        | IR::CreateIndex { .. }
+                        | IR::DropIndex { .. }
+                )
+            })
+        {
+            return None;
+        }
+        let g = self.g.borrow();
+        let mut node = self.plan.node(idx);
+        loop {
+            let rows = match node.data() {
+                IR::NodeScan(_) | IR::RelationshipScan(_) if node.num_children() == 1 => None,
+                IR::NodeScan(node_pattern) if !uses_index(node_pattern, &g) => {
+                    Some(if node_pattern.labels.is_empty() {
+                        g.node_count()
+                    } else {
+                        node_pattern
+                            .labels
+                            .iter()
+                            .map(|label| g.label_node_count(label))
+                            .min()
+                            .unwrap_or(0)
+                    })
+                }
+                IR::RelationshipScan(relationship_pattern) => {
+                    Some(if relationship_pattern.types.is_empty() {
+                        g.relationship_count()
+                    } else {
+                        relationship_pattern
+                            .types
+                            .iter()
+                            .map(|t| g.type_relationship_count(t))
+                            .sum()
+                    })
+                }
+                IR::Filter(_)
+                | IR::Project(_)
+                | IR::PathBuilder(_)
+                | IR::ExpandInto(_)
+                | IR::Unwind(..)
+                    if node.num_children() == 1 =>
+                {
+                    None
+                }
+                _ => return None,
+            };
+            if let Some(rows) = rows {
+                let parts = (rows / MIN_PARTITION_ROWS).min(MAX_PARTITIONS);
+                return (parts > 1).then(|| (node.idx(), parts));
+            }
+            node = node.child(0);
+        }
+    }
+
+    /// Runs the operator `child` in up to `parts` partitions, each scanning a slice of the operator `scan`,
+    /// and concatenates their rows.
+    ///
+    /// The first partition runs on this thread and each other one on a scoped thread spawned for it,
+    /// as long as spare threads can be taken for them, so the threads running queries
+    /// stay within the pool size while the pool is not busy. Each spawn costs a thread creation,
+    /// which the minimum rows of a partition and [`MAX_PARTITIONS`] keep small against the scan.
+    /// The partitions share the deadline and the memory usage of this runtime.
+    fn run_partitions(
+        &self,
+        child: usize,
+        scan: usize,
+        parts: u64,
+    ) -> Result<Vec<Env>, String> {
+        let spare_threads = self
+            .spare_threads
+            .as_ref()
+            .expect("partitioned runtimes have spare threads");
+        let taken = spare_threads.try_take(parts as usize - 1);
+        let parts = taken as u64 + 1;
+        let (g, parameters, plan, import_folder) =
+            (self.g, &self.parameters, &self.plan, &self.import_folder);
+        let (deadline, mem_capacity, mem_usage) =
+            (self.deadline, self.mem_capacity, &self.mem_usage);
+        let run_part = move |part| {
+            let mut runtime = Runtime::new(
+                g,
+                parameters.clone(),
+                false,
+                plan.clone(),
+                false,
+                false,
+                import_folder.clone(),
+            );
+            runtime.deadline = deadline;
+            runtime.mem_capacity = mem_capacity;
+            runtime.mem_usage = mem_usage.clone();
+            runtime.partition = Some((scan, part, parts));
+            let idx = runtime.operator_ids[child].clone();
+            runtime
+                .run(&idx)?
+                .map(|env| {
+                    let env = env?;
+                    runtime.track_memory(|| env.memory_usage())?;
+                    Ok(env)
+                })
+                .collect::<Result<Vec<_>, String>>()
+        };
+        let (first, rest) = thread::scope(|scope| {
+            let handles = (1..parts)
+                .map(|part| scope.spawn(move || run_part(part)))
+                .collect::<Vec<_>>();
+            let first = run_part(0);
+            let rest = handles
+                .into_iter()
+                .map(|handle| handle.join())
+                .collect::<Vec<_>>();
+            (first, rest)
+        });
+        spare_threads.release(taken);
+        let mut envs = first?;
+        for part in rest {
+            match part {
+                Ok(part) => envs.extend(part?),
+                Err(err) => panic::resume_unwind(err),
+            }
+        }
+        Ok(envs)
+    }
+
+    /// Returns the slice of the scan at `idx` run by this runtime.
+    fn scan_part(
+        &self,
+        idx: &NodeIdx<Dyn<IR>>,
+    ) -> (u64, u64) {
+        match self.partition {
+            Some((scan, part, parts)) if self.operator_ids[scan] == *idx => (part, parts),
+            _ => (0, 1),
+        }
+    }
+
     #[allow(clippy::too_many_lines)]
     #[instrument(name = "run", level = "debug", skip(self, idx))]
-    fn run(
+    fn run_operator(
         &'a self,
         idx: &NodeIdx<Dyn<IR>>,
     ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + 'a>, String> {
@@ -870,6 +1323,130 @@ impl<'a> Runtime<'a> {
                         self.record.borrow_mut().push((idx.clone(), res.clone()));
                     }))
             }
+            IR::Foreach(list, var) => {
+                let iter = if let Some(child_idx) = child1_idx {
+                    self.run(&child_idx)?
+                } else {
+                    Box::new(once(Ok(Env::default())))
+                };
+
+                let idx = idx.clone();
+                Ok(iter
+                    .try_map(move |vars| {
+                        let values = match self.run_expr(list, list.root().idx(), &vars, None)? {
+                            Value::List(values) => values,
+                            Value::Null => vec![],
+                            value => {
+                                return Err(format!(
+                                    "Type mismatch: expected List or Null but was {}",
+                                    value.name()
+                                ));
+                            }
+                        };
+                        for value in values {
+                            let mut env = vars.clone();
+                            env.insert(var, value);
+                            // the updating clauses run to completion before the next element
+                            self.arguments.borrow_mut().push(env);
+                            let res = self
+                                .run(child0_idx.as_ref().unwrap())
+                                .and_then(|mut rows| rows.try_for_each(|row| row.map(drop)));
+                            self.arguments.borrow_mut().pop();
+                            res?;
+                        }
+                        Ok(vars)
+                    })
+                    .cond_inspect(self.inspect, move |res| {
+                        self.record.borrow_mut().push((idx.clone(), res.clone()));
+                    }))
+            }
+            IR::Apply(batch_size) => {
+                let iter = if let Some(child_idx) = child1_idx {
+                    self.run(&child_idx)?
+                } else {
+                    Box::new(once(Ok(Env::default())))
+                };
+                let body_idx = child0_idx.unwrap();
+                let returning = !self.plan.node(&body_idx).get_return_names().is_empty();
+                let apply = move |vars: Env| -> Result<Vec<Env>, String> {
+                    self.arguments.borrow_mut().push(vars.clone());
+                    let rows = self
+                        .run(&body_idx)
+                        .and_then(|rows| rows.collect::<Result<Vec<_>, String>>());
+                    self.arguments.borrow_mut().pop();
+                    let rows = rows?;
+                    // a subquery returning nothing keeps the row as is
+                    if !returning {
+                        return Ok(vec![vars]);
+                    }
+                    Ok(rows
+                        .into_iter()
+                        .map(|row| {
+                            let mut vars = vars.clone();
+                            vars.merge(row);
+                            vars
+                        })
+                        .collect())
+                };
+
+                let idx = idx.clone();
+                let Some(batch_size) = batch_size else {
+                    return Ok(iter
+                        .try_flat_map(move |vars| Ok(apply(vars)?.into_iter().map(Ok)))
+                        .cond_inspect(self.inspect, move |res| {
+                            self.record.borrow_mut().push((idx.clone(), res.clone()));
+                        }));
+                };
+                let batch_size = match self.run_expr(
+                    batch_size,
+                    batch_size.root().idx(),
+                    &Env::default(),
+                    None,
+                )? {
+                    Value::Int(size) if size > 0 => usize::try_from(size).unwrap_or(usize::MAX),
+                    _ => {
+                        return Err(String::from(
+                            "Transaction batch size must be a positive integer",
+                        ));
+                    }
+                };
+                // the input is read first so its scans don't see the batches committed meanwhile
+                let input = iter
+                    .map(|env| {
+                        let env = env?;
+                        self.track_memory(|| env.memory_usage())?;
+                        Ok(env)
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                let mut rows = vec![];
+                for batch in input.chunks(batch_size) {
+                    // a timeout fails the query before the next batch,
+                    // like any later error it keeps the batches committed so far
+                    self.check_limits()?;
+                    for vars in batch {
+                        for row in apply(vars.clone())? {
+                            self.track_memory(|| row.memory_usage())?;
+                            rows.push(row);
+                        }
+                    }
+                    self.commit_pending();
+                }
+                Ok(rows
+                    .into_iter()
+                    .map(Ok)
+                    .cond_inspect(self.inspect, move |res| {
+                        self.record.borrow_mut().push((idx.clone(), res.clone()));
+                    }))
+            }
+            IR::Argument => {
+                let env = self
+                    .arguments
+                    .borrow()
+                    .last()
+                    .cloned()
+                    .ok_or("Argument outside of FOREACH or CALL")?;
+                Ok(Box::new(once(Ok(env))))
+            }
             IR::Create(pattern) => {
                 let iter = if let Some(child_idx) = child0_idx {
                     self.run(&child_idx)?
@@ -988,9 +1565,10 @@ impl<'a> Runtime<'a> {
                     Box::new(once(Ok(Env::default())))
                 };
 
+                let (part, parts) = self.scan_part(idx);
                 let idx = idx.clone();
                 Ok(iter
-                    .try_flat_map(move |vars| self.node_scan(node_pattern, vars))
+                    .try_flat_map(move |vars| self.node_scan(node_pattern, vars, part, parts))
                     .cond_inspect(self.inspect, move |res| {
                         self.record.borrow_mut().push((idx.clone(), res.clone()));
                     }))
@@ -1002,9 +1580,12 @@ impl<'a> Runtime<'a> {
                     Box::new(once(Ok(Env::default())))
                 };
 
+                let (part, parts) = self.scan_part(idx);
                 let idx = idx.clone();
                 Ok(iter
-                    .try_flat_map(move |vars| self.relationship_scan(relationship_pattern, vars))
+                    .try_flat_map(move |vars| {
+                        self.relationship_scan(relationship_pattern, vars, part, parts)
+                    })
                     .cond_inspect(self.inspect, move |res| {
                         self.record.borrow_mut().push((idx.clone(), res.clone()));
                     }))
@@ -1023,6 +1604,34 @@ impl<'a> Runtime<'a> {
                         self.record.borrow_mut().push((idx.clone(), res.clone()));
                     }))
             }
+            IR::VarLengthTraverse(relationship_pattern) => {
+                let iter = if let Some(child_idx) = child0_idx {
+                    self.run(&child_idx)?
+                } else {
+                    Box::new(once(Ok(Env::default())))
+                };
+
+                let idx = idx.clone();
+                Ok(iter
+                    .try_flat_map(move |vars| self.var_length_traverse(relationship_pattern, vars))
+                    .cond_inspect(self.inspect, move |res| {
+                        self.record.borrow_mut().push((idx.clone(), res.clone()));
+                    }))
+            }
+            IR::ShortestPath(relationship_pattern) => {
+                let iter = if let Some(child_idx) = child0_idx {
+                    self.run(&child_idx)?
+                } else {
+                    Box::new(once(Ok(Env::default())))
+                };
+
+                let idx = idx.clone();
+                Ok(iter
+                    .try_flat_map(move |vars| self.shortest_paths(relationship_pattern, vars))
+                    .cond_inspect(self.inspect, move |res| {
+                        self.record.borrow_mut().push((idx.clone(), res.clone()));
+                    }))
+            }
             IR::PathBuilder(paths) => {
                 if let Some(child_idx) = child0_idx {
                     let idx = idx.clone();
@@ -1031,16 +1640,24 @@ impl<'a> Runtime<'a> {
                         .try_map(move |mut vars| {
                             let mut paths = paths.clone();
                             for path in &mut paths {
-                                let p = path
-                                    .vars
-                                    .iter()
-                                    .map(|v| {
-                                        vars.get(v).map_or_else(
-                                            || Err(format!("Variable {} not found", v.as_str())),
-                                            Ok,
-                                        )
-                                    })
-                                    .collect::<Result<_, String>>()?;
+                                let mut p = vec![];
+                                let mut iter = path.vars.iter();
+                                while let Some(v) = iter.next() {
+                                    match vars.get(v) {
+                                        Some(Value::List(relationships)) => {
+                                            extend_path(&mut p, relationships);
+                                            // the path already ends with the last node
+                                            iter.next();
+                                        }
+                                        Some(value) => p.push(value),
+                                        None => {
+                                            return Err(format!(
+                                                "Variable {} not found",
+                                                v.as_str()
+                                            ));
+                                        }
+                                    }
+                                }
                                 vars.insert(&path.var, Value::Path(p));
                             }
                             Ok(vars)
@@ -1097,6 +1714,26 @@ impl<'a> Runtime<'a> {
                 }
                 unreachable!();
             }
+            IR::Union => {
+                let mut iter: Box<dyn Iterator<Item = Result<Env, String>> + 'a> =
+                    Box::new(empty());
+                for child in self.plan.node(idx).children() {
+                    let names = child.get_return_names();
+                    let rows = self.run(&child.idx())?;
+                    // the columns of each query are bound to the variables of the first one
+                    iter = Box::new(iter.chain(rows.try_map(move |vars| {
+                        let mut row = Env::default();
+                        for (column, name) in self.return_names.iter().zip(&names) {
+                            row.insert(column, vars.get(name).unwrap_or(Value::Null));
+                        }
+                        Ok(row)
+                    })));
+                }
+                let idx = idx.clone();
+                Ok(iter.cond_inspect(self.inspect, move |res| {
+                    self.record.borrow_mut().push((idx.clone(), res.clone()));
+                }))
+            }
             IR::LoadCsv {
                 file_path,
                 headers,
@@ -1154,20 +1791,19 @@ impl<'a> Runtime<'a> {
             IR::Sort(trees) => {
                 if let Some(child_idx) = child0_idx {
                     let mut items = self
-                        .run(&child_idx)?
+                        .run_partitioned(&child_idx)?
                         .try_map(|env| {
-                            Ok((
-                                env.clone(),
-                                trees
-                                    .iter()
-                                    .map(|(tree, desc)| {
-                                        Ok((
-                                            self.run_expr(tree, tree.root().idx(), &env, None)?,
-                                            desc,
-                                        ))
-                                    })
-                                    .collect::<Result<Vec<_>, String>>()?,
-                            ))
+                            let keys = trees
+                                .iter()
+                                .map(|(tree, desc)| {
+                                    Ok((self.run_expr(tree, tree.root().idx(), &env, None)?, desc))
+                                })
+                                .collect::<Result<Vec<_>, String>>()?;
+                            self.track_memory(|| {
+                                env.memory_usage()
+                                    + keys.iter().map(|(v, _)| v.memory_usage()).sum::<usize>()
+                            })?;
+                            Ok((env, keys))
                         })
                         .collect::<Result<Vec<_>, String>>()?;
                     items.sort_by(|(_, a), (_, b)| {
@@ -1242,10 +1878,15 @@ impl<'a> Runtime<'a> {
                     cache.insert(k, (key, Ok(env.clone())));
                 }
                 let iter = if let Some(child_idx) = child0_idx {
-                    self.run(&child_idx)?
+                    self.run_partitioned(&child_idx)?
                 } else {
                     Box::new(once(Ok(Env::default())))
                 };
+                // the size of the last computed group key, accounted when it starts a new group
+                let key_size = Rc::new(Cell::new(0));
+                let last_key_size = key_size.clone();
+                // rows aggregated and accounted accumulators size per group
+                let groups = RefCell::new(HashMap::<u64, (u64, usize)>::new());
                 let idx = idx.clone();
                 Ok(iter
                     .aggregate(
@@ -1256,6 +1897,9 @@ impl<'a> Runtime<'a> {
                                 let value = self.run_expr(tree, tree.root().idx(), &vars, None)?;
                                 return_vars.insert(name, value);
                             }
+                            if self.mem_capacity.is_some() {
+                                key_size.set(return_vars.memory_usage());
+                            }
                             Ok::<Env, String>(return_vars)
                         },
                         Ok(env),
@@ -1271,6 +1915,23 @@ impl<'a> Runtime<'a> {
                                     group_key,
                                 )?;
                             }
+                            self.track_memory(|| {
+                                // a new group holds its key and accumulators, the growth of
+                                // the accumulators is measured every power of two rows
+                                // to keep the accounting linear in the number of rows
+                                let mut groups = groups.borrow_mut();
+                                let (rows, size) = groups.entry(group_key).or_default();
+                                *rows += 1;
+                                if *rows == 1 {
+                                    *size = acc.memory_usage();
+                                    return last_key_size.get() + *size;
+                                }
+                                if !rows.is_power_of_two() {
+                                    return 0;
+                                }
+                                let prev = std::mem::replace(size, acc.memory_usage());
+                                size.saturating_sub(prev)
+                            })?;
                             Ok(acc)
                         },
                         cache,
@@ -1311,9 +1972,11 @@ impl<'a> Runtime<'a> {
             IR::Distinct => {
                 if let Some(child_idx) = child0_idx {
                     let deduper = ValuesDeduper::default();
+                    // the columns of the projection below, which may be in a subquery
+                    let return_names = self.plan.node(&child_idx).get_return_names();
                     let idx = idx.clone();
                     return Ok(self
-                        .run(&child_idx)?
+                        .run_partitioned(&child_idx)?
                         .filter_map(move |item| {
                             // Propagate errors immediately
                             let vars = match item {
@@ -1324,7 +1987,7 @@ impl<'a> Runtime<'a> {
                             // compute the hash of all the values in return_names
                             // by order
                             let mut hasher = DefaultHasher::new();
-                            for name in &self.return_names {
+                            for name in &return_names {
                                 vars.get(name)
                                     .unwrap_or_else(|| {
                                         unreachable!("Variable {} not found", name.as_str())
@@ -1333,6 +1996,8 @@ impl<'a> Runtime<'a> {
                             }
                             if deduper.has_hash(hasher.finish()) {
                                 None
+                            } else if let Err(e) = self.track_memory(size_of::<u64>) {
+                                Some(Err(e))
                             } else {
                                 Some(Ok(vars))
                             }
@@ -1351,10 +2016,16 @@ impl<'a> Runtime<'a> {
                 }
                 let iter = self
                     .run(&child0_idx.ok_or("nothing to commit")?)?
+                    .map(|env| {
+                        let env = env?;
+                        self.track_memory(|| env.memory_usage())?;
+                        Ok(env)
+                    })
                     .collect::<Result<Vec<_>, String>>()?
                     .into_iter()
                     .map(Ok);
-                self.pending.borrow_mut().commit(self.g, &self.stats);
+                self.committed.set(true);
+                self.commit_pending();
                 let idx = idx.clone();
                 Ok(iter.cond_inspect(self.inspect, move |res| {
                     self.record.borrow_mut().push((idx.clone(), res.clone()));
@@ -1381,11 +2052,20 @@ impl<'a> Runtime<'a> {
         }
     }
 
+    /// Applies the pending changes to the graph, keeping them as effects to replicate.
+    fn commit_pending(&self) {
+        let mut pending = self.pending.borrow_mut();
+        if !pending.is_empty() {
+            pending.encode(&mut *self.effects.borrow_mut());
+        }
+        pending.commit(self.g, &self.stats);
+    }
+
     fn load_csv(
         &'a self,
         path: &str,
         headers: bool,
-        delimiter: Rc<String>,
+        delimiter: Arc<String>,
         var: &'a Variable,
         vars: &Env,
     ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + 'a>, String> {
@@ -1401,7 +2081,7 @@ impl<'a> Runtime<'a> {
                 .headers()
                 .map_err(|e| format!("Failed to read CSV headers: {e}"))?
                 .iter()
-                .map(|s| Rc::new(String::from(s)))
+                .map(|s| Arc::new(String::from(s)))
                 .collect::<Vec<_>>();
             Ok(Box::new(reader.into_records().map(
                 move |record| match record {
@@ -1409,7 +2089,7 @@ impl<'a> Runtime<'a> {
                         let mut env = vars.clone();
                         env.insert(
                             var,
-                            Value::Map(Rc::new(
+                            Value::Map(Arc::new(
                                 record
                                     .iter()
                                     .enumerate()
@@ -1418,11 +2098,10 @@ impl<'a> Runtime<'a> {
                                             None
                                         } else {
                                             Some((
-                                                headers
-                                                    .get(i)
-                                                    .cloned()
-                                                    .unwrap_or_else(|| Rc::new(format!("col_{i}"))),
-                                                Value::String(Rc::new(String::from(field))),
+                                                headers.get(i).cloned().unwrap_or_else(|| {
+                                                    Arc::new(format!("col_{i}"))
+                                                }),
+                                                Value::String(Arc::new(String::from(field))),
                                             ))
                                         }
                                     })
@@ -1448,7 +2127,7 @@ impl<'a> Runtime<'a> {
                                         if field.is_empty() {
                                             Value::Null
                                         } else {
-                                            Value::String(Rc::new(String::from(field)))
+                                            Value::String(Arc::new(String::from(field)))
                                         }
                                     })
                                     .collect(),
@@ -1632,6 +2311,8 @@ impl<'a> Runtime<'a> {
         &self,
         relationship_pattern: &'a QueryRelationship,
         vars: Env,
+        part: u64,
+        parts: u64,
     ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + '_>, String> {
         let filter_attrs = self.run_expr(
             &relationship_pattern.attrs,
@@ -1655,6 +2336,8 @@ impl<'a> Runtime<'a> {
             &relationship_pattern.types,
             &relationship_pattern.from.labels,
             &relationship_pattern.to.labels,
+            part,
+            parts,
         );
         Ok(Box::new(iter.flat_map(move |(src, dst)| {
             let vars = vars.clone();
@@ -1755,10 +2438,172 @@ impl<'a> Runtime<'a> {
         ))
     }
 
+    /// Follows a variable-length relationship from its source node, or from every node
+    /// with its labels when unbound, to the nodes reached within its bounds,
+    /// a relationship is followed at most once in each path.
+    fn var_length_traverse(
+        &self,
+        relationship_pattern: &'a QueryRelationship,
+        vars: Env,
+    ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + '_>, String> {
+        let (min_hops, max_hops) = relationship_pattern.var_length.unwrap_or((1, Some(1)));
+        let max_hops = max_hops.unwrap_or(u64::MAX);
+        let filter_attrs = self.run_expr(
+            &relationship_pattern.attrs,
+            relationship_pattern.attrs.root().idx(),
+            &vars,
+            None,
+        )?;
+        let node_id = |alias| match vars.get(alias) {
+            Some(Value::Node(id)) => Some(id),
+            _ => None,
+        };
+        let from_id = node_id(&relationship_pattern.from.alias);
+        let to_id = node_id(&relationship_pattern.to.alias);
+        let g = self.g.borrow();
+        let Some(to_labels) = relationship_pattern
+            .to
+            .labels
+            .iter()
+            .map(|label| g.get_label_id(label))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(Box::new(empty()));
+        };
+        let starts = from_id.map_or_else(
+            || {
+                g.get_nodes(&relationship_pattern.from.labels, 0, 1)
+                    .collect::<Vec<_>>()
+            },
+            |id| vec![id],
+        );
+        let adjacent = |node| {
+            adjacent_relationships(&g, relationship_pattern, &filter_attrs, node, false).into_iter()
+        };
+
+        let mut rows = vec![];
+        for start in starts {
+            let end = to_id.or_else(|| {
+                (relationship_pattern.from.alias.id == relationship_pattern.to.alias.id)
+                    .then_some(start)
+            });
+            let mut emit = |node: NodeId, path: &[(RelationshipId, NodeId, NodeId)]| {
+                if (path.len() as u64) < min_hops || end.is_some_and(|end| end != node) {
+                    return Ok(());
+                }
+                if !to_labels.is_empty() {
+                    let labels = g.get_node_label_ids(node).collect::<Vec<_>>();
+                    if !to_labels.iter().all(|label| labels.contains(label)) {
+                        return Ok(());
+                    }
+                }
+                let mut vars = vars.clone();
+                vars.insert(
+                    &relationship_pattern.alias,
+                    Value::List(
+                        path.iter()
+                            .map(|(id, src, dest)| Value::Relationship(*id, *src, *dest))
+                            .collect(),
+                    ),
+                );
+                vars.insert(&relationship_pattern.from.alias, Value::Node(start));
+                vars.insert(&relationship_pattern.to.alias, Value::Node(node));
+                self.track_memory(|| vars.memory_usage())?;
+                rows.push(Ok(vars));
+                Ok::<_, String>(())
+            };
+            // depth-first, the relationships left to follow from each node of the current path
+            let mut path = vec![];
+            emit(start, &path)?;
+            let mut stack = vec![(
+                start,
+                if max_hops > 0 {
+                    adjacent(start)
+                } else {
+                    vec![].into_iter()
+                },
+            )];
+            while let Some((node, next)) = stack.last_mut() {
+                let node = *node;
+                let Some((src, dest, id)) = next.next() else {
+                    stack.pop();
+                    path.pop();
+                    continue;
+                };
+                if path.iter().any(|(r, _, _)| *r == id) {
+                    continue;
+                }
+                // the paths grow exponentially with the hops on dense graphs
+                self.check_limits()?;
+                let other = if src == node { dest } else { src };
+                path.push((id, src, dest));
+                emit(other, &path)?;
+                let next = if (path.len() as u64) < max_hops {
+                    adjacent(other)
+                } else {
+                    vec![].into_iter()
+                };
+                stack.push((other, next));
+            }
+        }
+        Ok(Box::new(rows.into_iter()))
+    }
+
+    /// Binds the relationship alias to each shortest path between the bound endpoints,
+    /// or only the first one found for `shortestPath`.
+    fn shortest_paths(
+        &self,
+        relationship_pattern: &'a QueryRelationship,
+        vars: Env,
+    ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + '_>, String> {
+        let (min_hops, max_hops) = relationship_pattern.var_length.unwrap_or((1, Some(1)));
+        let filter_attrs = self.run_expr(
+            &relationship_pattern.attrs,
+            relationship_pattern.attrs.root().idx(),
+            &vars,
+            None,
+        )?;
+        let (Some(Value::Node(from)), Some(Value::Node(to))) = (
+            vars.get(&relationship_pattern.from.alias),
+            vars.get(&relationship_pattern.to.alias),
+        ) else {
+            return Ok(Box::new(empty()));
+        };
+        let mut paths = if from == to {
+            if min_hops == 0 { vec![vec![]] } else { vec![] }
+        } else {
+            find_shortest_paths(
+                &self.g.borrow(),
+                relationship_pattern,
+                &filter_attrs,
+                (from, to),
+                max_hops.unwrap_or(u64::MAX),
+                relationship_pattern.shortest == Some(ShortestPaths::All),
+                || self.check_limits(),
+            )?
+        };
+        // the shortest paths are not searched past their length, so shorter ones are dropped
+        paths.retain(|path| path.len() as u64 >= min_hops);
+        Ok(Box::new(paths.into_iter().map(move |path| {
+            let mut vars = vars.clone();
+            vars.insert(
+                &relationship_pattern.alias,
+                Value::List(
+                    path.into_iter()
+                        .map(|(id, src, dest)| Value::Relationship(id, src, dest))
+                        .collect(),
+                ),
+            );
+            Ok(vars)
+        })))
+    }
+
     fn node_scan(
         &self,
         node_pattern: &'a QueryNode,
         vars: Env,
+        part: u64,
+        parts: u64,
     ) -> Result<Box<dyn Iterator<Item = Result<Env, String>> + '_>, String> {
         let attrs = self.run_expr(
             &node_pattern.attrs,
@@ -1787,7 +2632,7 @@ impl<'a> Runtime<'a> {
                 }
             }
         }
-        let iter = self.g.borrow().get_nodes(&node_pattern.labels);
+        let iter = self.g.borrow().get_nodes(&node_pattern.labels, part, parts);
         Ok(Box::new(iter.filter_map(move |v| {
             let mut vars = vars.clone();
             if let Value::Map(attrs) = &attrs
@@ -1871,7 +2716,7 @@ impl<'a> Runtime<'a> {
                 Value::Map(attrs) => {
                     self.pending
                         .borrow_mut()
-                        .set_node_attributes(id, Rc::unwrap_or_clone(attrs));
+                        .set_node_attributes(id, Arc::unwrap_or_clone(attrs));
                 }
                 _ => unreachable!(),
             }
@@ -1905,7 +2750,7 @@ impl<'a> Runtime<'a> {
                 Value::Map(attrs) => {
                     self.pending
                         .borrow_mut()
-                        .set_relationship_attributes(id, Rc::unwrap_or_clone(attrs));
+                        .set_relationship_attributes(id, Arc::unwrap_or_clone(attrs));
                 }
                 _ => {
                     return Err(String::from("Invalid relationship properties"));
@@ -1919,7 +2764,7 @@ impl<'a> Runtime<'a> {
     pub fn get_node_attribute(
         &self,
         id: NodeId,
-        attribute: &Rc<String>,
+        attribute: &Arc<String>,
     ) -> Option<Value> {
         if let Some(value) = self.pending.borrow().get_node_attribute(id, attribute) {
             return Some(value.clone());
@@ -1933,7 +2778,7 @@ impl<'a> Runtime<'a> {
     pub fn get_relationship_attribute(
         &self,
         id: RelationshipId,
-        attribute: &Rc<String>,
+        attribute: &Arc<String>,
     ) -> Option<Value> {
         if let Some(value) = self
             .pending
@@ -1951,7 +2796,7 @@ impl<'a> Runtime<'a> {
     pub fn get_node_labels(
         &self,
         id: NodeId,
-    ) -> OrderSet<Rc<String>> {
+    ) -> OrderSet<Arc<String>> {
         let mut labels = self.g.borrow().get_node_labels(id).collect::<OrderSet<_>>();
         self.pending.borrow().update_node_labels(id, &mut labels);
         labels
@@ -1960,9 +2805,9 @@ impl<'a> Runtime<'a> {
     pub fn get_node_attrs(
         &self,
         id: NodeId,
-    ) -> OrderMap<Rc<String>, Value> {
+    ) -> OrderMap<Arc<String>, Value> {
         let g = self.g.borrow();
-        let mut actual: OrderMap<Rc<String>, Value> = g
+        let mut actual: OrderMap<Arc<String>, Value> = g
             .get_node_attrs(id)
             .iter()
             .map(|(k, v)| (g.get_node_attribute_string(*k).unwrap(), v.clone()))
@@ -1974,9 +2819,9 @@ impl<'a> Runtime<'a> {
     pub fn get_relationship_attrs(
         &self,
         id: RelationshipId,
-    ) -> OrderMap<Rc<String>, Value> {
+    ) -> OrderMap<Arc<String>, Value> {
         let g = self.g.borrow();
-        let mut actual: OrderMap<Rc<String>, Value> = g
+        let mut actual: OrderMap<Arc<String>, Value> = g
             .get_relationship_attrs(id)
             .iter()
             .map(|(k, v)| (g.get_relationship_attribute_string(*k).unwrap(), v.clone()))
@@ -1990,7 +2835,7 @@ impl<'a> Runtime<'a> {
     pub fn get_relationship_type(
         &self,
         id: RelationshipId,
-    ) -> Option<Rc<String>> {
+    ) -> Option<Arc<String>> {
         if let Some(type_name) = self.pending.borrow().get_relationship_type(id) {
             return Some(type_name);
         }
@@ -1999,19 +2844,192 @@ impl<'a> Runtime<'a> {
             .get_type(self.g.borrow().get_relationship_type_id(id))
     }
 
-    pub fn get_labels(&self) -> Vec<Rc<String>> {
+    pub fn get_labels(&self) -> Vec<Arc<String>> {
         self.g.borrow().get_labels()
     }
 
-    pub fn get_types(&self) -> Vec<Rc<String>> {
+    pub fn get_types(&self) -> Vec<Arc<String>> {
         self.g.borrow().get_types()
     }
 
-    pub fn get_attrs(&self) -> Vec<Rc<String>> {
+    pub fn get_attrs(&self) -> Vec<Arc<String>> {
         self.g.borrow().get_attrs()
     }
 }
 
+/// Appends the relationships of a variable-length relationship to `path`,
+/// each followed by the node it leads to from the node ending the path.
+fn extend_path(
+    path: &mut Vec<Value>,
+    mut relationships: Vec<Value>,
+) {
+    let Some(Value::Node(mut node)) = path.last().cloned() else {
+        unreachable!("a path starts with a node");
+    };
+    // the relationships are ordered from the source of the pattern
+    if relationships.first().is_some_and(
+        |r| !matches!(r, Value::Relationship(_, src, dest) if *src == node || *dest == node),
+    ) {
+        relationships.reverse();
+    }
+    for relationship in relationships {
+        let Value::Relationship(_, src, dest) = relationship else {
+            unreachable!("a variable-length relationship binds to relationships");
+        };
+        node = if src == node { dest } else { src };
+        path.push(relationship);
+        path.push(Value::Node(node));
+    }
+}
+
+/// Returns the relationships matching `pattern` leaving `node`, or entering it when `incoming`,
+/// following them in both directions when the pattern is bidirectional.
+fn adjacent_relationships(
+    g: &Graph,
+    pattern: &QueryRelationship,
+    filter_attrs: &Value,
+    node: NodeId,
+    incoming: bool,
+) -> Vec<(NodeId, NodeId, RelationshipId)> {
+    let mut relationships = g.get_adjacent_relationships(node, &pattern.types, incoming);
+    if pattern.bidirectional {
+        relationships.extend(
+            g.get_adjacent_relationships(node, &pattern.types, !incoming)
+                .into_iter()
+                .filter(|(src, dest, _)| src != dest),
+        );
+    }
+    relationships.retain(|(_, _, id)| {
+        let Value::Map(filter_attrs) = filter_attrs else {
+            return true;
+        };
+        let attrs = g.get_relationship_attrs(*id);
+        filter_attrs.iter().all(|(key, avalue)| {
+            g.get_relationship_attribute_id(key)
+                .and_then(|key| attrs.get(&key))
+                .is_some_and(|pvalue| *avalue == *pvalue)
+        })
+    });
+    relationships
+}
+
+/// A relationship of a path with its source and destination nodes.
+type Step = (RelationshipId, NodeId, NodeId);
+
+/// For each node reached by a breadth-first search, the relationships reaching it
+/// from the previous level with the node they come from.
+type Parents = HashMap<NodeId, Vec<(Step, NodeId)>>;
+
+/// Returns the shortest paths of at most `max_hops` relationships between `endpoints`,
+/// all of them when `all` or else only the first one found.
+///
+/// Searches breadth-first from both endpoints, expanding the smaller frontier one level at a time
+/// until the searches meet, the paths then go through the nodes reached by both.
+/// `check_limits` is called for each level and each path, failing the search with its error.
+fn find_shortest_paths(
+    g: &Graph,
+    pattern: &QueryRelationship,
+    filter_attrs: &Value,
+    (from, to): (NodeId, NodeId),
+    max_hops: u64,
+    all: bool,
+    check_limits: impl Fn() -> Result<(), String>,
+) -> Result<Vec<Vec<Step>>, String> {
+    let mut parents: [Parents; 2] = [
+        HashMap::from([(from, vec![])]),
+        HashMap::from([(to, vec![])]),
+    ];
+    let mut frontiers = [vec![from], vec![to]];
+    let mut hops = 0;
+    let meeting = loop {
+        if hops == max_hops || frontiers.iter().any(Vec::is_empty) {
+            return Ok(vec![]);
+        }
+        check_limits()?;
+        hops += 1;
+        let side = usize::from(frontiers[1].len() < frontiers[0].len());
+        let mut next = Parents::new();
+        for &node in &frontiers[side] {
+            // the search from the destination follows the relationships backwards
+            for (src, dest, id) in adjacent_relationships(g, pattern, filter_attrs, node, side == 1)
+            {
+                let other = if src == node { dest } else { src };
+                if !parents[side].contains_key(&other) {
+                    next.entry(other).or_default().push(((id, src, dest), node));
+                }
+            }
+        }
+        frontiers[side] = next.keys().copied().collect();
+        let meeting = next
+            .keys()
+            .filter(|node| parents[1 - side].contains_key(*node))
+            .copied()
+            .collect::<Vec<_>>();
+        parents[side].extend(next);
+        if !meeting.is_empty() {
+            break meeting;
+        }
+    };
+    let mut paths = vec![];
+    for node in meeting {
+        for forward in half_paths(&parents[0], node, all) {
+            for backward in half_paths(&parents[1], node, all) {
+                // the shortest paths multiply on graphs with many equal routes
+                check_limits()?;
+                paths.push(
+                    forward
+                        .iter()
+                        .chain(backward.iter().rev())
+                        .copied()
+                        .collect(),
+                );
+                if !all {
+                    return Ok(paths);
+                }
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Returns the paths from the start of the search of `parents` to `node`,
+/// all of them when `all` or else only one.
+fn half_paths(
+    parents: &Parents,
+    node: NodeId,
+    all: bool,
+) -> Vec<Vec<Step>> {
+    let steps = &parents[&node];
+    if steps.is_empty() {
+        return vec![vec![]];
+    }
+    let mut paths = vec![];
+    for (step, parent) in steps.iter().take(if all { steps.len() } else { 1 }) {
+        for mut path in half_paths(parents, *parent, all) {
+            path.push(*step);
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// Returns whether scanning `node_pattern` may look its nodes up in an index.
+fn uses_index(
+    node_pattern: &QueryNode,
+    g: &Graph,
+) -> bool {
+    node_pattern.attrs.root().children().any(|child| {
+        if let ExprIR::String(key) = child.data() {
+            node_pattern
+                .labels
+                .iter()
+                .any(|label| g.is_indexed(label, key))
+        } else {
+            false
+        }
+    })
+}
+
 pub fn evaluate_param(expr: &DynNode<ExprIR>) -> Result<Value, String> {
     match expr.data() {
         ExprIR::Null => Ok(Value::Null),
@@ -2024,7 +3042,7 @@ pub fn evaluate_param(expr: &DynNode<ExprIR>) -> Result<Value, String> {
                 .map(|c| evaluate_param(&c))
                 .collect::<Result<Vec<_>, _>>()?,
         )),
-        ExprIR::Map => Ok(Value::Map(Rc::new(
+        ExprIR::Map => Ok(Value::Map(Arc::new(
             expr.children()
                 .map(|ir| match ir.data() {
                     ExprIR::String(key) => {
@@ -1,13 +1,12 @@
 #![allow(clippy::cast_precision_loss)]
 
 use std::{
-    cell::RefCell,
     cmp::Ordering,
     collections::HashSet,
     fmt::Debug,
     hash::{DefaultHasher, Hash, Hasher},
     ops::{Add, Div, Mul, Rem, Sub},
-    rc::Rc,
+    sync::{Arc, Mutex, PoisonError},
 };
 
 use ordermap::OrderMap;
@@ -25,13 +24,13 @@ pub enum Value {
     Bool(bool),
     Int(i64),
     Float(f64),
-    String(Rc<String>),
+    String(Arc<String>),
     List(Vec<Value>),
-    Map(Rc<OrderMap<Rc<String>, Value>>),
+    Map(Arc<OrderMap<Arc<String>, Value>>),
     Node(NodeId),
     Relationship(RelationshipId, NodeId, NodeId),
     Path(Vec<Value>),
-    Rc(Rc<Value>),
+    Arc(Arc<Value>),
 }
 
 impl Value {
@@ -97,7 +96,7 @@ impl Hash for Value {
                 8.hash(state);
                 x.hash(state);
             }
-            Self::Rc(x) => {
+            Self::Arc(x) => {
                 x.hash(state);
             }
         }
@@ -141,6 +140,12 @@ impl Env {
             self.0[key] = value;
         }
     }
+
+    /// Approximate number of bytes held by the environment.
+    #[must_use]
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>() + self.0.iter().map(Value::memory_usage).sum::<usize>()
+    }
 }
 
 impl AsRef<Vec<Value>> for Env {
@@ -193,10 +198,10 @@ impl Add for Value {
                 new_list.extend(l);
                 Ok(Self::List(new_list))
             }
-            (Self::String(a), Self::String(b)) => Ok(Self::String(Rc::new(format!("{a}{b}")))),
-            (Self::String(s), Self::Int(i)) => Ok(Self::String(Rc::new(format!("{s}{i}")))),
-            (Self::String(s), Self::Float(f)) => Ok(Self::String(Rc::new(format!("{s}{f}")))),
-            (Self::String(s), Self::Bool(f)) => Ok(Self::String(Rc::new(format!("{s}{f}")))),
+            (Self::String(a), Self::String(b)) => Ok(Self::String(Arc::new(format!("{a}{b}")))),
+            (Self::String(s), Self::Int(i)) => Ok(Self::String(Arc::new(format!("{s}{i}")))),
+            (Self::String(s), Self::Float(f)) => Ok(Self::String(Arc::new(format!("{s}{f}")))),
+            (Self::String(s), Self::Bool(f)) => Ok(Self::String(Arc::new(format!("{s}{f}")))),
             (a, b) => Err(format!(
                 "Unexpected types for add operator ({}, {})",
                 a.name(),
@@ -341,7 +346,7 @@ impl OrderedEnum for Value {
             Self::Node(_) => 1 << 1,
             Self::Relationship(_, _, _) => 1 << 2,
             Self::Path(_) => 1 << 4,
-            Self::Rc(inner) => inner.order(),
+            Self::Arc(inner) => inner.order(),
         }
     }
 }
@@ -422,8 +427,8 @@ impl ValueTypeOf for Value {
             | (Self::Relationship(_, _, _), Type::Relationship)
             | (Self::Path(_), Type::Path)
             | (_, Type::Any) => None,
-            (Self::Rc(inner), ty) => {
-                // If the inner value is a Rc, we need to check its type
+            (Self::Arc(inner), ty) => {
+                // If the inner value is an Arc, we need to check its type
                 inner.value_of_type(ty)
             }
             (v, Type::Optional(ty)) => v.value_of_type(ty),
@@ -455,12 +460,30 @@ impl ValueGetType for Value {
             Self::Node(_) => Type::Node,
             Self::Relationship(_, _, _) => Type::Relationship,
             Self::Path(_) => Type::Path,
-            Self::Rc(inner) => inner.get_type(),
+            Self::Arc(inner) => inner.get_type(),
         }
     }
 }
 
 impl Value {
+    /// Approximate number of bytes held by the value, including heap allocations.
+    #[must_use]
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                Self::String(s) => size_of::<String>() + s.capacity(),
+                Self::List(values) | Self::Path(values) => {
+                    values.iter().map(Self::memory_usage).sum::<usize>()
+                }
+                Self::Map(map) => map
+                    .iter()
+                    .map(|(k, v)| size_of::<Arc<String>>() + k.capacity() + v.memory_usage())
+                    .sum::<usize>(),
+                Self::Arc(inner) => inner.memory_usage(),
+                _ => 0,
+            }
+    }
+
     pub(crate) fn name(&self) -> String {
         match self {
             Self::Null => String::from("Null"),
@@ -473,7 +496,7 @@ impl Value {
             Self::Node(_) => String::from("Node"),
             Self::Relationship(_, _, _) => String::from("Relationship"),
             Self::Path(_) => String::from("Path"),
-            Self::Rc(inner) => inner.name(),
+            Self::Arc(inner) => inner.name(),
         }
     }
 
@@ -529,8 +552,8 @@ impl Value {
     }
 
     fn compare_map(
-        a: &OrderMap<Rc<String>, Self>,
-        b: &OrderMap<Rc<String>, Self>,
+        a: &OrderMap<Arc<String>, Self>,
+        b: &OrderMap<Arc<String>, Self>,
     ) -> (Ordering, DisjointOrNull) {
         let a_key_count = a.len();
         let b_key_count = b.len();
@@ -539,9 +562,9 @@ impl Value {
         }
 
         // sort keys
-        let mut a_keys: Vec<&Rc<String>> = a.keys().collect();
+        let mut a_keys: Vec<&Arc<String>> = a.keys().collect();
         a_keys.sort();
-        let mut b_keys: Vec<&Rc<String>> = b.keys().collect();
+        let mut b_keys: Vec<&Arc<String>> = b.keys().collect();
         b_keys.sort();
 
         // iterate over keys count
@@ -627,7 +650,7 @@ fn compare_floats(
 }
 #[derive(Default, Debug)]
 pub struct ValuesDeduper {
-    seen: RefCell<HashSet<u64>>,
+    seen: Mutex<HashSet<u64>>,
 }
 
 impl ValuesDeduper {
@@ -654,7 +677,7 @@ impl ValuesDeduper {
         &self,
         hash: u64,
     ) -> bool {
-        let mut seen = self.seen.borrow_mut();
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
         if seen.contains(&hash) {
             true
         } else {
@@ -0,0 +1,272 @@
+use crate::cursor::invalidate_cursors;
+use graph::{
+    graph::graph::{Graph, GraphCell},
+    runtime::runtime::SpareThreads,
+};
+use redis_module::{Context, raw};
+use std::{
+    mem::transmute,
+    os::raw::c_void,
+    ptr::null_mut,
+    sync::{
+        Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{Sender, channel},
+    },
+    thread,
+};
+
+/// A graph stored in a key.
+///
+/// Read-only queries run on worker threads while the main thread keeps serving
+/// other commands, they share the graph with each other and wait for writes.
+pub struct GraphLock {
+    lock: RwLock<()>,
+    graph: GraphCell,
+    /// Incremented by every write, see [`GraphLock::version`].
+    version: AtomicU64,
+    /// The writes waiting for the readers to finish.
+    writers: AtomicUsize,
+}
+
+impl GraphLock {
+    #[must_use]
+    pub fn new(graph: Graph) -> Arc<Self> {
+        Arc::new(Self {
+            lock: RwLock::new(()),
+            graph: GraphCell::new(graph),
+            version: AtomicU64::new(0),
+            writers: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the number of writes started on the graph,
+    /// the graph did not change as long as it stays the same.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Runs `func` with shared access to the graph,
+    /// concurrently with other readers.
+    pub fn read<R>(
+        &self,
+        func: impl FnOnce(&GraphCell) -> R,
+    ) -> R {
+        let _guard = self.lock.read().unwrap_or_else(PoisonError::into_inner);
+        func(&self.graph)
+    }
+
+    /// Runs `func` with shared access to the graph without taking the lock,
+    /// for the commands and type callbacks running on the main thread or in a forked child.
+    ///
+    /// Waiting for the lock there would stall Redis behind a write waiting for the readers,
+    /// and block forever in a child forked while the write was waiting.
+    ///
+    /// # Safety
+    /// The caller must hold the GIL, writes only change the graph while holding it.
+    pub unsafe fn read_with_gil<R>(
+        &self,
+        func: impl FnOnce(&GraphCell) -> R,
+    ) -> R {
+        func(&self.graph)
+    }
+
+    /// Locks the graph for reading until the returned guard is dropped,
+    /// returns `None` without waiting when a write holds or waits for the lock.
+    pub fn try_read_owned(self: &Arc<Self>) -> Option<ReadGuard> {
+        let guard = match self.lock.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        // SAFETY: the lock lives in `graph`, which the guard keeps alive and drops after it
+        let guard =
+            unsafe { transmute::<RwLockReadGuard<'_, ()>, RwLockReadGuard<'static, ()>>(guard) };
+        Some(ReadGuard {
+            _guard: guard,
+            graph: self.clone(),
+        })
+    }
+
+    /// Returns true while a write waits for the readers of the graph.
+    pub fn writers_waiting(&self) -> bool {
+        self.writers.load(Ordering::Acquire) > 0
+    }
+
+    /// Runs `func` with exclusive access to the graph,
+    /// waiting for the queries running on worker threads to finish.
+    ///
+    /// Like [`GraphLock::try_write`], it must be called while holding the GIL.
+    pub fn write<R>(
+        &self,
+        func: impl FnOnce(&GraphCell) -> R,
+    ) -> R {
+        let _guard = self.lock_exclusive();
+        self.version.fetch_add(1, Ordering::AcqRel);
+        func(&self.graph)
+    }
+
+    /// Runs `func` with exclusive access to the graph if no query is reading it,
+    /// returns `None` without waiting otherwise.
+    pub fn try_write<R>(
+        &self,
+        func: impl FnOnce(&GraphCell) -> R,
+    ) -> Option<R> {
+        invalidate_cursors(self);
+        let _guard = match self.lock.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        self.version.fetch_add(1, Ordering::AcqRel);
+        Some(func(&self.graph))
+    }
+
+    /// Waits for the queries reading the graph to finish, without keeping it locked.
+    pub fn wait_readers(&self) {
+        drop(self.lock_exclusive());
+    }
+
+    /// Takes the write lock, invalidating the cursors that would hold it back.
+    fn lock_exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        // counted before invalidating, so the cursors put back meanwhile are invalidated too
+        self.writers.fetch_add(1, Ordering::AcqRel);
+        invalidate_cursors(self);
+        let guard = self.lock.write().unwrap_or_else(PoisonError::into_inner);
+        self.writers.fetch_sub(1, Ordering::AcqRel);
+        guard
+    }
+}
+
+/// A read lock on a graph kept across commands, see [`GraphLock::try_read_owned`].
+pub struct ReadGuard {
+    // declared first to be released before the graph holding the lock
+    _guard: RwLockReadGuard<'static, ()>,
+    graph: Arc<GraphLock>,
+}
+
+impl ReadGuard {
+    pub fn graph(&self) -> &GraphCell {
+        &self.graph.graph
+    }
+}
+
+/// A client blocked until its query finishes on a worker thread.
+///
+/// Dropping it unblocks the client.
+pub struct BlockedClient(*mut raw::RedisModuleBlockedClient);
+
+// SAFETY: Redis allows the blocked client handle to be used from any thread.
+unsafe impl Send for BlockedClient {}
+
+impl BlockedClient {
+    #[must_use]
+    pub fn new(ctx: &Context) -> Self {
+        unsafe {
+            Self(raw::RedisModule_BlockClient.unwrap()(
+                ctx.ctx, None, None, None, 0,
+            ))
+        }
+    }
+
+    /// Replies through a thread-safe context bound to the blocked client.
+    ///
+    /// The replies are accumulated and sent once the client is unblocked,
+    /// so `func` does not need to hold the GIL.
+    pub fn reply(
+        self,
+        func: impl FnOnce(&Context),
+    ) {
+        unsafe {
+            let ctx = raw::RedisModule_GetThreadSafeContext.unwrap()(self.0);
+            func(&Context::new(ctx));
+            raw::RedisModule_FreeThreadSafeContext.unwrap()(ctx);
+        }
+    }
+
+    /// Runs `func` with a thread-safe context bound to the blocked client while holding the GIL,
+    /// so it can open keys, replicate and reply like a command running on the main thread.
+    pub fn lock<R>(
+        &self,
+        func: impl FnOnce(&Context) -> R,
+    ) -> R {
+        unsafe {
+            let ctx = raw::RedisModule_GetThreadSafeContext.unwrap()(self.0);
+            raw::RedisModule_ThreadSafeContextLock.unwrap()(ctx);
+            let res = func(&Context::new(ctx));
+            raw::RedisModule_ThreadSafeContextUnlock.unwrap()(ctx);
+            raw::RedisModule_FreeThreadSafeContext.unwrap()(ctx);
+            res
+        }
+    }
+}
+
+impl Drop for BlockedClient {
+    fn drop(&mut self) {
+        unsafe {
+            raw::RedisModule_UnblockClient.unwrap()(self.0, null_mut::<c_void>());
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of worker threads executing jobs in submission order.
+pub struct ThreadPool {
+    sender: Sender<Job>,
+    /// The idle workers, bounding the threads spawned by the queries splitting their scans.
+    spare_threads: Arc<SpareThreads>,
+}
+
+impl ThreadPool {
+    /// # Panics
+    /// Panics if a worker thread can not be spawned.
+    #[must_use]
+    pub fn new(threads: usize) -> Self {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let spare_threads = Arc::new(SpareThreads::default());
+        spare_threads.release(threads);
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            let spare_threads = spare_threads.clone();
+            thread::Builder::new()
+                .name(format!("falkordb-worker-{i}"))
+                .spawn(move || {
+                    loop {
+                        let job = receiver
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .recv();
+                        match job {
+                            Ok(job) => {
+                                spare_threads.take();
+                                job();
+                                spare_threads.release(1);
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                })
+                .expect("Failed to spawn worker thread");
+        }
+        Self {
+            sender,
+            spare_threads,
+        }
+    }
+
+    pub fn execute(
+        &self,
+        job: impl FnOnce() + Send + 'static,
+    ) {
+        self.sender
+            .send(Box::new(job))
+            .expect("worker threads live as long as the pool");
+    }
+
+    /// Returns the idle workers, see [`SpareThreads`].
+    pub fn spare_threads(&self) -> Arc<SpareThreads> {
+        self.spare_threads.clone()
+    }
+}
@@ -0,0 +1,243 @@
+use crate::concurrency::{GraphLock, ReadGuard};
+use graph::{
+    graph::graph::GraphCell,
+    runtime::{
+        runtime::{QueryStatistics, Runtime},
+        value::Env,
+    },
+};
+use std::{
+    collections::HashMap,
+    iter::once,
+    mem::ManuallyDrop,
+    ptr,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// The pipeline of a suspended query and the runtime it borrows.
+struct SuspendedQuery {
+    rows: ManuallyDrop<Box<dyn Iterator<Item = Result<Env, String>>>>,
+    runtime: *mut Runtime<'static>,
+    /// Keeps the graph read-locked while the rows are pulled lazily,
+    /// released after the rows and the runtime borrowing the graph.
+    guard: Option<ReadGuard>,
+}
+
+impl Drop for SuspendedQuery {
+    fn drop(&mut self) {
+        // SAFETY: the rows borrowing the runtime are dropped first
+        unsafe {
+            ManuallyDrop::drop(&mut self.rows);
+            drop(Box::from_raw(self.runtime));
+        }
+    }
+}
+
+/// A query suspended between two batches of its rows, see `GRAPH.CURSOR`.
+///
+/// The cursor keeps the graph read-locked and pulls the rows from the query pipeline
+/// only when a batch is read. When the lock can not be kept, like for a write query,
+/// the rows are all computed when the cursor is created.
+/// A write to the graph invalidates its cursors, see [`invalidate_cursors`].
+pub struct Cursor {
+    /// `None` once the cursor is invalidated, dropped before the graph it borrows.
+    query: Option<SuspendedQuery>,
+    graph: Arc<GraphLock>,
+    version: u64,
+    pub compact: bool,
+    /// The number of rows read when `GRAPH.CURSOR READ` has no `COUNT`.
+    pub count: usize,
+    /// When the cursor was last kept, see [`expire_cursors`].
+    accessed: Instant,
+}
+
+// SAFETY: the runtime and the rows borrowing it are only reachable through the cursor,
+// they move between threads together and are used by one thread at a time.
+// The read lock of the cursor may be released by another thread than the one that took it,
+// which the futex and queue based `RwLock` of the standard library allow.
+unsafe impl Send for Cursor {}
+
+impl Cursor {
+    /// Starts the query of `runtime` on `graph`.
+    ///
+    /// # Safety
+    /// `runtime` must run on the graph of `graph`, borrowed from [`GraphLock::read`] or [`GraphLock::write`].
+    ///
+    /// The runtime is extended to `'static` as the cursor holds `graph`, so the graph outlives it.
+    /// The rows only borrow the graph while it can not change: either the cursor keeps it read-locked,
+    /// or they are computed here, under the lock of the caller.
+    ///
+    /// A query failing to start fails the first batch, the runtime stays available for its effects.
+    pub unsafe fn new(
+        graph: Arc<GraphLock>,
+        runtime: Runtime<'_>,
+        compact: bool,
+        count: usize,
+    ) -> Self {
+        let runtime = Box::into_raw(Box::new(unsafe {
+            std::mem::transmute::<Runtime<'_>, Runtime<'static>>(runtime)
+        }));
+        let rows = unsafe { &*runtime }
+            .query_lazy()
+            .unwrap_or_else(|err| Box::new(once(Err(err))));
+        let guard = graph.try_read_owned();
+        let rows = if guard.is_some() {
+            rows
+        } else {
+            Box::new(rows.collect::<Vec<_>>().into_iter())
+        };
+        Self {
+            query: Some(SuspendedQuery {
+                rows: ManuallyDrop::new(rows),
+                runtime,
+                guard,
+            }),
+            version: graph.version(),
+            graph,
+            compact,
+            count,
+            accessed: Instant::now(),
+        }
+    }
+
+    /// # Panics
+    /// Panics if the cursor is invalidated.
+    pub fn runtime(&self) -> &Runtime<'static> {
+        let query = self.query.as_ref().expect("cursor invalidated");
+        unsafe { &*query.runtime }
+    }
+
+    pub fn take_stats(&self) -> QueryStatistics {
+        self.runtime().take_stats()
+    }
+
+    /// Pulls up to `count` rows, the cursor is exhausted when less are returned.
+    ///
+    /// Must be called through [`Cursor::read`], except for the first batch by the query creating the cursor.
+    pub fn next_batch(
+        &mut self,
+        count: usize,
+    ) -> Result<Vec<Env>, String> {
+        let query = self.query.as_mut().ok_or_else(invalidated)?;
+        query.rows.by_ref().take(count).collect()
+    }
+
+    /// Runs `func` with the graph locked for reading,
+    /// failing when the graph changed since the cursor was created.
+    pub fn read<R>(
+        &mut self,
+        func: impl FnOnce(&mut Self, &GraphCell) -> R,
+    ) -> Result<R, String> {
+        let query = self.query.as_mut().ok_or_else(invalidated)?;
+        if let Some(guard) = query.guard.take() {
+            // the graph is already locked, locking it again would wait behind a pending write
+            let res = func(self, guard.graph());
+            if let Some(query) = &mut self.query {
+                query.guard = Some(guard);
+            }
+            return Ok(res);
+        }
+        let graph = self.graph.clone();
+        graph.read(|g| {
+            if graph.version() != self.version {
+                return Err(invalidated());
+            }
+            Ok(func(self, g))
+        })
+    }
+
+    /// Drops the query of the cursor, releasing its read lock on the graph.
+    fn invalidate(&mut self) {
+        self.query = None;
+    }
+}
+
+fn invalidated() -> String {
+    String::from("Cursor invalidated by a write to the graph")
+}
+
+static NEXT_CURSOR_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The cursors by the id of their client and their own id.
+static CURSORS: Mutex<Option<HashMap<(u64, u64), Cursor>>> = Mutex::new(None);
+
+/// Keeps the cursor `id` of `client`,
+/// invalidating it when a write is waiting for its read lock.
+fn keep_cursor(
+    client: u64,
+    id: u64,
+    mut cursor: Cursor,
+) {
+    let mut cursors = CURSORS.lock().unwrap_or_else(PoisonError::into_inner);
+    if cursor.graph.writers_waiting() {
+        cursor.invalidate();
+    }
+    cursor.accessed = Instant::now();
+    cursors.get_or_insert_default().insert((client, id), cursor);
+}
+
+/// Keeps `cursor` until `client` reads it or deletes it and returns its id.
+pub fn add_cursor(
+    client: u64,
+    cursor: Cursor,
+) -> u64 {
+    let id = NEXT_CURSOR_ID.fetch_add(1, Ordering::Relaxed);
+    keep_cursor(client, id, cursor);
+    id
+}
+
+/// Removes the cursor `id` of `client` on `graph`, to be added back if it is not exhausted.
+pub fn take_cursor(
+    client: u64,
+    graph: &Arc<GraphLock>,
+    id: u64,
+) -> Option<Cursor> {
+    let mut cursors = CURSORS.lock().unwrap_or_else(PoisonError::into_inner);
+    let cursors = cursors.as_mut()?;
+    if !Arc::ptr_eq(&cursors.get(&(client, id))?.graph, graph) {
+        return None;
+    }
+    cursors.remove(&(client, id))
+}
+
+/// Puts back a cursor taken by [`take_cursor`] under the same id.
+pub fn restore_cursor(
+    client: u64,
+    id: u64,
+    cursor: Cursor,
+) {
+    keep_cursor(client, id, cursor);
+}
+
+/// Invalidates the cursors of `graph` so their read locks do not hold back a write.
+///
+/// The cursors being read are invalidated when they are put back.
+pub fn invalidate_cursors(graph: &GraphLock) {
+    let mut cursors = CURSORS.lock().unwrap_or_else(PoisonError::into_inner);
+    for cursor in cursors.iter_mut().flat_map(HashMap::values_mut) {
+        if ptr::eq(Arc::as_ptr(&cursor.graph), graph) {
+            cursor.invalidate();
+        }
+    }
+}
+
+/// Drops the cursors of `client`, called when it disconnects.
+pub fn drop_client_cursors(client: u64) {
+    remove_cursors(|(c, _), _| *c == client);
+}
+
+/// Drops the cursors not read for longer than `max_idle`.
+pub fn expire_cursors(max_idle: Duration) {
+    remove_cursors(|_, cursor| cursor.accessed.elapsed() > max_idle);
+}
+
+fn remove_cursors(mut pred: impl FnMut(&(u64, u64), &Cursor) -> bool) {
+    let mut cursors = CURSORS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(cursors) = cursors.as_mut() {
+        cursors.retain(|key, cursor| !pred(key, cursor));
+    }
+}
@@ -1,17 +1,32 @@
 #![allow(clippy::cast_possible_wrap)]
 
+mod concurrency;
+mod cursor;
+mod reply;
+
+use concurrency::{BlockedClient, GraphLock, ThreadPool};
+use cursor::{
+    Cursor, add_cursor, drop_client_cursors, expire_cursors, restore_cursor, take_cursor,
+};
 use graph::{
     ast::Variable,
     cypher::Parser,
+    explain::{explain, profile},
     graph::{
-        graph::{Graph, Plan},
+        bulk::{NodeBatch, RelationshipBatch},
+        changes::Change,
+        encoding::{Decoder, ENCODING_VERSION, Encoder, SliceDecoder},
+        graph::{Graph, GraphCell, Plan},
         matrix::init,
     },
-    planner::Planner,
+    planner::{IR, Planner},
     runtime::{
         functions::init_functions,
-        runtime::{GetVariables, QueryStatistics, ResultSummary, Runtime, evaluate_param},
-        value::Value,
+        pending::Pending,
+        runtime::{
+            GetVariables, QueryStatistics, ResultSummary, Runtime, SpareThreads, evaluate_param,
+        },
+        value::{Env, Value},
     },
 };
 use lazy_static::lazy_static;
@@ -25,20 +40,22 @@ use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor};
 use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
 #[cfg(feature = "zipkin")]
 use opentelemetry_zipkin::ZipkinExporter;
-use orx_tree::{Bfs, NodeRef};
+use orx_tree::{Bfs, DynTree, NodeRef};
 use redis_module::{
-    ConfigurationValue, Context, NextArg, REDISMODULE_TYPE_METHOD_VERSION, RedisError,
+    Context, ContextFlags, NextArg, NotifyEvent, REDISMODULE_TYPE_METHOD_VERSION, RedisError,
     RedisGILGuard, RedisModule_Alloc, RedisModule_Calloc, RedisModule_Free, RedisModule_Realloc,
     RedisModuleIO, RedisModuleTypeMethods, RedisResult, RedisString, RedisValue, Status,
-    configuration::{ConfigurationContext, ConfigurationFlags},
-    native_types::RedisType,
-    raw, redis_module,
+    configuration::ConfigurationFlags, native_types::RedisType, raw, redis_module,
 };
+use reply::{JsonWriter, RespWriter, ValueWriter, write_value};
 use std::{
-    cell::RefCell,
     collections::HashMap,
+    num::NonZero,
     os::raw::{c_char, c_void},
     ptr::null_mut,
+    sync::{Arc, OnceLock},
+    thread::available_parallelism,
+    time::{Duration, Instant},
 };
 #[cfg(feature = "fuzz")]
 use std::{fs::File, io::Write};
@@ -53,16 +70,16 @@ const EMPTY_KEY_ERR: RedisResult = Err(RedisError::Str("ERR Invalid graph operat
 
 static GRAPH_TYPE: RedisType = RedisType::new(
     "graphdata",
-    0,
+    ENCODING_VERSION as i32,
     RedisModuleTypeMethods {
         version: REDISMODULE_TYPE_METHOD_VERSION as u64,
         rdb_load: Some(graph_rdb_load),
         rdb_save: Some(graph_rdb_save),
-        aof_rewrite: None,
+        aof_rewrite: Some(graph_aof_rewrite),
         free: Some(my_free),
 
         // Currently unused by Redis
-        mem_usage: None,
+        mem_usage: Some(graph_mem_usage),
         digest: None,
 
         // Aux data
@@ -71,9 +88,11 @@ static GRAPH_TYPE: RedisType = RedisType::new(
         aux_save2: None,
         aux_save_triggers: 0,
 
-        free_effort: None,
+        free_effort: Some(graph_free_effort),
         unlink: None,
-        copy: None,
+        copy: Some(graph_copy_value),
+        // Defrag is not supported, the graph storage lives in GraphBLAS matrices
+        // and Rust collections whose allocations can not be moved by Redis.
         defrag: None,
 
         copy2: None,
@@ -83,34 +102,164 @@ static GRAPH_TYPE: RedisType = RedisType::new(
     },
 );
 
+struct RdbEncoder(*mut RedisModuleIO);
+
+impl Encoder for RdbEncoder {
+    fn write_u64(
+        &mut self,
+        value: u64,
+    ) {
+        raw::save_unsigned(self.0, value);
+    }
+
+    fn write_i64(
+        &mut self,
+        value: i64,
+    ) {
+        raw::save_signed(self.0, value);
+    }
+
+    fn write_f64(
+        &mut self,
+        value: f64,
+    ) {
+        raw::save_double(self.0, value);
+    }
+
+    fn write_bytes(
+        &mut self,
+        value: &[u8],
+    ) {
+        raw::save_slice(self.0, value);
+    }
+}
+
+struct RdbDecoder(*mut RedisModuleIO);
+
+impl Decoder for RdbDecoder {
+    fn read_u64(&mut self) -> Result<u64, String> {
+        raw::load_unsigned(self.0).map_err(|e| e.to_string())
+    }
+
+    fn read_i64(&mut self) -> Result<i64, String> {
+        raw::load_signed(self.0).map_err(|e| e.to_string())
+    }
+
+    fn read_f64(&mut self) -> Result<f64, String> {
+        raw::load_double(self.0).map_err(|e| e.to_string())
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>, String> {
+        raw::load_string_buffer(self.0)
+            .map(|buf| buf.as_ref().to_vec())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Loads a graph saved by [`graph_rdb_save`].
+///
+/// Returning null signals Redis that the RDB is corrupted.
 #[unsafe(no_mangle)]
-#[allow(clippy::missing_const_for_fn)]
 unsafe extern "C" fn graph_rdb_load(
-    _: *mut RedisModuleIO,
-    _: i32,
+    rdb: *mut RedisModuleIO,
+    encver: i32,
 ) -> *mut c_void {
-    null_mut()
+    let mut dec = RdbDecoder(rdb);
+    Graph::decode(&mut dec, encver as u64).map_or(null_mut(), |graph| {
+        Box::into_raw(Box::new(GraphLock::new(graph))).cast::<c_void>()
+    })
 }
 
 #[unsafe(no_mangle)]
-#[allow(clippy::missing_const_for_fn)]
 unsafe extern "C" fn graph_rdb_save(
-    _: *mut RedisModuleIO,
-    _: *mut c_void,
+    rdb: *mut RedisModuleIO,
+    value: *mut c_void,
+) {
+    let graph = unsafe { &*value.cast::<Arc<GraphLock>>() };
+    unsafe { graph.read_with_gil(|g| g.borrow().encode(&mut RdbEncoder(rdb))) };
+}
+
+/// Max size of a single `GRAPH.RESTORE` payload emitted on AOF rewrite.
+const AOF_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Rewrites a graph key as a sequence of `GRAPH.RESTORE key CHUNK <payload>`
+/// commands followed by `GRAPH.RESTORE key COMMIT <version>`.
+///
+/// The payload is the same encoding used for RDB so replaying it
+/// rebuilds the graph exactly, including ids and indexes.
+#[unsafe(no_mangle)]
+unsafe extern "C" fn graph_aof_rewrite(
+    aof: *mut RedisModuleIO,
+    key: *mut raw::RedisModuleString,
+    value: *mut c_void,
 ) {
+    let graph = unsafe { &*value.cast::<Arc<GraphLock>>() };
+    let mut buf = Vec::new();
+    unsafe { graph.read_with_gil(|g| g.borrow().encode(&mut buf)) };
+    unsafe {
+        let emit_aof = raw::RedisModule_EmitAOF.unwrap();
+        for chunk in buf.chunks(AOF_CHUNK_SIZE) {
+            emit_aof(
+                aof,
+                c"GRAPH.RESTORE".as_ptr(),
+                c"scb".as_ptr(),
+                key,
+                c"CHUNK".as_ptr(),
+                chunk.as_ptr().cast::<c_char>(),
+                chunk.len(),
+            );
+        }
+        emit_aof(
+            aof,
+            c"GRAPH.RESTORE".as_ptr(),
+            c"scl".as_ptr(),
+            key,
+            c"COMMIT".as_ptr(),
+            ENCODING_VERSION as std::os::raw::c_longlong,
+        );
+    }
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn graph_mem_usage(value: *const c_void) -> usize {
+    let graph = unsafe { &*value.cast::<Arc<GraphLock>>() };
+    size_of::<GraphLock>() + unsafe { graph.read_with_gil(|g| g.borrow().memory_usage()) }
+}
+
+/// Redis frees the value in a background thread
+/// when the effort is above its lazy-free threshold.
+#[unsafe(no_mangle)]
+unsafe extern "C" fn graph_free_effort(
+    _key: *mut raw::RedisModuleString,
+    value: *const c_void,
+) -> usize {
+    let graph = unsafe { &*value.cast::<Arc<GraphLock>>() };
+    unsafe { graph.read_with_gil(|g| g.borrow().free_effort()) }
+}
+
+/// Called by Redis `COPY`.
+#[unsafe(no_mangle)]
+unsafe extern "C" fn graph_copy_value(
+    _from_key: *mut raw::RedisModuleString,
+    _to_key: *mut raw::RedisModuleString,
+    value: *const c_void,
+) -> *mut c_void {
+    let graph = unsafe { &*value.cast::<Arc<GraphLock>>() };
+    let graph = unsafe { graph.read_with_gil(|g| g.borrow().dup()) };
+    Box::into_raw(Box::new(GraphLock::new(graph))).cast::<c_void>()
 }
 
 #[unsafe(no_mangle)]
 unsafe extern "C" fn my_free(value: *mut c_void) {
     unsafe {
-        drop(Box::from_raw(value.cast::<RefCell<Graph>>()));
+        drop(Box::from_raw(value.cast::<Arc<GraphLock>>()));
     }
 }
 
 #[allow(clippy::too_many_lines)]
 fn reply_compact_value(
     ctx: &Context,
-    g: &RefCell<Graph>,
+    g: &GraphCell,
     r: Value,
 ) {
     match r {
@@ -237,127 +386,12 @@ fn reply_compact_value(
                 }
             }
         }
-        Value::Rc(inner) => {
+        Value::Arc(inner) => {
             reply_compact_value(ctx, g, (*inner).clone());
         }
     }
 }
 
-#[allow(clippy::too_many_lines)]
-fn reply_verbose_value(
-    ctx: &Context,
-    g: &RefCell<Graph>,
-    r: Value,
-) {
-    match r {
-        Value::Null => {
-            raw::reply_with_null(ctx.ctx);
-        }
-        Value::Bool(x) => {
-            let str = if x { "true" } else { "false" };
-            raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
-        }
-        Value::Int(x) => {
-            raw::reply_with_long_long(ctx.ctx, x as _);
-        }
-        Value::Float(x) => {
-            let str = format!("{x:.14e}");
-            raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
-        }
-        Value::String(x) => {
-            raw::reply_with_string_buffer(ctx.ctx, x.as_str().as_ptr().cast::<c_char>(), x.len());
-        }
-        Value::List(values) => {
-            raw::reply_with_array(ctx.ctx, values.len() as _);
-            for v in values {
-                reply_verbose_value(ctx, g, v.clone());
-            }
-        }
-        Value::Map(map) => {
-            raw::reply_with_array(ctx.ctx, (map.len() * 2) as _);
-
-            for (key, value) in map.iter() {
-                raw::reply_with_string_buffer(
-                    ctx.ctx,
-                    key.as_str().as_ptr().cast::<c_char>(),
-                    key.len(),
-                );
-                reply_verbose_value(ctx, g, value.clone());
-            }
-        }
-        Value::Node(id) => {
-            raw::reply_with_array(ctx.ctx, 3);
-            raw::reply_with_long_long(ctx.ctx, u64::from(id) as _);
-            let labels = g.borrow().get_node_label_ids(id).collect::<Vec<_>>();
-            raw::reply_with_array(ctx.ctx, labels.len() as _);
-            for label in labels {
-                let label = g.borrow().get_label_by_id(label);
-                raw::reply_with_string_buffer(
-                    ctx.ctx,
-                    label.as_ptr().cast::<c_char>(),
-                    label.len(),
-                );
-            }
-            let bg = g.borrow();
-            let props = bg.get_node_attrs(id);
-            raw::reply_with_array(ctx.ctx, props.len() as _);
-            for (key, value) in props {
-                raw::reply_with_array(ctx.ctx, 2);
-                let key_name = bg.get_node_attribute_string(*key).unwrap();
-                raw::reply_with_string_buffer(
-                    ctx.ctx,
-                    key_name.as_ptr().cast::<c_char>(),
-                    key_name.len(),
-                );
-                reply_verbose_value(ctx, g, value.clone());
-            }
-        }
-        Value::Relationship(id, from, to) => {
-            raw::reply_with_array(ctx.ctx, 5);
-            raw::reply_with_long_long(ctx.ctx, u64::from(id) as _);
-            let rel_type = g
-                .borrow()
-                .get_type(g.borrow().get_relationship_type_id(id))
-                .unwrap();
-            raw::reply_with_string_buffer(
-                ctx.ctx,
-                rel_type.as_ptr().cast::<c_char>(),
-                rel_type.len(),
-            );
-            raw::reply_with_long_long(ctx.ctx, u64::from(from) as _);
-            raw::reply_with_long_long(ctx.ctx, u64::from(to) as _);
-            let bg = g.borrow();
-            let props = bg.get_relationship_attrs(id);
-            raw::reply_with_array(ctx.ctx, props.len() as _);
-            for (key, value) in props {
-                raw::reply_with_array(ctx.ctx, 2);
-                let key_name = bg.get_relationship_attribute_string(*key).unwrap();
-                raw::reply_with_string_buffer(
-                    ctx.ctx,
-                    key_name.as_ptr().cast::<c_char>(),
-                    key_name.len(),
-                );
-                reply_verbose_value(ctx, g, value.clone());
-            }
-        }
-        Value::Path(path) => {
-            raw::reply_with_array(ctx.ctx, path.len() as _);
-
-            for node in path {
-                match node {
-                    Value::Relationship(_, _, _) | Value::Node(_) => {
-                        reply_verbose_value(ctx, g, node.clone());
-                    }
-                    _ => unreachable!("Path should only contain nodes and relationships"),
-                }
-            }
-        }
-        Value::Rc(inner) => {
-            reply_verbose_value(ctx, g, (*inner).clone());
-        }
-    }
-}
-
 /// This function is used to delete a graph
 ///
 /// See: <https://docs.falkordb.com/commands/graph.delete.html>
@@ -379,104 +413,415 @@ fn graph_delete(
     let mut args = args.into_iter().skip(1);
     let key = args.next_arg()?;
     let key = ctx.open_key_writable(&key);
-    if key.get_value::<RefCell<Graph>>(&GRAPH_TYPE)?.is_some() {
+    if key.get_value::<Arc<GraphLock>>(&GRAPH_TYPE)?.is_some() {
         key.delete()
     } else {
         EMPTY_KEY_ERR
     }
 }
 
+fn plan_cache_size(ctx: &Context) -> usize {
+    usize::try_from(*CONFIGURATION_CACHE_SIZE.lock(ctx)).unwrap_or(0)
+}
+
+/// Creates an empty graph sized by the initial capacity configurations.
+fn new_graph(ctx: &Context) -> Arc<GraphLock> {
+    GraphLock::new(Graph::new(
+        CONFIGURATION_INITIAL_NODE_CAPACITY.lock(ctx).unsigned_abs(),
+        CONFIGURATION_INITIAL_RELATIONSHIP_CAPACITY
+            .lock(ctx)
+            .unsigned_abs(),
+    ))
+}
+
+/// The configurations used by a query, read on the main thread
+/// as the query itself may run on a worker thread.
+struct QueryConfig {
+    import_folder: String,
+    timeout: Option<Duration>,
+    mem_capacity: Option<usize>,
+    resultset_size: Option<usize>,
+    cache_size: usize,
+    spare_threads: Arc<SpareThreads>,
+}
+
+impl QueryConfig {
+    fn new(
+        ctx: &Context,
+        timeout: Option<Duration>,
+    ) -> Self {
+        let mem_capacity = *CONFIGURATION_QUERY_MEM_CAPACITY.lock(ctx);
+        Self {
+            import_folder: CONFIGURATION_IMPORT_FOLDER.lock(ctx).clone(),
+            timeout,
+            mem_capacity: (mem_capacity > 0)
+                .then(|| usize::try_from(mem_capacity).unwrap_or(usize::MAX)),
+            // a negative size means the result set is unlimited
+            resultset_size: usize::try_from(*CONFIGURATION_RESULTSET_SIZE.lock(ctx)).ok(),
+            cache_size: plan_cache_size(ctx),
+            spare_threads: thread_pool(ctx).spare_threads(),
+        }
+    }
+
+    /// Applies the spare threads, the timeout and the memory capacity to `runtime`.
+    fn limit<'a>(
+        &self,
+        mut runtime: Runtime<'a>,
+    ) -> Runtime<'a> {
+        runtime = runtime.with_spare_threads(self.spare_threads.clone());
+        if let Some(timeout) = self.timeout {
+            runtime = runtime.with_timeout(timeout);
+        }
+        if let Some(mem_capacity) = self.mem_capacity {
+            runtime = runtime.with_mem_capacity(mem_capacity);
+        }
+        runtime
+    }
+}
+
+/// Returns whether running `plan` changes the graph.
+fn is_write_plan(plan: &DynTree<IR>) -> bool {
+    plan.root().walk::<Bfs>().any(|ir| {
+        matches!(
+            ir,
+            IR::Commit | IR::CreateIndex { .. } | IR::DropIndex { .. }
+        )
+    })
+}
+
+/// How a write is propagated to the replicas and the AOF.
+enum Replication {
+    /// The graph did not change.
+    None,
+    /// The command is replayed as is, like a query changing the indexes.
+    Verbatim,
+    /// The changes committed by the query, replayed with `GRAPH.EFFECT`
+    /// so replicas don't depend on `rand()`, `timestamp()` or the reuse of deleted ids.
+    Effects(Vec<u8>),
+}
+
+impl Replication {
+    fn new(
+        plan: &DynTree<IR>,
+        effects: Vec<u8>,
+    ) -> Self {
+        if plan
+            .root()
+            .walk::<Bfs>()
+            .any(|ir| matches!(ir, IR::CreateIndex { .. } | IR::DropIndex { .. }))
+        {
+            Self::Verbatim
+        } else if effects.is_empty() {
+            Self::None
+        } else {
+            Self::Effects(effects)
+        }
+    }
+
+    /// `argv` is the command to replicate verbatim when it does not run on the main thread,
+    /// where it can not be replicated with `replicate_verbatim`.
+    fn replicate(
+        self,
+        ctx: &Context,
+        key: &RedisString,
+        argv: Option<&[Vec<u8>]>,
+    ) {
+        match self {
+            Self::None => {}
+            Self::Verbatim => match argv {
+                Some([command, args @ ..]) => {
+                    let args = args.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                    ctx.replicate(&String::from_utf8_lossy(command), args.as_slice());
+                }
+                _ => {
+                    ctx.replicate_verbatim();
+                }
+            },
+            Self::Effects(effects) => {
+                ctx.replicate("GRAPH.EFFECT", &[key.as_slice(), effects.as_slice()][..]);
+            }
+        }
+    }
+}
+
+/// The changes of a write query, propagated once the key holds the graph.
+struct Changes {
+    replication: Replication,
+    /// The module keyspace events fired for the query statistics.
+    events: Vec<&'static str>,
+    /// The error of a query failing after committing some of its changes,
+    /// replied once they are propagated, see [`failed_query`].
+    error: Option<RedisError>,
+}
+
+impl Changes {
+    fn new(
+        replication: Replication,
+        stats: &QueryStatistics,
+    ) -> Self {
+        let events = [
+            ("graph.labels_added", stats.labels_added > 0),
+            ("graph.nodes_created", stats.nodes_created > 0),
+            ("graph.nodes_deleted", stats.nodes_deleted > 0),
+            (
+                "graph.relationships_created",
+                stats.relationships_created > 0,
+            ),
+            (
+                "graph.relationships_deleted",
+                stats.relationships_deleted > 0,
+            ),
+            ("graph.properties_set", stats.properties_set > 0),
+            ("graph.properties_removed", stats.properties_removed > 0),
+        ];
+        Self {
+            replication,
+            events: events
+                .into_iter()
+                .filter_map(|(event, fired)| fired.then_some(event))
+                .collect(),
+            error: None,
+        }
+    }
+
+    /// Notifies the keyspace events of `key` and replicates the query, see [`Replication::replicate`],
+    /// then fails with the error of the query if it has one.
+    fn propagate(
+        self,
+        ctx: &Context,
+        key: &RedisString,
+        argv: Option<&[Vec<u8>]>,
+    ) -> RedisResult {
+        for event in self.events {
+            ctx.notify_keyspace_event(NotifyEvent::MODULE, event, key);
+        }
+        self.replication.replicate(ctx, key, argv);
+        self.error.map_or(Ok(RedisValue::NoReply), Err)
+    }
+}
+
+/// Fails with `err`, after propagating the changes the query committed before failing.
+///
+/// Each batch of `CALL {} IN TRANSACTIONS` is committed on its own,
+/// the batches committed before the error stay applied and are replicated as effects.
+fn failed_query(
+    runtime: &Runtime,
+    err: String,
+) -> Result<Changes, RedisError> {
+    let effects = runtime.take_effects();
+    if effects.is_empty() {
+        return Err(RedisError::String(err));
+    }
+    let mut changes = Changes::new(Replication::Effects(effects), &runtime.take_stats());
+    changes.error = Some(RedisError::String(err));
+    Ok(changes)
+}
+
 #[inline]
 fn query_mut(
     ctx: &Context,
-    graph: &RefCell<Graph>,
+    graph: &Arc<GraphLock>,
+    g: &GraphCell,
     query: &str,
-    compact: bool,
+    args: &QueryArgs,
+    config: &QueryConfig,
     write: bool,
-) -> Result<(), RedisError> {
+) -> Result<Changes, RedisError> {
     // Create a child span for parsing and execution
     tracing::debug_span!("query_execution", query = %query).in_scope(|| {
         let Plan {
             plan, parameters, ..
-        } = graph.borrow().get_plan(query).map_err(RedisError::String)?;
+        } = g
+            .borrow()
+            .get_plan(query, config.cache_size)
+            .map_err(RedisError::String)?;
         let parameters = parameters
             .into_iter()
             .map(|(k, v)| Ok((k, evaluate_param(&v.root())?)))
             .collect::<Result<HashMap<_, _>, String>>()
             .map_err(RedisError::String)?;
-        let scope = CONFIGURATION_IMPORT_FOLDER.lock(ctx);
-        let mut runtime = Runtime::new(graph, parameters, write, plan, false, (*scope).clone());
-        let result = runtime.query().map_err(RedisError::String)?;
-        if compact {
-            reply_compact(ctx, graph, &runtime.return_names, result);
-        } else {
-            reply_verbose(ctx, graph, &runtime.return_names, result);
+        let mut runtime = config.limit(Runtime::new(
+            g,
+            parameters,
+            write,
+            plan.clone(),
+            false,
+            false,
+            config.import_folder.clone(),
+        ));
+        if let Some(size) = config.resultset_size {
+            runtime = runtime.with_resultset_size(size);
+        }
+        let compact = matches!(args.format, ReplyFormat::Compact);
+        if let Some(count) = args.cursor {
+            let start = Instant::now();
+            // SAFETY: `g` is the graph of `graph`, locked by the caller
+            let mut cursor = unsafe { Cursor::new(graph.clone(), runtime, compact, count) };
+            let rows = match cursor.next_batch(count) {
+                Ok(rows) => rows,
+                Err(err) => return failed_query(cursor.runtime(), err),
+            };
+            let mut stats = cursor.take_stats();
+            stats.execution_time = start.elapsed().as_secs_f64() * 1000.0;
+            let return_names = cursor.runtime().return_names.clone();
+            let changes = Changes::new(
+                Replication::new(&plan, cursor.runtime().take_effects()),
+                &stats,
+            );
+            let id = if rows.len() < count {
+                0
+            } else {
+                add_cursor(args.client, cursor)
+            };
+            let result = ResultSummary {
+                stats,
+                result: rows,
+            };
+            reply_result(ctx, g, &return_names, result, compact, Some(id));
+            return Ok(changes);
+        }
+        let result = match runtime.query() {
+            Ok(result) => result,
+            Err(err) => return failed_query(&runtime, err),
+        };
+        let changes = Changes::new(
+            Replication::new(&plan, runtime.take_effects()),
+            &result.stats,
+        );
+        match args.format {
+            ReplyFormat::Json => reply_json(ctx, g, &runtime.return_names, result),
+            _ => reply_result(ctx, g, &runtime.return_names, result, compact, None),
         }
-        Ok(())
+        Ok(changes)
     })
 }
 
-fn reply_stats(
-    ctx: &Context,
-    stats: &QueryStatistics,
-) {
-    let mut stats_len = 1;
+/// Returns the statistics lines ending a query reply.
+fn stats_lines(stats: &QueryStatistics) -> Vec<String> {
+    let mut lines = vec![];
     if stats.labels_added > 0 {
-        stats_len += 1;
+        lines.push(format!("Labels added: {}", stats.labels_added));
     }
     if stats.nodes_created > 0 {
-        stats_len += 1;
+        lines.push(format!("Nodes created: {}", stats.nodes_created));
     }
     if stats.nodes_deleted > 0 {
-        stats_len += 1;
+        lines.push(format!("Nodes deleted: {}", stats.nodes_deleted));
     }
     if stats.properties_set > 0 {
-        stats_len += 1;
+        lines.push(format!("Properties set: {}", stats.properties_set));
     }
     if stats.properties_removed > 0 {
-        stats_len += 1;
+        lines.push(format!("Properties removed: {}", stats.properties_removed));
     }
     if stats.relationships_created > 0 {
-        stats_len += 1;
+        lines.push(format!(
+            "Relationships created: {}",
+            stats.relationships_created
+        ));
     }
     if stats.relationships_deleted > 0 {
-        stats_len += 1;
-    }
-
-    raw::reply_with_array(ctx.ctx, stats_len.into());
-    if stats.labels_added > 0 {
-        let str = format!("Labels added: {}", stats.labels_added);
-        raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
-    }
-    if stats.nodes_created > 0 {
-        let str = format!("Nodes created: {}", stats.nodes_created);
-        raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
+        lines.push(format!(
+            "Relationships deleted: {}",
+            stats.relationships_deleted
+        ));
     }
-    if stats.nodes_deleted > 0 {
-        let str = format!("Nodes deleted: {}", stats.nodes_deleted);
-        raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
-    }
-    if stats.properties_set > 0 {
-        let str = format!("Properties set: {}", stats.properties_set);
-        raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
+    if stats.resultset_truncated {
+        lines.push(String::from("Result set truncated: 1"));
     }
-    if stats.properties_removed > 0 {
-        let str = format!("Properties removed: {}", stats.properties_removed);
-        raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
+    lines.push(format!(
+        "Query internal execution time: {} milliseconds",
+        stats.execution_time
+    ));
+    lines
+}
+
+fn reply_stats(
+    ctx: &Context,
+    stats: &QueryStatistics,
+) {
+    let lines = stats_lines(stats);
+    raw::reply_with_array(ctx.ctx, lines.len() as _);
+    for line in lines {
+        raw::reply_with_string_buffer(ctx.ctx, line.as_ptr().cast::<c_char>(), line.len());
     }
-    if stats.relationships_created > 0 {
-        let str = format!("Relationships created: {}", stats.relationships_created);
-        raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
+}
+
+/// How the result set of a query is replied.
+#[derive(Clone, Copy)]
+enum ReplyFormat {
+    Verbose,
+    /// `--compact`, entities are replied by their ids.
+    Compact,
+    /// `--json` or `FORMAT JSON`, the whole reply is one JSON document.
+    Json,
+}
+
+/// The optional arguments following the query.
+struct QueryArgs {
+    format: ReplyFormat,
+    /// The number of rows replied before the rest is read with `GRAPH.CURSOR`.
+    cursor: Option<usize>,
+    timeout: Option<Duration>,
+    /// The client owning the cursor of the query.
+    client: u64,
+}
+
+/// Parses the optional arguments following the query: `--compact`, `--json`,
+/// `FORMAT <VERBOSE|COMPACT|JSON>`, `CURSOR <count>` and `TIMEOUT <ms>`.
+///
+/// Without a `TIMEOUT` argument the `TIMEOUT_DEFAULT` configuration is used,
+/// a timeout of 0 means the query is never aborted.
+fn parse_query_args(
+    ctx: &Context,
+    mut args: impl Iterator<Item = RedisString>,
+) -> Result<QueryArgs, RedisError> {
+    let mut format = ReplyFormat::Verbose;
+    let mut cursor = None;
+    let mut timeout = CONFIGURATION_TIMEOUT_DEFAULT.lock(ctx).unsigned_abs();
+    while let Ok(arg) = args.next_str() {
+        if arg == "--compact" {
+            format = ReplyFormat::Compact;
+        } else if arg == "--json" {
+            format = ReplyFormat::Json;
+        } else if arg.eq_ignore_ascii_case("FORMAT") {
+            let value = args.next_str().unwrap_or_default();
+            format = if value.eq_ignore_ascii_case("VERBOSE") {
+                ReplyFormat::Verbose
+            } else if value.eq_ignore_ascii_case("COMPACT") {
+                ReplyFormat::Compact
+            } else if value.eq_ignore_ascii_case("JSON") {
+                ReplyFormat::Json
+            } else {
+                return Err(RedisError::Str("ERR Unknown FORMAT value"));
+            };
+        } else if arg.eq_ignore_ascii_case("CURSOR") {
+            cursor = Some(parse_cursor_count(&mut args)?);
+        } else if arg.eq_ignore_ascii_case("TIMEOUT") {
+            timeout = args
+                .next_u64()
+                .map_err(|_| RedisError::Str("ERR Failed to parse TIMEOUT value"))?;
+        }
     }
-    if stats.relationships_deleted > 0 {
-        let str = format!("Relationships deleted: {}", stats.relationships_deleted);
-        raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
+    if cursor.is_some() && matches!(format, ReplyFormat::Json) {
+        return Err(RedisError::Str(
+            "ERR CURSOR is not supported with the JSON format",
+        ));
     }
-    let str = format!(
-        "Query internal execution time: {} milliseconds",
-        stats.execution_time
-    );
-    raw::reply_with_string_buffer(ctx.ctx, str.as_ptr().cast::<c_char>(), str.len());
+    Ok(QueryArgs {
+        format,
+        cursor,
+        timeout: (timeout > 0).then(|| Duration::from_millis(timeout)),
+        client: ctx.get_client_id(),
+    })
+}
+
+fn parse_cursor_count(args: &mut impl Iterator<Item = RedisString>) -> Result<usize, RedisError> {
+    args.next_u64()
+        .ok()
+        .and_then(|count| usize::try_from(count).ok())
+        .filter(|count| *count > 0)
+        .ok_or(RedisError::Str("ERR Failed to parse COUNT value"))
 }
 
 #[cfg(feature = "fuzz")]
@@ -487,8 +832,9 @@ fn graph_query(
     ctx: &Context,
     args: Vec<RedisString>,
 ) -> RedisResult {
+    let argv = args.iter().map(|arg| arg.as_slice().to_vec()).collect();
     let mut args = args.into_iter().skip(1);
-    let key = args.next_arg()?;
+    let key_name = args.next_arg()?;
     let query = args.next_str()?;
 
     #[cfg(feature = "fuzz")]
@@ -502,30 +848,113 @@ fn graph_query(
         file_id += 1;
     }
 
-    let compact = args.next_str().is_ok_and(|arg| arg == "--compact");
-    let key = ctx.open_key_writable(&key);
+    let args = parse_query_args(ctx, args)?;
+    let config = QueryConfig::new(ctx, args.timeout);
+    let query = query.to_string();
+
+    write_graph(ctx, argv, &key_name, true, move |ctx, graph, g| {
+        query_mut(ctx, graph, g, &query, &args, &config, true)
+    })
+}
 
-    if let Some(graph) = key.get_value::<RefCell<Graph>>(&GRAPH_TYPE)? {
-        query_mut(ctx, graph, query, compact, true)?;
+/// Runs `func` on the graph of `key_name`, creating the graph when the key is empty
+/// if `create` is set, and propagates its changes.
+///
+/// Fails with the graph when a read-only query is using it, unless `wait` is set.
+fn try_write_graph<F>(
+    ctx: &Context,
+    key_name: &RedisString,
+    argv: Option<&[Vec<u8>]>,
+    create: bool,
+    func: &F,
+    wait: bool,
+) -> Result<RedisResult, Arc<GraphLock>>
+where
+    F: Fn(&Context, &Arc<GraphLock>, &GraphCell) -> Result<Changes, RedisError>,
+{
+    let key = ctx.open_key_writable(key_name);
+    let graph = match key.get_value::<Arc<GraphLock>>(&GRAPH_TYPE) {
+        Ok(Some(graph)) => graph.clone(),
+        Ok(None) if !create => return Ok(EMPTY_KEY_ERR),
+        Ok(None) => {
+            let graph = new_graph(ctx);
+            let res = graph.write(|g| func(ctx, &graph, g)).and_then(|changes| {
+                key.set_value(&GRAPH_TYPE, graph.clone())?;
+                changes.propagate(ctx, key_name, argv)
+            });
+            return Ok(res);
+        }
+        Err(err) => return Ok(Err(err)),
+    };
+    let changes = if wait {
+        graph.write(|g| func(ctx, &graph, g))
     } else {
-        let graph = RefCell::new(Graph::new(16384, 16384));
-        query_mut(ctx, &graph, query, compact, true)?;
-        key.set_value(&GRAPH_TYPE, graph)?;
-    }
+        graph
+            .try_write(|g| func(ctx, &graph, g))
+            .ok_or_else(|| graph.clone())?
+    };
+    Ok(changes.and_then(|changes| changes.propagate(ctx, key_name, argv)))
+}
+
+/// Runs a write command on the graph of `key_name`, see [`try_write_graph`].
+///
+/// Waiting for the read-only queries of the graph would stall the main thread,
+/// so the client is blocked instead and a worker thread retries the write
+/// holding the GIL once they finished. Clients that can not be blocked,
+/// like inside MULTI or scripts, wait for them on the main thread.
+fn write_graph<F>(
+    ctx: &Context,
+    argv: Vec<Vec<u8>>,
+    key_name: &RedisString,
+    create: bool,
+    func: F,
+) -> RedisResult
+where
+    F: Fn(&Context, &Arc<GraphLock>, &GraphCell) -> Result<Changes, RedisError> + Send + 'static,
+{
+    let wait = ctx.get_flags().contains(ContextFlags::DENY_BLOCKING);
+    let mut graph = match try_write_graph(ctx, key_name, None, create, &func, wait) {
+        Ok(res) => return res,
+        Err(graph) => graph,
+    };
 
+    let blocked_client = BlockedClient::new(ctx);
+    thread_pool(ctx).execute(move || {
+        loop {
+            graph.wait_readers();
+            let res = blocked_client.lock(|ctx| {
+                let key_name = ctx.create_string(argv[1].clone());
+                try_write_graph(ctx, &key_name, Some(argv.as_slice()), create, &func, false).map(
+                    |res| {
+                        if let Err(err) = res {
+                            ctx.reply(Err(err));
+                        }
+                    },
+                )
+            });
+            match res {
+                Ok(()) => break,
+                // a new read-only query started, or the key now holds another graph
+                Err(busy) => graph = busy,
+            }
+        }
+    });
     RedisResult::Ok(RedisValue::NoReply)
 }
 
 #[inline]
 fn record_mut(
     ctx: &Context,
-    graph: &RefCell<Graph>,
+    graph: &GraphCell,
     query: &str,
-) -> Result<(), RedisError> {
+) -> Result<Changes, RedisError> {
     // Create a child span for parsing and execution
     let Plan {
         plan, parameters, ..
-    } = graph.borrow().get_plan(query).map_err(RedisError::String)?;
+    } = graph
+        .borrow()
+        .get_plan(query, plan_cache_size(ctx))
+        .map_err(RedisError::String)?;
     let parameters = parameters
         .into_iter()
         .map(|(k, v)| Ok((k, evaluate_param(&v.root())?)))
@@ -538,15 +967,19 @@ fn record_mut(
         true,
         plan.clone(),
         true,
+        false,
         (*scope).clone(),
     );
-    let _ = runtime.query().map_err(RedisError::String)?;
+    let result = match runtime.query() {
+        Ok(result) => result,
+        Err(err) => return failed_query(&runtime, err),
+    };
+    let mut w = RespWriter::new(ctx);
     raw::reply_with_array(ctx.ctx, 2);
     raw::reply_with_array(ctx.ctx, runtime.record.borrow().len() as _);
     for (idx, res) in runtime.record.borrow().iter() {
-        let idx_str = format!("{idx:?}");
         raw::reply_with_array(ctx.ctx, 3);
-        raw::reply_with_string_buffer(ctx.ctx, idx_str[17..].as_ptr().cast::<c_char>(), 9);
+        raw::reply_with_long_long(ctx.ctx, runtime.operator_id(idx) as _);
         match res {
             Err(err) => {
                 raw::reply_with_long_long(ctx.ctx, 0);
@@ -562,7 +995,7 @@ fn record_mut(
                             raw::reply_with_null(ctx.ctx);
                         }
                         Some(value) => {
-                            reply_verbose_value(ctx, graph, value);
+                            write_value(&mut w, graph, value);
                         }
                     }
                 }
@@ -574,16 +1007,10 @@ fn record_mut(
     raw::reply_with_array(ctx.ctx, len as _);
     for idx in plan.root().indices::<Bfs>() {
         raw::reply_with_array(ctx.ctx, 4);
-        let idx_str = format!("{idx:?}");
-        raw::reply_with_string_buffer(ctx.ctx, idx_str[17..].as_ptr().cast::<c_char>(), 9);
+        raw::reply_with_long_long(ctx.ctx, runtime.operator_id(&idx) as _);
         match plan.node(&idx).parent() {
-            Some(parent_idx) => {
-                let parent_idx_str = format!("{:?}", parent_idx.idx());
-                raw::reply_with_string_buffer(
-                    ctx.ctx,
-                    parent_idx_str[17..].as_ptr().cast::<c_char>(),
-                    9,
-                );
+            Some(parent) => {
+                raw::reply_with_long_long(ctx.ctx, runtime.operator_id(&parent.idx()) as _);
             }
             None => {
                 raw::reply_with_null(ctx.ctx);
@@ -601,64 +1028,155 @@ fn record_mut(
             );
         }
     }
-    Ok(())
+    Ok(Changes::new(
+        Replication::new(&plan, runtime.take_effects()),
+        &result.stats,
+    ))
 }
 
 fn graph_record(
     ctx: &Context,
     args: Vec<RedisString>,
 ) -> RedisResult {
+    let argv = args.iter().map(|arg| arg.as_slice().to_vec()).collect();
     let mut args = args.into_iter().skip(1);
-    let key = args.next_arg()?;
-    let query = args.next_str()?;
-
-    let key = ctx.open_key_writable(&key);
-
-    if let Some(graph) = key.get_value::<RefCell<Graph>>(&GRAPH_TYPE)? {
-        record_mut(ctx, graph, query)?;
-    } else {
-        let graph = RefCell::new(Graph::new(16384, 16384));
-        record_mut(ctx, &graph, query)?;
-        key.set_value(&GRAPH_TYPE, graph)?;
-    }
+    let key_name = args.next_arg()?;
+    let query = args.next_str()?.to_string();
 
-    RedisResult::Ok(RedisValue::NoReply)
+    write_graph(ctx, argv, &key_name, true, move |ctx, _, g| {
+        record_mut(ctx, g, &query)
+    })
 }
 
-fn reply_verbose(
+#[inline]
+fn profile_mut(
+    ctx: &Context,
+    graph: &GraphCell,
+    query: &str,
+    config: &QueryConfig,
+    write: bool,
+) -> Result<Changes, RedisError> {
+    let Plan {
+        plan, parameters, ..
+    } = graph
+        .borrow()
+        .get_plan(query, config.cache_size)
+        .map_err(RedisError::String)?;
+    let parameters = parameters
+        .into_iter()
+        .map(|(k, v)| Ok((k, evaluate_param(&v.root())?)))
+        .collect::<Result<HashMap<_, _>, String>>()
+        .map_err(RedisError::String)?;
+    let mut runtime = config.limit(Runtime::new(
+        graph,
+        parameters,
+        write,
+        plan.clone(),
+        false,
+        true,
+        config.import_folder.clone(),
+    ));
+    let result = match runtime.query() {
+        Ok(result) => result,
+        Err(err) => return failed_query(&runtime, err),
+    };
+    let lines = profile(&plan, &runtime.profile_stats.borrow());
+    raw::reply_with_array(ctx.ctx, lines.len() as _);
+    for line in lines {
+        raw::reply_with_string_buffer(ctx.ctx, line.as_ptr().cast::<c_char>(), line.len());
+    }
+    Ok(Changes::new(
+        Replication::new(&plan, runtime.take_effects()),
+        &result.stats,
+    ))
+}
+
+/// This function is used to execute a query and report, for every operator,
+/// the records it produced and its cumulative execution time
+///
+/// A read-only query is profiled on a worker thread like `GRAPH.RO_QUERY`,
+/// both are subject to the same timeout and memory capacity as `GRAPH.QUERY`.
+///
+/// See: <https://docs.falkordb.com/commands/graph.profile.html>
+///
+/// # Example
+///
+/// ```sh
+/// GRAPH.PROFILE graph "MATCH (n:Person) RETURN n"
+/// ```
+fn graph_profile(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let argv = args.iter().map(|arg| arg.as_slice().to_vec()).collect();
+    let mut args = args.into_iter().skip(1);
+    let key_name = args.next_arg()?;
+    let query = args.next_str()?.to_string();
+    let config = QueryConfig::new(ctx, parse_query_args(ctx, args)?.timeout);
+
+    let graph = ctx
+        .open_key(&key_name)
+        .get_value::<Arc<GraphLock>>(&GRAPH_TYPE)?
+        .cloned();
+    if let Some(graph) = graph {
+        let plan =
+            unsafe { graph.read_with_gil(|g| g.borrow().get_plan(&query, config.cache_size)) }
+                .map_err(RedisError::String)?;
+        if !is_write_plan(&plan.plan) {
+            if ctx.get_flags().contains(ContextFlags::DENY_BLOCKING) {
+                unsafe { graph.read_with_gil(|g| profile_mut(ctx, g, &query, &config, false)) }?;
+                return RedisResult::Ok(RedisValue::NoReply);
+            }
+            let blocked_client = BlockedClient::new(ctx);
+            thread_pool(ctx).execute(move || {
+                blocked_client.reply(|ctx| {
+                    if let Err(err) = graph.read(|g| profile_mut(ctx, g, &query, &config, false)) {
+                        ctx.reply(Err(err));
+                    }
+                });
+            });
+            return RedisResult::Ok(RedisValue::NoReply);
+        }
+    }
+
+    write_graph(ctx, argv, &key_name, true, move |ctx, _, g| {
+        profile_mut(ctx, g, &query, &config, true)
+    })
+}
+
+fn reply_rows(
     ctx: &Context,
-    g: &RefCell<Graph>,
+    g: &GraphCell,
     return_names: &Vec<Variable>,
-    result: ResultSummary,
+    rows: Vec<Env>,
+    compact: bool,
 ) {
-    raw::reply_with_array(ctx.ctx, 3);
-    raw::reply_with_array(ctx.ctx, return_names.len() as _);
-    for name in return_names {
-        raw::reply_with_array(ctx.ctx, 2);
-        raw::reply_with_long_long(ctx.ctx, 1);
-        raw::reply_with_string_buffer(
-            ctx.ctx,
-            name.as_str().as_ptr().cast::<c_char>(),
-            name.as_str().len(),
-        );
-    }
-    raw::reply_with_array(ctx.ctx, result.result.len() as _);
-    for row in result.result {
+    let mut w = RespWriter::new(ctx);
+    raw::reply_with_array(ctx.ctx, rows.len() as _);
+    for row in rows {
         raw::reply_with_array(ctx.ctx, return_names.len() as _);
         for name in return_names {
-            reply_verbose_value(ctx, g, row.get(name).unwrap());
+            if compact {
+                raw::reply_with_array(ctx.ctx, 2);
+                reply_compact_value(ctx, g, row.get(name).unwrap());
+            } else {
+                write_value(&mut w, g, row.get(name).unwrap());
+            }
         }
     }
-    reply_stats(ctx, &result.stats);
 }
 
-fn reply_compact(
+/// Replies the header, the rows and the statistics of a query,
+/// followed by the id of its cursor when it was run with `CURSOR`.
+fn reply_result(
     ctx: &Context,
-    g: &RefCell<Graph>,
+    g: &GraphCell,
     return_names: &Vec<Variable>,
     result: ResultSummary,
+    compact: bool,
+    cursor: Option<u64>,
 ) {
-    raw::reply_with_array(ctx.ctx, 3);
+    raw::reply_with_array(ctx.ctx, if cursor.is_some() { 4 } else { 3 });
     raw::reply_with_array(ctx.ctx, return_names.len() as _);
     for name in return_names {
         raw::reply_with_array(ctx.ctx, 2);
@@ -669,15 +1187,264 @@ fn reply_compact(
             name.as_str().len(),
         );
     }
-    raw::reply_with_array(ctx.ctx, result.result.len() as _);
+    reply_rows(ctx, g, return_names, result.result, compact);
+    reply_stats(ctx, &result.stats);
+    if let Some(cursor) = cursor {
+        raw::reply_with_long_long(ctx.ctx, cursor as _);
+    }
+}
+
+/// Replies the columns, rows and statistics of a query as one JSON document:
+/// `{"columns": [...], "data": [[...], ...], "statistics": [...]}`.
+fn reply_json(
+    ctx: &Context,
+    g: &GraphCell,
+    return_names: &Vec<Variable>,
+    result: ResultSummary,
+) {
+    let mut w = JsonWriter::default();
+    w.map(3);
+    w.key("columns");
+    w.list(return_names.len());
+    for name in return_names {
+        w.string(name.as_str());
+    }
+    w.end_list();
+    w.key("data");
+    w.list(result.result.len());
     for row in result.result {
-        raw::reply_with_array(ctx.ctx, return_names.len() as _);
+        w.list(return_names.len());
         for name in return_names {
-            raw::reply_with_array(ctx.ctx, 2);
-            reply_compact_value(ctx, g, row.get(name).unwrap());
+            write_value(&mut w, g, row.get(name).unwrap());
         }
+        w.end_list();
+    }
+    w.end_list();
+    w.key("statistics");
+    let lines = stats_lines(&result.stats);
+    w.list(lines.len());
+    for line in &lines {
+        w.string(line);
+    }
+    w.end_list();
+    w.end_map();
+    let json = w.into_string();
+    raw::reply_with_string_buffer(ctx.ctx, json.as_ptr().cast::<c_char>(), json.len());
+}
+
+/// This function is used to copy a graph to a new key
+///
+/// See: <https://docs.falkordb.com/commands/graph.copy.html>
+///
+/// # Example
+///
+/// ```sh
+/// GRAPH.COPY graph graph_backup
+/// ```
+fn graph_copy(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let src = args.next_arg()?;
+    let dest = args.next_arg()?;
+    args.done()?;
+
+    let src = ctx.open_key(&src);
+    let Some(graph) = src.get_value::<Arc<GraphLock>>(&GRAPH_TYPE)? else {
+        return EMPTY_KEY_ERR;
+    };
+
+    let dest = ctx.open_key_writable(&dest);
+    if !dest.is_empty() {
+        return Err(RedisError::Str("ERR destination key already exists"));
+    }
+    let graph = unsafe { graph.read_with_gil(|g| g.borrow().dup()) };
+    dest.set_value(&GRAPH_TYPE, GraphLock::new(graph))?;
+    ctx.replicate_verbatim();
+
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
+/// This function is used to apply the changes of a write query on a replica
+///
+/// The payload encodes the entities created with their ids, the entities deleted,
+/// the labels and the properties set by the query on the primary,
+/// replaying them leaves the replica with the same graph without running the query.
+/// Only accepted while loading the AOF or from the primary,
+/// the ids of the payload are checked against the graph before it is changed.
+///
+/// # Example
+///
+/// ```sh
+/// GRAPH.EFFECT graph <payload>
+/// ```
+fn graph_effect(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let argv = args.iter().map(|arg| arg.as_slice().to_vec()).collect();
+    let mut args = args.into_iter().skip(1);
+    let key_name = args.next_arg()?;
+    let payload = args.next_arg()?.as_slice().to_vec();
+    args.done()?;
+
+    if !ctx
+        .get_flags()
+        .intersects(ContextFlags::LOADING | ContextFlags::REPLICATED)
+    {
+        return Err(RedisError::Str(
+            "ERR GRAPH.EFFECT is only accepted while loading the AOF or from the primary",
+        ));
+    }
+
+    write_graph(ctx, argv, &key_name, true, move |ctx, _, g| {
+        let stats = Pending::replay(g, &payload).map_err(RedisError::String)?;
+        ctx.reply(Ok(RedisValue::SimpleStringStatic("OK")));
+        Ok(Changes::new(Replication::Verbatim, &stats))
+    })
+}
+
+/// This function is used to load nodes and relationships without going through queries
+///
+/// `BEGIN` creates the graph, which must not exist, otherwise the graph is extended.
+/// The node and relationship counts are followed by the number of node and relationship batches
+/// and the batches themselves, see [`NodeBatch`] and [`RelationshipBatch`] for their encoding.
+/// Relationships connect nodes by id, the nodes of a load get consecutive ids on a graph
+/// without deleted nodes, starting at 0 for a graph created with `BEGIN`.
+///
+/// # Example
+///
+/// ```sh
+/// 127.0.0.1:6379> GRAPH.BULK graph BEGIN 2 1 1 1 <node batch> <relationship batch>
+/// "2 nodes created, 1 relations created"
+/// ```
+fn graph_bulk(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let argv = args.iter().map(|arg| arg.as_slice().to_vec()).collect();
+    let mut args = args.into_iter().skip(1).peekable();
+    let key_name = args.next_arg()?;
+    let begin = args
+        .next_if(|arg| arg.to_string_lossy().eq_ignore_ascii_case("BEGIN"))
+        .is_some();
+    let node_count = args.next_u64()?;
+    let relationship_count = args.next_u64()?;
+    let node_batch_count = args.next_u64()?;
+    let relationship_batch_count = args.next_u64()?;
+
+    let node_batches = (0..node_batch_count)
+        .map(|_| decode_batch(&args.next_arg()?, |dec| NodeBatch::decode(dec)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let relationship_batches = (0..relationship_batch_count)
+        .map(|_| decode_batch(&args.next_arg()?, |dec| RelationshipBatch::decode(dec)))
+        .collect::<Result<Vec<_>, _>>()?;
+    args.done()?;
+    let nodes = node_batches
+        .iter()
+        .map(|batch| batch.nodes.len() as u64)
+        .sum::<u64>();
+    let relationships = relationship_batches
+        .iter()
+        .map(|batch| batch.relationships.len() as u64)
+        .sum::<u64>();
+    if nodes != node_count || relationships != relationship_count {
+        return Err(RedisError::String(format!(
+            "ERR Bulk payload holds {nodes} nodes and {relationships} relations, expected {node_count} and {relationship_count}"
+        )));
+    }
+
+    // the graph of BEGIN is created without waiting, as the key is empty
+    if begin && !ctx.open_key(&key_name).is_empty() {
+        return Err(RedisError::String(format!(
+            "ERR Graph with name '{key_name}' cannot be created, as key '{key_name}' already exists."
+        )));
+    }
+    write_graph(ctx, argv, &key_name, begin, move |ctx, _, g| {
+        let stats = g
+            .borrow_mut()
+            .bulk_insert(&node_batches, &relationship_batches)
+            .map_err(RedisError::String)?;
+        ctx.reply(Ok(RedisValue::SimpleString(format!(
+            "{} nodes created, {} relations created",
+            stats.nodes_created, stats.relationships_created
+        ))));
+        Ok(Changes::new(Replication::Verbatim, &stats))
+    })
+}
+
+/// Decodes a `GRAPH.BULK` batch, failing unless `decode` reads the whole payload.
+fn decode_batch<T>(
+    payload: &RedisString,
+    decode: impl FnOnce(&mut SliceDecoder) -> Result<T, String>,
+) -> Result<T, RedisError> {
+    let mut dec = SliceDecoder::new(payload.as_slice());
+    let batch = decode(&mut dec).map_err(RedisError::String)?;
+    if !dec.is_empty() {
+        return Err(RedisError::Str("ERR Unexpected data after bulk batch"));
+    }
+    Ok(batch)
+}
+
+/// Replays a graph emitted by the AOF rewrite.
+///
+/// `CHUNK` appends to a staging buffer for the key and
+/// `COMMIT` decodes the buffer and replaces the key value.
+/// Only accepted while loading the AOF or from the primary, the staged size is not bounded
+/// as a graph rewritten to the AOF may be larger than any fixed limit.
+/// Staging buffers left by a truncated AOF are dropped when loading ends.
+///
+/// # Example
+///
+/// ```sh
+/// GRAPH.RESTORE graph CHUNK <payload>
+/// GRAPH.RESTORE graph COMMIT 1
+/// ```
+fn graph_restore(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    let subcommand = args.next_str()?;
+    let key_name = key.to_string_lossy();
+
+    if !ctx
+        .get_flags()
+        .intersects(ContextFlags::LOADING | ContextFlags::REPLICATED)
+    {
+        return Err(RedisError::Str(
+            "ERR GRAPH.RESTORE is only accepted while loading the AOF or from the primary",
+        ));
+    }
+
+    match subcommand.to_uppercase().as_str() {
+        "CHUNK" => {
+            let payload = args.next_arg()?;
+            args.done()?;
+            PENDING_RESTORES
+                .lock(ctx)
+                .entry(key_name)
+                .or_default()
+                .extend_from_slice(payload.as_slice());
+            Ok(RedisValue::SimpleStringStatic("OK"))
+        }
+        "COMMIT" => {
+            let version = args.next_u64()?;
+            args.done()?;
+            let buf = PENDING_RESTORES
+                .lock(ctx)
+                .remove(&key_name)
+                .unwrap_or_default();
+            let graph =
+                Graph::decode(&mut SliceDecoder::new(&buf), version).map_err(RedisError::String)?;
+            let key = ctx.open_key_writable(&key);
+            key.set_value(&GRAPH_TYPE, GraphLock::new(graph))?;
+            Ok(RedisValue::SimpleStringStatic("OK"))
+        }
+        _ => Err(RedisError::Str("ERR Unknown GRAPH.RESTORE subcommand")),
     }
-    reply_stats(ctx, &result.stats);
 }
 
 /// This function is used to execute a read only query on a graph
@@ -696,19 +1463,53 @@ fn graph_ro_query(
     let mut args = args.into_iter().skip(1);
     let key = args.next_arg()?;
     let query = args.next_str()?;
-    let compact = args.next_str().is_ok_and(|arg| arg == "--compact");
+    let args = parse_query_args(ctx, args)?;
+    let config = QueryConfig::new(ctx, args.timeout);
 
     let key = ctx.open_key(&key);
 
     // We check if the key exists and is of type Graph if wrong type `get_value` return an error
-    (key.get_value::<RefCell<Graph>>(&GRAPH_TYPE)?).map_or(
+    let Some(graph) = key.get_value::<Arc<GraphLock>>(&GRAPH_TYPE)? else {
         // If the key does not exist, we return an error
-        EMPTY_KEY_ERR,
-        |graph| {
-            query_mut(ctx, graph, query, compact, false)?;
-            RedisResult::Ok(RedisValue::NoReply)
-        },
-    )
+        return EMPTY_KEY_ERR;
+    };
+
+    // clients that can not be blocked, like inside MULTI or scripts, run the query inline
+    if ctx.get_flags().contains(ContextFlags::DENY_BLOCKING) {
+        unsafe {
+            graph.read_with_gil(|g| query_mut(ctx, &graph, g, query, &args, &config, false))
+        }?;
+        return RedisResult::Ok(RedisValue::NoReply);
+    }
+
+    let graph = graph.clone();
+    let query = query.to_string();
+    let blocked_client = BlockedClient::new(ctx);
+    thread_pool(ctx).execute(move || {
+        blocked_client.reply(|ctx| {
+            if let Err(err) =
+                graph.read(|g| query_mut(ctx, &graph, g, &query, &args, &config, false))
+            {
+                ctx.reply(Err(err));
+            }
+        });
+    });
+    RedisResult::Ok(RedisValue::NoReply)
+}
+
+static THREAD_POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// The worker threads running read-only queries, started on first use.
+fn thread_pool(ctx: &Context) -> &'static ThreadPool {
+    THREAD_POOL.get_or_init(|| ThreadPool::new(thread_count(ctx)))
+}
+
+/// `THREAD_COUNT`, or one thread per core when it is 0.
+fn thread_count(ctx: &Context) -> usize {
+    match usize::try_from(*CONFIGURATION_THREAD_COUNT.lock(ctx)).unwrap_or(0) {
+        0 => available_parallelism().map_or(1, NonZero::get),
+        threads => threads,
+    }
 }
 
 /// This function is used to list all the graphs
@@ -791,6 +1592,442 @@ fn graph_plan(
     }
 }
 
+/// This function is used to show the execution plan of a query on a graph
+/// without running it
+///
+/// See: <https://docs.falkordb.com/commands/graph.explain.html>
+///
+/// # Example
+///
+/// ```sh
+/// GRAPH.EXPLAIN graph "MATCH (n:Person) WHERE n.age > 30 RETURN n"
+/// ```
+fn graph_explain(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    let query = args.next_str()?;
+    args.done()?;
+
+    let key = ctx.open_key(&key);
+
+    (key.get_value::<Arc<GraphLock>>(&GRAPH_TYPE)?).map_or(EMPTY_KEY_ERR, |graph| unsafe {
+        graph.read_with_gil(|g| {
+            let g = g.borrow();
+            let Plan { plan, .. } = g
+                .get_plan(query, plan_cache_size(ctx))
+                .map_err(RedisError::String)?;
+            Ok(RedisValue::Array(
+                explain(&plan, &g)
+                    .into_iter()
+                    .map(RedisValue::BulkString)
+                    .collect(),
+            ))
+        })
+    })
+}
+
+/// The configurations exposed through `GRAPH.CONFIG`.
+const CONFIGURATIONS: [&str; 9] = [
+    "IMPORT_FOLDER",
+    "TIMEOUT_DEFAULT",
+    "QUERY_MEM_CAPACITY",
+    "RESULTSET_SIZE",
+    "CACHE_SIZE",
+    "INITIAL_NODE_CAPACITY",
+    "INITIAL_RELATIONSHIP_CAPACITY",
+    "THREAD_COUNT",
+    "CURSOR_MAX_IDLE",
+];
+
+fn configuration_value(
+    ctx: &Context,
+    name: &str,
+) -> RedisValue {
+    let value = match name {
+        "IMPORT_FOLDER" => {
+            return RedisValue::BulkString(CONFIGURATION_IMPORT_FOLDER.lock(ctx).clone());
+        }
+        "TIMEOUT_DEFAULT" => *CONFIGURATION_TIMEOUT_DEFAULT.lock(ctx),
+        "QUERY_MEM_CAPACITY" => *CONFIGURATION_QUERY_MEM_CAPACITY.lock(ctx),
+        "RESULTSET_SIZE" => *CONFIGURATION_RESULTSET_SIZE.lock(ctx),
+        "CACHE_SIZE" => *CONFIGURATION_CACHE_SIZE.lock(ctx),
+        "INITIAL_NODE_CAPACITY" => *CONFIGURATION_INITIAL_NODE_CAPACITY.lock(ctx),
+        "INITIAL_RELATIONSHIP_CAPACITY" => *CONFIGURATION_INITIAL_RELATIONSHIP_CAPACITY.lock(ctx),
+        "THREAD_COUNT" => *CONFIGURATION_THREAD_COUNT.lock(ctx),
+        "CURSOR_MAX_IDLE" => *CONFIGURATION_CURSOR_MAX_IDLE.lock(ctx),
+        _ => unreachable!("unknown configuration {name}"),
+    };
+    RedisValue::Integer(value)
+}
+
+fn find_configuration(name: &str) -> Result<&'static str, RedisError> {
+    CONFIGURATIONS
+        .into_iter()
+        .find(|c| c.eq_ignore_ascii_case(name))
+        .ok_or(RedisError::Str("ERR Unknown configuration field"))
+}
+
+/// This function is used to read the rows of a query run with `CURSOR <count>`
+///
+/// `READ` replies with the next batch of rows, `COUNT` rows by default as many as the first batch,
+/// and with the id to read the following batch from, 0 once the rows are exhausted.
+/// `DEL` releases a cursor before its rows are exhausted.
+/// A cursor is only known to the client that ran its query, and a write to the graph invalidates it.
+/// It is dropped when its client disconnects or after `CURSOR_MAX_IDLE` milliseconds without a read.
+///
+/// # Example
+///
+/// ```sh
+/// 127.0.0.1:6379> GRAPH.QUERY graph "UNWIND range(1, 3) AS x RETURN x" CURSOR 2
+/// 1) 1) 1) (integer) 1
+///       2) "x"
+/// 2) 1) 1) (integer) 1
+///    2) 1) (integer) 2
+/// 3) 1) "Query internal execution time: 0.120000 milliseconds"
+/// 4) (integer) 1
+/// 127.0.0.1:6379> GRAPH.CURSOR READ graph 1 COUNT 2
+/// 1) 1) 1) (integer) 3
+/// 2) (integer) 0
+/// ```
+fn graph_cursor(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let subcommand = args.next_str()?;
+    let key = args.next_arg()?;
+    let id = args.next_u64()?;
+
+    let key = ctx.open_key(&key);
+    let Some(graph) = key.get_value::<Arc<GraphLock>>(&GRAPH_TYPE)? else {
+        return EMPTY_KEY_ERR;
+    };
+    let client = ctx.get_client_id();
+
+    if subcommand.eq_ignore_ascii_case("READ") {
+        let Some(cursor) = take_cursor(client, graph, id) else {
+            return Err(RedisError::Str("ERR Unknown cursor"));
+        };
+        let mut count = cursor.count;
+        while let Ok(arg) = args.next_str() {
+            if arg.eq_ignore_ascii_case("COUNT") {
+                count = parse_cursor_count(&mut args)?;
+            } else {
+                return Err(RedisError::Str("ERR syntax error"));
+            }
+        }
+
+        // clients that can not be blocked, like inside MULTI or scripts, read inline
+        if ctx.get_flags().contains(ContextFlags::DENY_BLOCKING) {
+            read_cursor(ctx, client, id, cursor, count)?;
+            return Ok(RedisValue::NoReply);
+        }
+
+        let blocked_client = BlockedClient::new(ctx);
+        thread_pool(ctx).execute(move || {
+            blocked_client.reply(|ctx| {
+                if let Err(err) = read_cursor(ctx, client, id, cursor, count) {
+                    ctx.reply(Err(err));
+                }
+            });
+        });
+        Ok(RedisValue::NoReply)
+    } else if subcommand.eq_ignore_ascii_case("DEL") {
+        args.done()?;
+        take_cursor(client, graph, id).map_or(Err(RedisError::Str("ERR Unknown cursor")), |_| {
+            Ok(RedisValue::SimpleStringStatic("OK"))
+        })
+    } else {
+        Err(RedisError::String(format!(
+            "ERR Unknown subcommand '{subcommand}' for GRAPH.CURSOR"
+        )))
+    }
+}
+
+/// Replies with the next `count` rows of the cursor `id` of `client`,
+/// and keeps it if it is not exhausted.
+fn read_cursor(
+    ctx: &Context,
+    client: u64,
+    id: u64,
+    mut cursor: Cursor,
+    count: usize,
+) -> Result<(), RedisError> {
+    let exhausted = cursor
+        .read(|cursor, g| {
+            let rows = cursor.next_batch(count)?;
+            let exhausted = rows.len() < count;
+            raw::reply_with_array(ctx.ctx, 2);
+            reply_rows(ctx, g, &cursor.runtime().return_names, rows, cursor.compact);
+            raw::reply_with_long_long(ctx.ctx, if exhausted { 0 } else { id as _ });
+            Ok::<_, String>(exhausted)
+        })
+        .and_then(|res| res)
+        .map_err(RedisError::String)?;
+    if !exhausted {
+        restore_cursor(client, id, cursor);
+    }
+    Ok(())
+}
+
+/// The number of changes kept when `GRAPH.CHANGES ENABLE` has no `CAPACITY`.
+const DEFAULT_CHANGES_CAPACITY: usize = 10_000;
+
+/// Replies a captured change as an array of its sequence number, its kind and its fields.
+fn reply_change(
+    ctx: &Context,
+    g: &GraphCell,
+    seq: u64,
+    change: &Change,
+) {
+    let mut w = RespWriter::new(ctx);
+    let len = match change {
+        Change::NodeCreated(_) | Change::NodeDeleted(_) => 3,
+        Change::LabelAdded(_, _) | Change::LabelRemoved(_, _) => 4,
+        Change::RelationshipDeleted { .. } => 5,
+        Change::RelationshipCreated { .. }
+        | Change::NodePropertySet { .. }
+        | Change::RelationshipPropertySet { .. } => 6,
+    };
+    raw::reply_with_array(ctx.ctx, len);
+    raw::reply_with_long_long(ctx.ctx, seq as _);
+    match change {
+        Change::NodeCreated(id) => {
+            w.string("node_created");
+            w.int(u64::from(*id) as _);
+        }
+        Change::NodeDeleted(id) => {
+            w.string("node_deleted");
+            w.int(u64::from(*id) as _);
+        }
+        Change::RelationshipCreated {
+            id,
+            type_name,
+            from,
+            to,
+        } => {
+            w.string("relationship_created");
+            w.int(u64::from(*id) as _);
+            w.string(type_name);
+            w.int(u64::from(*from) as _);
+            w.int(u64::from(*to) as _);
+        }
+        Change::RelationshipDeleted { id, from, to } => {
+            w.string("relationship_deleted");
+            w.int(u64::from(*id) as _);
+            w.int(u64::from(*from) as _);
+            w.int(u64::from(*to) as _);
+        }
+        Change::LabelAdded(id, label) => {
+            w.string("label_added");
+            w.int(u64::from(*id) as _);
+            w.string(label);
+        }
+        Change::LabelRemoved(id, label) => {
+            w.string("label_removed");
+            w.int(u64::from(*id) as _);
+            w.string(label);
+        }
+        Change::NodePropertySet { id, key, old, new } => {
+            w.string("node_property_set");
+            w.int(u64::from(*id) as _);
+            w.string(key);
+            write_value(&mut w, g, old.clone());
+            write_value(&mut w, g, new.clone());
+        }
+        Change::RelationshipPropertySet { id, key, old, new } => {
+            w.string("relationship_property_set");
+            w.int(u64::from(*id) as _);
+            w.string(key);
+            write_value(&mut w, g, old.clone());
+            write_value(&mut w, g, new.clone());
+        }
+    }
+}
+
+/// This function is used to capture the changes committed to a graph
+///
+/// `ENABLE` starts keeping the latest `CAPACITY` changes, 10000 by default,
+/// `DISABLE` stops and drops the kept changes.
+/// `READ` replies with up to `COUNT` changes following the change numbered `FROM`, oldest first.
+/// Each change is an array of its sequence number, its kind and its fields:
+/// `node_created` and `node_deleted` with the node id,
+/// `relationship_created` with the relationship id, type, source and destination node ids,
+/// `relationship_deleted` with the relationship id, source and destination node ids,
+/// `label_added` and `label_removed` with the node id and label,
+/// `node_property_set` and `relationship_property_set` with the entity id, the property,
+/// its old and its new value, null when the property was absent or removed.
+/// The captured changes live in memory only and are not persisted,
+/// `ENABLE` and `DISABLE` are replicated so replicas capture the changes they apply too,
+/// and `READ` does not write, so it is served by read-only replicas and when writes are denied.
+///
+/// # Example
+///
+/// ```sh
+/// 127.0.0.1:6379> GRAPH.CHANGES graph ENABLE CAPACITY 1000
+/// OK
+/// 127.0.0.1:6379> GRAPH.QUERY graph "CREATE (:Person {name: 'Alice'})"
+/// 127.0.0.1:6379> GRAPH.CHANGES graph READ FROM 0 COUNT 10
+/// 1) 1) (integer) 1
+///    2) "node_created"
+///    3) (integer) 0
+/// 2) 1) (integer) 2
+///    2) "label_added"
+///    3) (integer) 0
+///    4) "Person"
+/// 3) 1) (integer) 3
+///    2) "node_property_set"
+///    3) (integer) 0
+///    4) "name"
+///    5) (nil)
+///    6) "Alice"
+/// ```
+fn graph_changes(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let argv = args.iter().map(|arg| arg.as_slice().to_vec()).collect();
+    let mut args = args.into_iter().skip(1);
+    let key_name = args.next_arg()?;
+    let subcommand = args.next_str()?;
+
+    if subcommand.eq_ignore_ascii_case("ENABLE") {
+        let mut capacity = DEFAULT_CHANGES_CAPACITY;
+        while let Ok(arg) = args.next_str() {
+            if arg.eq_ignore_ascii_case("CAPACITY") {
+                capacity = args
+                    .next_i64()
+                    .ok()
+                    .and_then(|capacity| usize::try_from(capacity).ok())
+                    .filter(|capacity| *capacity > 0)
+                    .ok_or(RedisError::Str("ERR Failed to parse CAPACITY value"))?;
+            } else {
+                return Err(RedisError::Str("ERR syntax error"));
+            }
+        }
+        write_graph(ctx, argv, &key_name, false, move |ctx, _, g| {
+            g.borrow_mut().enable_change_capture(capacity);
+            ctx.reply(Ok(RedisValue::SimpleStringStatic("OK")));
+            Ok(Changes::new(
+                Replication::Verbatim,
+                &QueryStatistics::default(),
+            ))
+        })
+    } else if subcommand.eq_ignore_ascii_case("DISABLE") {
+        args.done()?;
+        write_graph(ctx, argv, &key_name, false, |ctx, _, g| {
+            g.borrow_mut().disable_change_capture();
+            ctx.reply(Ok(RedisValue::SimpleStringStatic("OK")));
+            Ok(Changes::new(
+                Replication::Verbatim,
+                &QueryStatistics::default(),
+            ))
+        })
+    } else if subcommand.eq_ignore_ascii_case("READ") {
+        let mut after = 0;
+        let mut count = usize::MAX;
+        while let Ok(arg) = args.next_str() {
+            if arg.eq_ignore_ascii_case("FROM") {
+                after = args.next_u64()?;
+            } else if arg.eq_ignore_ascii_case("COUNT") {
+                count = parse_cursor_count(&mut args)?;
+            } else {
+                return Err(RedisError::Str("ERR syntax error"));
+            }
+        }
+        let key = ctx.open_key(&key_name);
+        let Some(graph) = key.get_value::<Arc<GraphLock>>(&GRAPH_TYPE)? else {
+            return EMPTY_KEY_ERR;
+        };
+        unsafe {
+            graph.read_with_gil(|g| {
+                let bg = g.borrow();
+                let Some(change_log) = bg.change_log() else {
+                    return Err(RedisError::Str(
+                        "ERR Change capture is not enabled on this graph",
+                    ));
+                };
+                let changes = change_log.read(after, count).collect::<Vec<_>>();
+                raw::reply_with_array(ctx.ctx, changes.len() as _);
+                for (seq, change) in changes {
+                    reply_change(ctx, g, *seq, change);
+                }
+                Ok(RedisValue::NoReply)
+            })
+        }
+    } else {
+        Err(RedisError::String(format!(
+            "ERR Unknown subcommand '{subcommand}' for GRAPH.CHANGES"
+        )))
+    }
+}
+
+/// This function is used to read and update the module configurations at runtime
+///
+/// `GET` replies with the name and value of a configuration, or with all of them for `*`.
+/// `SET` goes through `CONFIG SET` so values are validated against the configuration bounds,
+/// the new values are used by the next query.
+///
+/// # Example
+///
+/// ```sh
+/// 127.0.0.1:6379> GRAPH.CONFIG GET TIMEOUT_DEFAULT
+/// 1) "TIMEOUT_DEFAULT"
+/// 2) (integer) 0
+/// 127.0.0.1:6379> GRAPH.CONFIG SET TIMEOUT_DEFAULT 1000
+/// OK
+/// ```
+fn graph_config(
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let subcommand = args.next_str()?;
+
+    if subcommand.eq_ignore_ascii_case("GET") {
+        let name = args.next_str()?;
+        args.done()?;
+        if name == "*" {
+            return Ok(RedisValue::Array(
+                CONFIGURATIONS
+                    .into_iter()
+                    .map(|name| {
+                        RedisValue::Array(vec![
+                            RedisValue::SimpleStringStatic(name),
+                            configuration_value(ctx, name),
+                        ])
+                    })
+                    .collect(),
+            ));
+        }
+        let name = find_configuration(name)?;
+        Ok(RedisValue::Array(vec![
+            RedisValue::SimpleStringStatic(name),
+            configuration_value(ctx, name),
+        ]))
+    } else if subcommand.eq_ignore_ascii_case("SET") {
+        let mut config_args = vec![String::from("SET")];
+        while let Ok(name) = args.next_str() {
+            let name = find_configuration(name)?;
+            config_args.push(format!("falkordb.{name}"));
+            config_args.push(args.next_str()?.to_string());
+        }
+        if config_args.len() == 1 {
+            return Err(RedisError::WrongArity);
+        }
+        let config_args = config_args.iter().map(String::as_str).collect::<Vec<_>>();
+        ctx.call("CONFIG", config_args.as_slice())?;
+        Ok(RedisValue::SimpleStringStatic("OK"))
+    } else {
+        Err(RedisError::String(format!(
+            "ERR Unknown subcommand '{subcommand}' for GRAPH.CONFIG"
+        )))
+    }
+}
+
 #[cfg(feature = "zipkin")]
 fn init_zipkin() {
     global::set_text_map_propagator(opentelemetry_zipkin::Propagator::new());
@@ -822,12 +2059,29 @@ fn init_zipkin() {
 }
 
 fn graph_init(
-    _: &Context,
+    ctx: &Context,
     _: &Vec<RedisString>,
 ) -> Status {
     #[cfg(feature = "zipkin")]
     init_zipkin();
 
+    let events: [(_, raw::RedisModuleEventCallback); 3] = [
+        (raw::REDISMODULE_EVENT_LOADING, Some(on_loading_event)),
+        (raw::REDISMODULE_EVENT_CLIENT_CHANGE, Some(on_client_change)),
+        (raw::REDISMODULE_EVENT_CRON_LOOP, Some(on_cron_loop)),
+    ];
+    for (id, callback) in events {
+        let event = raw::RedisModuleEvent {
+            id: u64::from(id),
+            dataver: 1,
+        };
+        if unsafe { raw::RedisModule_SubscribeToServerEvent.unwrap()(ctx.ctx, event, callback) }
+            != raw::REDISMODULE_OK as i32
+        {
+            return Status::Err;
+        }
+    }
+
     unsafe {
         init(
             RedisModule_Alloc,
@@ -845,13 +2099,56 @@ fn graph_init(
 lazy_static! {
     static ref CONFIGURATION_IMPORT_FOLDER: RedisGILGuard<String> =
         RedisGILGuard::new("/var/lib/FalkorDB/import/".into());
+    static ref CONFIGURATION_TIMEOUT_DEFAULT: RedisGILGuard<i64> = RedisGILGuard::new(0);
+    static ref CONFIGURATION_QUERY_MEM_CAPACITY: RedisGILGuard<i64> = RedisGILGuard::new(0);
+    static ref CONFIGURATION_RESULTSET_SIZE: RedisGILGuard<i64> = RedisGILGuard::new(-1);
+    static ref CONFIGURATION_CACHE_SIZE: RedisGILGuard<i64> = RedisGILGuard::new(25);
+    static ref CONFIGURATION_INITIAL_NODE_CAPACITY: RedisGILGuard<i64> = RedisGILGuard::new(16384);
+    static ref CONFIGURATION_INITIAL_RELATIONSHIP_CAPACITY: RedisGILGuard<i64> =
+        RedisGILGuard::new(16384);
+    static ref CONFIGURATION_THREAD_COUNT: RedisGILGuard<i64> = RedisGILGuard::new(0);
+    static ref CONFIGURATION_CURSOR_MAX_IDLE: RedisGILGuard<i64> = RedisGILGuard::new(300_000);
+    static ref PENDING_RESTORES: RedisGILGuard<HashMap<String, Vec<u8>>> =
+        RedisGILGuard::new(HashMap::new());
 }
 
-fn on_configuration_changed<T: ConfigurationValue<String>>(
-    _config_ctx: &ConfigurationContext,
-    _name: &str,
-    _val: &'static T,
+/// Drops the `GRAPH.RESTORE` staging buffers when loading starts or ends,
+/// so a truncated AOF does not leave them behind.
+unsafe extern "C" fn on_loading_event(
+    ctx: *mut raw::RedisModuleCtx,
+    _eid: raw::RedisModuleEvent,
+    _subevent: u64,
+    _data: *mut c_void,
 ) {
+    let ctx = Context::new(ctx);
+    PENDING_RESTORES.lock(&ctx).clear();
+}
+
+/// Drops the cursors of a client when it disconnects.
+unsafe extern "C" fn on_client_change(
+    _ctx: *mut raw::RedisModuleCtx,
+    _eid: raw::RedisModuleEvent,
+    subevent: u64,
+    data: *mut c_void,
+) {
+    if subevent == u64::from(raw::REDISMODULE_SUBEVENT_CLIENT_CHANGE_DISCONNECTED) {
+        let info = unsafe { &*data.cast::<raw::RedisModuleClientInfo>() };
+        drop_client_cursors(info.id);
+    }
+}
+
+/// Drops the cursors idle for longer than `CURSOR_MAX_IDLE` milliseconds, 0 keeps them.
+unsafe extern "C" fn on_cron_loop(
+    ctx: *mut raw::RedisModuleCtx,
+    _eid: raw::RedisModuleEvent,
+    _subevent: u64,
+    _data: *mut c_void,
+) {
+    let ctx = Context::new(ctx);
+    let max_idle = CONFIGURATION_CURSOR_MAX_IDLE.lock(&ctx).unsigned_abs();
+    if max_idle > 0 {
+        expire_cursors(Duration::from_millis(max_idle));
+    }
 }
 
 //////////////////////////////////////////////////////
@@ -869,12 +2166,30 @@ redis_module! {
         ["graph.LIST", graph_list, "readonly", 0, 0, 0, ""],
         ["graph.PARSE", graph_parse, "readonly", 0, 0, 0, ""],
         ["graph.PLAN", graph_plan, "readonly", 0, 0, 0, ""],
+        ["graph.EXPLAIN", graph_explain, "readonly", 1, 1, 1, ""],
         ["graph.RECORD", graph_record, "write deny-oom", 1, 1, 1, ""],
+        ["graph.PROFILE", graph_profile, "write deny-oom", 1, 1, 1, ""],
+        ["graph.COPY", graph_copy, "write deny-oom", 1, 2, 1, ""],
+        ["graph.RESTORE", graph_restore, "write deny-oom", 1, 1, 1, ""],
+        ["graph.EFFECT", graph_effect, "write", 1, 1, 1, ""],
+        ["graph.BULK", graph_bulk, "write deny-oom", 1, 1, 1, ""],
+        ["graph.CONFIG", graph_config, "admin noscript may-replicate", 0, 0, 0, ""],
+        ["graph.CURSOR", graph_cursor, "readonly", 2, 2, 1, ""],
+        ["graph.CHANGES", graph_changes, "may-replicate", 1, 1, 1, ""],
     ],
     configurations: [
-        i64: [],
+        i64: [
+            ["TIMEOUT_DEFAULT", &*CONFIGURATION_TIMEOUT_DEFAULT, 0, 0, i64::MAX, ConfigurationFlags::DEFAULT, None],
+            ["QUERY_MEM_CAPACITY", &*CONFIGURATION_QUERY_MEM_CAPACITY, 0, 0, i64::MAX, ConfigurationFlags::DEFAULT, None],
+            ["RESULTSET_SIZE", &*CONFIGURATION_RESULTSET_SIZE, -1, -1, i64::MAX, ConfigurationFlags::DEFAULT, None],
+            ["CACHE_SIZE", &*CONFIGURATION_CACHE_SIZE, 25, 0, 512, ConfigurationFlags::DEFAULT, None],
+            ["INITIAL_NODE_CAPACITY", &*CONFIGURATION_INITIAL_NODE_CAPACITY, 16384, 1, i64::from(u32::MAX), ConfigurationFlags::DEFAULT, None],
+            ["INITIAL_RELATIONSHIP_CAPACITY", &*CONFIGURATION_INITIAL_RELATIONSHIP_CAPACITY, 16384, 1, i64::from(u32::MAX), ConfigurationFlags::DEFAULT, None],
+            ["THREAD_COUNT", &*CONFIGURATION_THREAD_COUNT, 0, 0, 1024, ConfigurationFlags::IMMUTABLE, None],
+            ["CURSOR_MAX_IDLE", &*CONFIGURATION_CURSOR_MAX_IDLE, 300_000, 0, i64::MAX, ConfigurationFlags::DEFAULT, None],
+        ],
         string: [
-            ["IMPORT_FOLDER", &*CONFIGURATION_IMPORT_FOLDER, "/var/lib/FalkorDB/import/", ConfigurationFlags::DEFAULT, Some(Box::new(on_configuration_changed))],
+            ["IMPORT_FOLDER", &*CONFIGURATION_IMPORT_FOLDER, "/var/lib/FalkorDB/import/", ConfigurationFlags::DEFAULT, None],
         ],
         bool: [],
         enum: [],
@@ -0,0 +1,463 @@
+use graph::{graph::graph::GraphCell, runtime::value::Value};
+use redis_module::{Context, ContextFlags, raw};
+use std::{fmt::Write, os::raw::c_char};
+
+/// Receives the parts of a value walked by [`write_value`].
+///
+/// Lists, maps, properties and entities are opened with their length
+/// and closed once their items were written.
+pub trait ValueWriter {
+    fn null(&mut self);
+    fn bool(
+        &mut self,
+        x: bool,
+    );
+    fn int(
+        &mut self,
+        x: i64,
+    );
+    fn float(
+        &mut self,
+        x: f64,
+    );
+    fn string(
+        &mut self,
+        x: &str,
+    );
+    fn list(
+        &mut self,
+        len: usize,
+    );
+    fn end_list(&mut self);
+    /// Opens a map of `len` keys, each written with [`ValueWriter::key`] before its value.
+    fn map(
+        &mut self,
+        len: usize,
+    );
+    fn key(
+        &mut self,
+        key: &str,
+    );
+    fn end_map(&mut self);
+    /// Opens the `len` properties of a node or relationship,
+    /// each written with [`ValueWriter::property`] before its value.
+    fn properties(
+        &mut self,
+        len: usize,
+    );
+    fn property(
+        &mut self,
+        key: &str,
+    );
+    fn end_properties(&mut self);
+    /// Opens a node or relationship of `len` fields,
+    /// each written with [`ValueWriter::field`] before its value.
+    fn entity(
+        &mut self,
+        len: usize,
+    );
+    fn field(
+        &mut self,
+        name: &str,
+    );
+    fn end_entity(&mut self);
+}
+
+/// Writes `r` to `w`, resolving the labels, types and properties of graph entities.
+pub fn write_value(
+    w: &mut impl ValueWriter,
+    g: &GraphCell,
+    r: Value,
+) {
+    match r {
+        Value::Null => w.null(),
+        Value::Bool(x) => w.bool(x),
+        Value::Int(x) => w.int(x),
+        Value::Float(x) => w.float(x),
+        Value::String(x) => w.string(&x),
+        Value::List(values) => {
+            w.list(values.len());
+            for v in values {
+                write_value(w, g, v.clone());
+            }
+            w.end_list();
+        }
+        Value::Map(map) => {
+            w.map(map.len());
+            for (key, value) in map.iter() {
+                w.key(key);
+                write_value(w, g, value.clone());
+            }
+            w.end_map();
+        }
+        Value::Node(id) => {
+            w.entity(3);
+            w.field("id");
+            w.int(u64::from(id) as _);
+            w.field("labels");
+            let labels = g.borrow().get_node_label_ids(id).collect::<Vec<_>>();
+            w.list(labels.len());
+            for label in labels {
+                w.string(&g.borrow().get_label_by_id(label));
+            }
+            w.end_list();
+            w.field("properties");
+            let bg = g.borrow();
+            let props = bg.get_node_attrs(id);
+            w.properties(props.len());
+            for (key, value) in props {
+                w.property(&bg.get_node_attribute_string(*key).unwrap());
+                write_value(w, g, value.clone());
+            }
+            w.end_properties();
+            w.end_entity();
+        }
+        Value::Relationship(id, from, to) => {
+            w.entity(5);
+            w.field("id");
+            w.int(u64::from(id) as _);
+            w.field("type");
+            let rel_type = g
+                .borrow()
+                .get_type(g.borrow().get_relationship_type_id(id))
+                .unwrap();
+            w.string(&rel_type);
+            w.field("src_node");
+            w.int(u64::from(from) as _);
+            w.field("dest_node");
+            w.int(u64::from(to) as _);
+            w.field("properties");
+            let bg = g.borrow();
+            let props = bg.get_relationship_attrs(id);
+            w.properties(props.len());
+            for (key, value) in props {
+                w.property(&bg.get_relationship_attribute_string(*key).unwrap());
+                write_value(w, g, value.clone());
+            }
+            w.end_properties();
+            w.end_entity();
+        }
+        Value::Path(path) => {
+            w.list(path.len());
+            for node in path {
+                match node {
+                    Value::Relationship(_, _, _) | Value::Node(_) => {
+                        write_value(w, g, node.clone());
+                    }
+                    _ => unreachable!("Path should only contain nodes and relationships"),
+                }
+            }
+            w.end_list();
+        }
+        Value::Arc(inner) => write_value(w, g, (*inner).clone()),
+    }
+}
+
+/// Replies values in the verbose format.
+///
+/// With RESP3 booleans, floats and maps are replied with their native types,
+/// with RESP2 booleans and floats are replied as strings and maps as flat arrays.
+pub struct RespWriter<'a> {
+    ctx: &'a Context,
+    resp3: bool,
+}
+
+impl<'a> RespWriter<'a> {
+    /// Creates a writer for the protocol the client negotiated with `HELLO`.
+    #[must_use]
+    pub fn new(ctx: &'a Context) -> Self {
+        Self {
+            ctx,
+            resp3: ctx.get_flags().contains(ContextFlags::RESP3),
+        }
+    }
+}
+
+impl ValueWriter for RespWriter<'_> {
+    fn null(&mut self) {
+        raw::reply_with_null(self.ctx.ctx);
+    }
+
+    fn bool(
+        &mut self,
+        x: bool,
+    ) {
+        if self.resp3 {
+            raw::reply_with_bool(self.ctx.ctx, x.into());
+        } else {
+            self.string(if x { "true" } else { "false" });
+        }
+    }
+
+    fn int(
+        &mut self,
+        x: i64,
+    ) {
+        raw::reply_with_long_long(self.ctx.ctx, x as _);
+    }
+
+    fn float(
+        &mut self,
+        x: f64,
+    ) {
+        if self.resp3 {
+            raw::reply_with_double(self.ctx.ctx, x);
+        } else {
+            self.string(&format!("{x:.14e}"));
+        }
+    }
+
+    fn string(
+        &mut self,
+        x: &str,
+    ) {
+        raw::reply_with_string_buffer(self.ctx.ctx, x.as_ptr().cast::<c_char>(), x.len());
+    }
+
+    fn list(
+        &mut self,
+        len: usize,
+    ) {
+        raw::reply_with_array(self.ctx.ctx, len as _);
+    }
+
+    fn end_list(&mut self) {}
+
+    fn map(
+        &mut self,
+        len: usize,
+    ) {
+        if self.resp3 {
+            raw::reply_with_map(self.ctx.ctx, len as _);
+        } else {
+            raw::reply_with_array(self.ctx.ctx, (len * 2) as _);
+        }
+    }
+
+    fn key(
+        &mut self,
+        key: &str,
+    ) {
+        self.string(key);
+    }
+
+    fn end_map(&mut self) {}
+
+    fn properties(
+        &mut self,
+        len: usize,
+    ) {
+        if self.resp3 {
+            raw::reply_with_map(self.ctx.ctx, len as _);
+        } else {
+            raw::reply_with_array(self.ctx.ctx, len as _);
+        }
+    }
+
+    fn property(
+        &mut self,
+        key: &str,
+    ) {
+        if !self.resp3 {
+            raw::reply_with_array(self.ctx.ctx, 2);
+        }
+        self.string(key);
+    }
+
+    fn end_properties(&mut self) {}
+
+    fn entity(
+        &mut self,
+        len: usize,
+    ) {
+        raw::reply_with_array(self.ctx.ctx, len as _);
+    }
+
+    fn field(
+        &mut self,
+        _name: &str,
+    ) {
+    }
+
+    fn end_entity(&mut self) {}
+}
+
+/// Serializes values to JSON, nodes and relationships become objects
+/// and paths arrays of their nodes and relationships in order.
+#[derive(Default)]
+pub struct JsonWriter {
+    out: String,
+    /// Whether the innermost open array or object has no item yet.
+    first: Vec<bool>,
+    /// Whether a key was written and waits for its value.
+    after_key: bool,
+}
+
+impl JsonWriter {
+    #[must_use]
+    pub fn into_string(self) -> String {
+        self.out
+    }
+
+    /// Writes the separator expected before the next item.
+    fn item(&mut self) {
+        if self.after_key {
+            self.after_key = false;
+        } else if let Some(first) = self.first.last_mut() {
+            if !*first {
+                self.out.push(',');
+            }
+            *first = false;
+        }
+    }
+
+    fn open(
+        &mut self,
+        c: char,
+    ) {
+        self.item();
+        self.out.push(c);
+        self.first.push(true);
+    }
+
+    fn close(
+        &mut self,
+        c: char,
+    ) {
+        self.first.pop();
+        self.out.push(c);
+    }
+
+    fn quoted(
+        &mut self,
+        x: &str,
+    ) {
+        self.out.push('"');
+        for c in x.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if c < ' ' => {
+                    let _ = write!(self.out, "\\u{:04x}", c as u32);
+                }
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+impl ValueWriter for JsonWriter {
+    fn null(&mut self) {
+        self.item();
+        self.out.push_str("null");
+    }
+
+    fn bool(
+        &mut self,
+        x: bool,
+    ) {
+        self.item();
+        self.out.push_str(if x { "true" } else { "false" });
+    }
+
+    fn int(
+        &mut self,
+        x: i64,
+    ) {
+        self.item();
+        let _ = write!(self.out, "{x}");
+    }
+
+    fn float(
+        &mut self,
+        x: f64,
+    ) {
+        self.item();
+        // JSON has no NaN nor infinities
+        if x.is_finite() {
+            let _ = write!(self.out, "{x:?}");
+        } else {
+            self.out.push_str("null");
+        }
+    }
+
+    fn string(
+        &mut self,
+        x: &str,
+    ) {
+        self.item();
+        self.quoted(x);
+    }
+
+    fn list(
+        &mut self,
+        _len: usize,
+    ) {
+        self.open('[');
+    }
+
+    fn end_list(&mut self) {
+        self.close(']');
+    }
+
+    fn map(
+        &mut self,
+        _len: usize,
+    ) {
+        self.open('{');
+    }
+
+    fn key(
+        &mut self,
+        key: &str,
+    ) {
+        self.item();
+        self.quoted(key);
+        self.out.push(':');
+        self.after_key = true;
+    }
+
+    fn end_map(&mut self) {
+        self.close('}');
+    }
+
+    fn properties(
+        &mut self,
+        len: usize,
+    ) {
+        self.map(len);
+    }
+
+    fn property(
+        &mut self,
+        key: &str,
+    ) {
+        self.key(key);
+    }
+
+    fn end_properties(&mut self) {
+        self.end_map();
+    }
+
+    fn entity(
+        &mut self,
+        len: usize,
+    ) {
+        self.map(len);
+    }
+
+    fn field(
+        &mut self,
+        name: &str,
+    ) {
+        self.key(name);
+    }
+
+    fn end_entity(&mut self) {
+        self.end_map();
+    }
+}
@@ -32,6 +32,7 @@ def start_redis(release=False):
             os.remove("redis-test.log")
         redis_server = subprocess.Popen(executable="/usr/local/bin/redis-server",
                                         args=["--save", "", "--port", port, "--logfile", "redis-test.log",
+                                              "--enable-debug-command", "yes",
                                               "--loadmodule", target],
                                         stdout=subprocess.PIPE)
     while True:
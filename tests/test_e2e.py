@@ -1,14 +1,18 @@
 from decimal import Decimal
 import subprocess
 import sys
+import threading
+import time
 from typing import Counter
 import common
 from falkordb import Node, Edge, Path
 from hypothesis import given, strategies as st
 import itertools
+import json
 import math
 import pytest
-from redis import ResponseError
+import struct
+from redis import Redis, ResponseError
 
 text_st = st.text().filter(lambda s: all(0x00 < ord(c) < 0x80 for c in s))
 at_least_1_text_st = st.text("abcdefghijklmnopqrstuvwxyz", min_size=1)
@@ -1795,3 +1799,1010 @@ def test_load_csv():
         [["Charlie", "35"]],
     ]
     assert res.result_set == expected
+
+
+def test_persistence():
+    query(
+        "UNWIND range(0, 9) AS x CREATE (:N {v: x, s: toString(x)})-[:R {w: x}]->(:M {l: [x, 1.5]})",
+        write=True,
+    )
+    query("MATCH (n:N {v: 0})-[r:R]->(m:M) DELETE r, n, m", write=True)
+    query("CREATE INDEX FOR (n:N) ON (n.v)", write=True)
+
+    before = query("MATCH (n:N)-[r:R]->(m:M) RETURN n, r, m ORDER BY n.v")
+
+    common.client.connection.execute_command("DEBUG", "RELOAD")
+
+    after = query("MATCH (n:N)-[r:R]->(m:M) RETURN n, r, m ORDER BY n.v")
+    assert after.result_set == before.result_set
+
+    res = query("MATCH (n:N {v: 5}) RETURN n.s")
+    assert res.result_set == [["5"]]
+
+    # deleted ids are reused after reload
+    res = query("CREATE (n:N {v: 10}) RETURN id(n)", write=True)
+    assert res.result_set == [[0]]
+
+
+def wait_aof_rewrite():
+    while True:
+        info = common.client.connection.info("persistence")
+        if (
+            info["aof_rewrite_in_progress"] == 0
+            and info["aof_rewrite_scheduled"] == 0
+        ):
+            return
+        time.sleep(0.1)
+
+
+def test_aof_rewrite():
+    query(
+        "UNWIND range(0, 9) AS x CREATE (:N {v: x})-[:R {w: [x, 'a']}]->(:M {f: x / 2.0})",
+        write=True,
+    )
+    query("MATCH (n:N {v: 3})-[r:R]->(m:M) DELETE r, n, m", write=True)
+    query("CREATE INDEX FOR (n:N) ON (n.v)", write=True)
+
+    before = query("MATCH (n:N)-[r:R]->(m:M) RETURN n, r, m ORDER BY n.v")
+
+    conn = common.client.connection
+    conn.config_set("appendonly", "yes")
+    try:
+        wait_aof_rewrite()
+        conn.bgrewriteaof()
+        wait_aof_rewrite()
+        conn.execute_command("DEBUG", "LOADAOF")
+    finally:
+        conn.config_set("appendonly", "no")
+
+    after = query("MATCH (n:N)-[r:R]->(m:M) RETURN n, r, m ORDER BY n.v")
+    assert after.result_set == before.result_set
+
+    res = query("MATCH (n:N {v: 5}) RETURN n.v")
+    assert res.result_set == [[5]]
+
+
+def test_restore_from_client():
+    with pytest.raises(ResponseError, match="only accepted while loading"):
+        common.client.connection.execute_command(
+            "GRAPH.RESTORE", common.g.name, "CHUNK", b"\x00" * 8
+        )
+    with pytest.raises(ResponseError, match="only accepted while loading"):
+        common.client.connection.execute_command(
+            "GRAPH.RESTORE", common.g.name, "COMMIT", 1
+        )
+
+
+def test_memory_usage():
+    query("RETURN 1")
+    empty = common.client.connection.memory_usage(common.g.name)
+
+    query("UNWIND range(0, 999) AS x CREATE (:N {v: x, s: 'some text'})", write=True)
+    full = common.client.connection.memory_usage(common.g.name)
+
+    assert full > empty
+
+
+def test_graph_copy():
+    query("UNWIND range(0, 4) AS x CREATE (:N {v: x})-[:R {w: x}]->(:M)", write=True)
+    query("CREATE INDEX FOR (n:N) ON (n.v)", write=True)
+    expected = query("MATCH (n:N)-[r:R]->(m:M) RETURN n, r, m ORDER BY n.v").result_set
+
+    conn = common.client.connection
+    for name, copy in [
+        ("copy1", lambda dst: conn.execute_command("GRAPH.COPY", common.g.name, dst)),
+        ("copy2", lambda dst: conn.copy(common.g.name, dst)),
+    ]:
+        copy(name)
+        g = common.client.select_graph(name)
+        res = g.query("MATCH (n:N)-[r:R]->(m:M) RETURN n, r, m ORDER BY n.v")
+        assert res.result_set == expected
+
+        # the copy is independent of the source
+        g.query("MATCH (n:N {v: 1}) SET n.v = 10")
+        assert query("MATCH (n:N {v: 1}) RETURN n.v").result_set == [[1]]
+        g.delete()
+
+    try:
+        conn.execute_command("GRAPH.COPY", "missing", "copy3")
+        assert False, "Expected an error"
+    except ResponseError as e:
+        assert "empty key" in str(e)
+
+
+def test_explain():
+    query("UNWIND range(0, 9) AS x CREATE (:N {v: x})", write=True)
+
+    plan = common.g.execute_command(
+        "GRAPH.EXPLAIN", common.g.name, "MATCH (n:N) WHERE n.v > 5 RETURN n.v AS v"
+    )
+    plan = [line.decode() if isinstance(line, bytes) else line for line in plan]
+    assert plan[0].startswith("Project | n.v AS v")
+    assert plan[1].startswith("    Filter | n.v > 5")
+    assert plan[2] == "        NodeScan | (n:N) | Estimated rows: 10"
+
+    query("CREATE INDEX FOR (n:N) ON (n.v)", write=True)
+    plan = common.g.execute_command(
+        "GRAPH.EXPLAIN", common.g.name, "MATCH (n:N {v: 1}) RETURN n"
+    )
+    plan = [line.decode() if isinstance(line, bytes) else line for line in plan]
+    assert "Index: :N(v)" in plan[-1]
+
+
+def test_profile():
+    query("UNWIND range(0, 9) AS x CREATE (:N {v: x})", write=True)
+
+    res = common.g.execute_command(
+        "GRAPH.PROFILE", common.g.name, "MATCH (n:N) WHERE n.v > 5 RETURN n.v"
+    )
+    res = [line.decode() if isinstance(line, bytes) else line for line in res]
+    assert res[0].startswith("Project | Operator id: 0 | Records produced: 4,")
+    assert res[1].startswith("    Filter | Operator id: 1 | Records produced: 4,")
+    assert res[2].startswith("        NodeScan | Operator id: 2 | Records produced: 10,")
+    assert all(line.endswith(" ms") for line in res)
+
+
+def test_timeout():
+    query("UNWIND range(0, 999) AS x CREATE (:N {v: x})", write=True)
+
+    for run in [common.g.query, common.g.ro_query]:
+        try:
+            run("MATCH (a:N), (b:N), (c:N) RETURN count(*)", timeout=1)
+            assert False, "Expected an error"
+        except ResponseError as e:
+            assert "Query timed out" in str(e)
+
+    res = common.g.ro_query("MATCH (n:N) RETURN count(n)", timeout=1000)
+    assert res.result_set == [[1000]]
+
+    # a timed out write query must not apply any of its changes
+    try:
+        common.g.query(
+            "MATCH (a:N), (b:N) CREATE (:M {a: a.v, b: b.v})",
+            timeout=1,
+        )
+        assert False, "Expected an error"
+    except ResponseError as e:
+        assert "Query timed out" in str(e)
+    res = query("MATCH (n:M) RETURN count(n)")
+    assert res.result_set == [[0]]
+
+    res = query("CREATE (n:M) RETURN id(n)", write=True)
+    assert res.result_set == [[1000]]
+
+    common.client.connection.config_set("falkordb.TIMEOUT_DEFAULT", 1)
+    try:
+        query_exception("MATCH (a:N), (b:N), (c:N) RETURN count(*)", "Query timed out")
+    finally:
+        common.client.connection.config_set("falkordb.TIMEOUT_DEFAULT", 0)
+
+
+def test_query_mem_capacity():
+    common.client.connection.config_set("falkordb.QUERY_MEM_CAPACITY", 1024 * 1024)
+    try:
+        for q in [
+            "UNWIND range(0, 1000000) AS x RETURN x",
+            "UNWIND range(0, 1000000) AS x RETURN x ORDER BY x",
+            "UNWIND range(0, 1000000) AS x RETURN collect(x)",
+            "UNWIND range(0, 1000000) AS x RETURN x, count(x)",
+        ]:
+            query_exception(q, "Query's mem consumption exceeded capacity")
+
+        res = common.g.query("UNWIND range(0, 1000) AS x RETURN count(x)")
+        assert res.result_set == [[1001]]
+    finally:
+        common.client.connection.config_set("falkordb.QUERY_MEM_CAPACITY", 0)
+
+
+def test_profile_limits():
+    query("UNWIND range(0, 999) AS x CREATE (:N {v: x})", write=True)
+
+    conn = common.client.connection
+    for q in [
+        "MATCH (a:N), (b:N), (c:N) RETURN count(*)",
+        "MATCH (a:N), (b:N) CREATE (:M {a: a.v, b: b.v})",
+    ]:
+        with pytest.raises(ResponseError, match="Query timed out"):
+            conn.execute_command("GRAPH.PROFILE", common.g.name, q, "TIMEOUT", 1)
+    res = query("MATCH (n:M) RETURN count(n)")
+    assert res.result_set == [[0]]
+
+    conn.config_set("falkordb.QUERY_MEM_CAPACITY", 1024 * 1024)
+    try:
+        with pytest.raises(
+            ResponseError, match="Query's mem consumption exceeded capacity"
+        ):
+            conn.execute_command(
+                "GRAPH.PROFILE",
+                common.g.name,
+                "UNWIND range(0, 1000000) AS x RETURN x ORDER BY x",
+            )
+    finally:
+        conn.config_set("falkordb.QUERY_MEM_CAPACITY", 0)
+
+    res = conn.execute_command("GRAPH.PROFILE", common.g.name, "MATCH (n:N) RETURN n")
+    assert len(res) == 2
+
+
+def test_graph_config():
+    conn = common.client.connection
+    assert conn.execute_command("GRAPH.CONFIG", "GET", "TIMEOUT_DEFAULT") == [
+        b"TIMEOUT_DEFAULT",
+        0,
+    ]
+    names = [entry[0] for entry in conn.execute_command("GRAPH.CONFIG", "GET", "*")]
+    assert b"RESULTSET_SIZE" in names and b"CACHE_SIZE" in names
+
+    try:
+        conn.execute_command("GRAPH.CONFIG", "GET", "NO_SUCH_CONFIG")
+        assert False, "Expected an error"
+    except ResponseError as e:
+        assert "Unknown configuration field" in str(e)
+
+    try:
+        conn.execute_command("GRAPH.CONFIG", "SET", "CACHE_SIZE", -1)
+        assert False, "Expected an error"
+    except ResponseError:
+        pass
+
+    conn.execute_command("GRAPH.CONFIG", "SET", "RESULTSET_SIZE", 3)
+    try:
+        assert conn.config_get("falkordb.RESULTSET_SIZE") == {
+            "falkordb.RESULTSET_SIZE": "3"
+        }
+        res = query("UNWIND range(1, 10) AS x RETURN x")
+        assert res.result_set == [[1], [2], [3]]
+    finally:
+        conn.execute_command("GRAPH.CONFIG", "SET", "RESULTSET_SIZE", -1)
+
+    conn.execute_command("GRAPH.CONFIG", "SET", "CACHE_SIZE", 0)
+    try:
+        res = query("UNWIND range(1, 10) AS x RETURN count(x)")
+        assert res.result_set == [[10]]
+    finally:
+        conn.execute_command("GRAPH.CONFIG", "SET", "CACHE_SIZE", 25)
+
+
+def test_ro_query_off_main_thread():
+    query("UNWIND range(0, 299) AS x CREATE (:N {v: x})", write=True)
+
+    other = common.client.select_graph("other")
+    other.query("CREATE (:M)")
+
+    errors = []
+
+    def slow_query():
+        try:
+            res = common.g.ro_query("MATCH (a:N), (b:N), (c:N) RETURN count(*)")
+            assert res.result_set == [[300 * 300 * 300]]
+        except Exception as e:
+            errors.append(e)
+
+    def write_query():
+        try:
+            res = common.g.query("CREATE (:N {v: 300})")
+            assert res.nodes_created == 1
+            conn = common.client.connection
+            assert conn.execute_command("GRAPH.CHANGES", "test", "ENABLE") == b"OK"
+            assert conn.execute_command("GRAPH.CHANGES", "test", "DISABLE") == b"OK"
+        except Exception as e:
+            errors.append(e)
+
+    try:
+        slow = threading.Thread(target=slow_query)
+        slow.start()
+        time.sleep(0.1)
+
+        # the write waits for the slow query without stalling the main thread
+        writer = threading.Thread(target=write_query)
+        writer.start()
+        time.sleep(0.1)
+
+        assert common.client.connection.ping()
+        # reading the graph on the main thread does not wait behind the queued write
+        assert common.client.connection.execute_command("MEMORY", "USAGE", "test") > 0
+        assert common.client.connection.execute_command("GRAPH.EXPLAIN", "test", "MATCH (n:N) RETURN n")
+        assert other.ro_query("MATCH (m:M) RETURN count(m)").result_set == [[1]]
+        assert other.query("CREATE (:M)").nodes_created == 1
+        # a stalled main thread would only have served these commands after the slow query
+        busy = slow.is_alive()
+
+        slow.join()
+        writer.join()
+        assert errors == []
+        assert busy
+    finally:
+        other.delete()
+
+    assert query("MATCH (n:N) RETURN count(n)").result_set == [[301]]
+
+
+def test_concurrent_ro_queries():
+    query("UNWIND range(0, 199) AS x CREATE (:N {v: x})", write=True)
+
+    errors = []
+
+    def slow_query():
+        try:
+            res = common.g.ro_query("MATCH (a:N), (b:N), (c:N) RETURN count(*)")
+            assert res.result_set == [[200 * 200 * 200]]
+        except Exception as e:
+            errors.append(e)
+
+    def read_query(i):
+        try:
+            res = common.g.ro_query("MATCH (n:N {v: $v}) RETURN n.v", {"v": i})
+            assert res.result_set == [[i]]
+        except Exception as e:
+            errors.append(e)
+
+    slow = threading.Thread(target=slow_query)
+    slow.start()
+    time.sleep(0.1)
+
+    # read-only queries of the same graph share it with the slow query
+    start = time.time()
+    assert common.g.ro_query("MATCH (n:N) RETURN count(n)").result_set == [[200]]
+    elapsed = time.time() - start
+    busy = slow.is_alive()
+
+    readers = [threading.Thread(target=read_query, args=(i,)) for i in range(8)]
+    for reader in readers:
+        reader.start()
+    # writes interleaved with the readers see a consistent graph
+    assert query("CREATE (:N {v: 200})", write=True).nodes_created == 1
+    for reader in readers:
+        reader.join()
+    slow.join()
+    assert errors == []
+    assert not busy or elapsed < 1
+
+    assert query("MATCH (n:N) RETURN count(n)").result_set == [[201]]
+
+
+def test_parallel_scan_aggregation():
+    query("UNWIND range(0, 49999) AS x CREATE (:Person {id: x, country: x % 5})", write=True)
+
+    # large scans feeding aggregations are split between threads
+    res = query("MATCH (p:Person) RETURN p.country AS country, count(p) AS c ORDER BY country")
+    assert res.result_set == [[i, 10000] for i in range(5)]
+
+    res = query("MATCH (p:Person) WHERE p.id >= 100 RETURN count(*), sum(p.id), min(p.id), max(p.id)")
+    assert res.result_set == [[49900, sum(range(100, 50000)), 100, 49999]]
+
+    res = query("MATCH (p:Person) RETURN DISTINCT p.country AS country ORDER BY country")
+    assert res.result_set == [[i] for i in range(5)]
+
+    res = query("MATCH (p:Person) RETURN p.id ORDER BY p.id DESC LIMIT 3")
+    assert res.result_set == [[49999], [49998], [49997]]
+
+    res = common.g.ro_query("MATCH (p:Person) WITH p.country AS country, collect(p.id) AS ids RETURN country, size(ids) ORDER BY country")
+    assert res.result_set == [[i, 10000] for i in range(5)]
+
+
+def test_resp3_replies():
+    query("CREATE (:N {b: true, f: 1.5, s: 'a'})", write=True)
+
+    kwargs = common.client.connection.connection_pool.connection_kwargs
+    r = Redis(host=kwargs["host"], port=kwargs["port"], protocol=3)
+    res = r.execute_command("GRAPH.QUERY", "test", "RETURN true, false, 1.5, {a: 1, b: [2.5, false]}")
+    assert res[1] == [[True, False, 1.5, {b"a": 1, b"b": [2.5, False]}]]
+
+    res = r.execute_command("GRAPH.RO_QUERY", "test", "MATCH (n:N) RETURN n")
+    assert res[1][0][0][2] == {b"b": True, b"f": 1.5, b"s": b"a"}
+
+    # RESP2 clients keep getting strings and flat arrays
+    res = common.client.connection.execute_command("GRAPH.QUERY", "test", "RETURN true, 1.5, {a: 1}")
+    assert res[1] == [[b"true", b"1.50000000000000e+00", [b"a", 1]]]
+
+
+def test_json_format():
+    query("CREATE (:A {name: 'a \\\"q\\\"'})-[:R {w: 0.5}]->(:B)", write=True)
+
+    conn = common.client.connection
+    res = json.loads(conn.execute_command("GRAPH.QUERY", "test", "MATCH p = (a:A)-[r:R]->(b:B) RETURN a, r, p, 1 AS one, true AS t, [1.5, null] AS l, {k: 'v'} AS m", "--json"))
+    a, r, p, one, t, lst, m = res["data"][0]
+    assert res["columns"] == ["a", "r", "p", "one", "t", "l", "m"]
+    assert a == {"id": a["id"], "labels": ["A"], "properties": {"name": 'a "q"'}}
+    assert r == {"id": r["id"], "type": "R", "src_node": a["id"], "dest_node": r["dest_node"], "properties": {"w": 0.5}}
+    assert [e["id"] for e in p] == [a["id"], r["id"], r["dest_node"]]
+    assert p[1]["type"] == "R"
+    assert [one, t, lst, m] == [1, True, [1.5, None], {"k": "v"}]
+    assert res["statistics"][-1].startswith("Query internal execution time")
+
+    res = json.loads(conn.execute_command("GRAPH.RO_QUERY", "test", "MATCH (n:B) RETURN count(n) AS c", "FORMAT", "JSON"))
+    assert res["columns"] == ["c"]
+    assert res["data"] == [[1]]
+
+    with pytest.raises(ResponseError, match="Unknown FORMAT value"):
+        conn.execute_command("GRAPH.QUERY", "test", "RETURN 1", "FORMAT", "XML")
+
+
+def test_cursor():
+    # cursors belong to the client running their query, so one connection is used throughout
+    kwargs = common.client.connection.connection_pool.connection_kwargs
+    conn = Redis(host=kwargs["host"], port=kwargs["port"], single_connection_client=True)
+    res = conn.execute_command("GRAPH.QUERY", "test", "UNWIND range(1, 5) AS x CREATE (:N {v: x}) RETURN x", "CURSOR", 2)
+    assert res[1] == [[1], [2]]
+    assert res[2][0] == b"Nodes created: 5"
+    cursor = res[3]
+    assert cursor != 0
+
+    # the writes were committed with the first batch
+    res = conn.execute_command("GRAPH.CURSOR", "READ", "test", cursor)
+    assert res == [[[3], [4]], cursor]
+    res = conn.execute_command("GRAPH.CURSOR", "READ", "test", cursor, "COUNT", 10)
+    assert res == [[[5]], 0]
+    with pytest.raises(ResponseError, match="Unknown cursor"):
+        conn.execute_command("GRAPH.CURSOR", "READ", "test", cursor)
+
+    res = conn.execute_command("GRAPH.RO_QUERY", "test", "MATCH (n:N) RETURN n.v ORDER BY n.v", "CURSOR", 3)
+    assert res[1] == [[1], [2], [3]]
+    cursor = res[3]
+    assert conn.execute_command("GRAPH.CURSOR", "DEL", "test", cursor) == b"OK"
+    with pytest.raises(ResponseError, match="Unknown cursor"):
+        conn.execute_command("GRAPH.CURSOR", "DEL", "test", cursor)
+
+    # a query exhausted by the first batch has no cursor
+    res = conn.execute_command("GRAPH.RO_QUERY", "test", "MATCH (n:N) RETURN count(n)", "CURSOR", 3)
+    assert res[1] == [[5]]
+    assert res[3] == 0
+
+    # a cursor is only known to its client and on its graph
+    cursor = conn.execute_command("GRAPH.RO_QUERY", "test", "MATCH (n:N) RETURN n.v ORDER BY n.v", "CURSOR", 1)[3]
+    other = Redis(host=kwargs["host"], port=kwargs["port"], single_connection_client=True)
+    with pytest.raises(ResponseError, match="Unknown cursor"):
+        other.execute_command("GRAPH.CURSOR", "READ", "test", cursor)
+    with pytest.raises(ResponseError, match="Unknown cursor"):
+        other.execute_command("GRAPH.CURSOR", "DEL", "test", cursor)
+    conn.execute_command("GRAPH.QUERY", "other", "RETURN 1")
+    with pytest.raises(ResponseError, match="Unknown cursor"):
+        conn.execute_command("GRAPH.CURSOR", "READ", "other", cursor)
+    res = conn.execute_command("GRAPH.CURSOR", "READ", "test", cursor)
+    assert res == [[[2]], cursor]
+
+    # writes do not wait for the cursors of the graph, they invalidate them
+    other.execute_command("GRAPH.QUERY", "test", "CREATE (:N {v: 6})")
+    with pytest.raises(ResponseError, match="Cursor invalidated"):
+        conn.execute_command("GRAPH.CURSOR", "READ", "test", cursor)
+    conn.execute_command("GRAPH.DELETE", "other")
+
+    # cursors not read for CURSOR_MAX_IDLE milliseconds are dropped
+    conn.execute_command("GRAPH.CONFIG", "SET", "CURSOR_MAX_IDLE", 1)
+    try:
+        cursor = conn.execute_command("GRAPH.RO_QUERY", "test", "MATCH (n:N) RETURN n.v", "CURSOR", 1)[3]
+        time.sleep(0.5)
+        with pytest.raises(ResponseError, match="Unknown cursor"):
+            conn.execute_command("GRAPH.CURSOR", "READ", "test", cursor)
+    finally:
+        conn.execute_command("GRAPH.CONFIG", "SET", "CURSOR_MAX_IDLE", 300000)
+
+
+def test_resultset_size_truncation():
+    conn = common.client.connection
+    conn.execute_command("GRAPH.CONFIG", "SET", "RESULTSET_SIZE", 3)
+    try:
+        res = conn.execute_command("GRAPH.QUERY", "test", "UNWIND range(1, 10) AS x RETURN x")
+        assert res[1] == [[1], [2], [3]]
+        assert b"Result set truncated: 1" in res[2]
+
+        # the writes upstream of the limit are all applied
+        res = conn.execute_command("GRAPH.QUERY", "test", "UNWIND range(1, 10) AS x CREATE (n:N {v: x}) RETURN n.v")
+        assert res[1] == [[1], [2], [3]]
+        assert b"Nodes created: 10" in res[2]
+        assert b"Result set truncated: 1" in res[2]
+
+        res = conn.execute_command("GRAPH.RO_QUERY", "test", "MATCH (n:N) RETURN n.v LIMIT 3")
+        assert len(res[1]) == 3
+        assert b"Result set truncated: 1" not in res[2]
+    finally:
+        conn.execute_command("GRAPH.CONFIG", "SET", "RESULTSET_SIZE", -1)
+
+
+def test_effects_replication():
+    query("UNWIND range(0, 4) AS x CREATE (:N {v: x})", write=True)
+
+    conn = common.client.connection
+    conn.config_set("appendonly", "yes")
+    try:
+        wait_aof_rewrite()
+        # the AOF gets the effects of the writes, not the queries
+        query("MATCH (n:N) SET n.r = rand()", write=True)
+        query("MATCH (n:N {v: 2}) DELETE n", write=True)
+        query("CREATE (:N:M {v: rand()})-[:R {t: timestamp()}]->(:N {v: 5})", write=True)
+        query("MATCH (n:N {v: 0}) SET n:K", write=True)
+        query("MATCH (n:K) REMOVE n:N", write=True)
+        before = query("MATCH (n) OPTIONAL MATCH (n)-[r]->() RETURN n, r ORDER BY id(n)")
+        conn.execute_command("DEBUG", "LOADAOF")
+    finally:
+        conn.config_set("appendonly", "no")
+
+    after = query("MATCH (n) OPTIONAL MATCH (n)-[r]->() RETURN n, r ORDER BY id(n)")
+    assert after.result_set == before.result_set
+
+
+def test_effect_from_client():
+    with pytest.raises(ResponseError, match="only accepted while loading"):
+        common.client.connection.execute_command("GRAPH.EFFECT", common.g.name, b"\x00" * 8)
+    res = query("MATCH (n) RETURN count(n)")
+    assert res.result_set == [[0]]
+
+
+def test_keyspace_notifications():
+    conn = common.client.connection
+    conn.config_set("notify-keyspace-events", "KEd")
+    try:
+        pubsub = conn.pubsub()
+        pubsub.psubscribe("__keyevent@0__:graph.*")
+        assert pubsub.get_message(timeout=1)["type"] == "psubscribe"
+
+        query("CREATE (:N {v: 1})-[:R]->(:N)", write=True)
+        query("MATCH (n:N) DETACH DELETE n", write=True)
+        common.g.ro_query("MATCH (n) RETURN n")
+
+        events = []
+        while (message := pubsub.get_message(timeout=1)) is not None:
+            assert message["data"] == common.g.name.encode()
+            events.append(message["channel"].decode().split(":")[1])
+        assert events == [
+            "graph.labels_added",
+            "graph.nodes_created",
+            "graph.relationships_created",
+            "graph.properties_set",
+            "graph.nodes_deleted",
+            "graph.relationships_deleted",
+        ]
+        pubsub.close()
+    finally:
+        conn.config_set("notify-keyspace-events", "")
+
+
+def test_change_capture():
+    query("RETURN 1", write=True)
+    conn = common.client.connection
+    try:
+        conn.execute_command("GRAPH.CHANGES", common.g.name, "READ")
+        assert False, "Expected an error"
+    except ResponseError as e:
+        assert "Change capture is not enabled on this graph" in str(e)
+
+    assert conn.execute_command("GRAPH.CHANGES", common.g.name, "ENABLE", "CAPACITY", 4) == b"OK"
+    res = query("CREATE (a:N {v: 1})-[r:R]->(b:N) RETURN id(a), id(r), id(b)", write=True)
+    a, r, b = res.result_set[0]
+    assert conn.execute_command("GRAPH.CHANGES", common.g.name, "READ") == [
+        [3, b"relationship_created", r, b"R", a, b],
+        [4, b"label_added", a, b"N"],
+        [5, b"label_added", b, b"N"],
+        [6, b"node_property_set", a, b"v", None, 1],
+    ]
+    assert conn.execute_command("GRAPH.CHANGES", common.g.name, "READ", "FROM", 4, "COUNT", 1) == [
+        [5, b"label_added", b, b"N"],
+    ]
+
+    # READ is served when writes are denied
+    conn.config_set("min-replicas-to-write", 1)
+    try:
+        assert len(conn.execute_command("GRAPH.CHANGES", common.g.name, "READ")) == 4
+    finally:
+        conn.config_set("min-replicas-to-write", 0)
+
+    query("MATCH (a:N {v: 1}) SET a.v = 2 REMOVE a:N", write=True)
+    query("MATCH ()-[r:R]->() DELETE r", write=True)
+    assert conn.execute_command("GRAPH.CHANGES", common.g.name, "READ", "FROM", 6) == [
+        [7, b"label_removed", a, b"N"],
+        [8, b"node_property_set", a, b"v", 1, 2],
+        [9, b"relationship_deleted", r, a, b],
+    ]
+
+    assert conn.execute_command("GRAPH.CHANGES", common.g.name, "DISABLE") == b"OK"
+    query("CREATE ()", write=True)
+    try:
+        conn.execute_command("GRAPH.CHANGES", common.g.name, "READ")
+        assert False, "Expected an error"
+    except ResponseError as e:
+        assert "Change capture is not enabled on this graph" in str(e)
+
+
+def test_change_capture_replication():
+    query("RETURN 1", write=True)
+    conn = common.client.connection
+    conn.config_set("appendonly", "yes")
+    try:
+        wait_aof_rewrite()
+        # ENABLE reaches the AOF, so the effects replayed after it are captured again
+        assert conn.execute_command("GRAPH.CHANGES", common.g.name, "ENABLE") == b"OK"
+        res = query("CREATE (n) RETURN id(n)", write=True)
+        conn.execute_command("DEBUG", "LOADAOF")
+        assert conn.execute_command("GRAPH.CHANGES", common.g.name, "READ") == [
+            [1, b"node_created", res.result_set[0][0]],
+        ]
+
+        assert conn.execute_command("GRAPH.CHANGES", common.g.name, "DISABLE") == b"OK"
+        conn.execute_command("DEBUG", "LOADAOF")
+        with pytest.raises(ResponseError, match="Change capture is not enabled"):
+            conn.execute_command("GRAPH.CHANGES", common.g.name, "READ")
+    finally:
+        conn.config_set("appendonly", "no")
+
+
+def bulk_u64(x):
+    return struct.pack("<Q", x)
+
+
+def bulk_str(x):
+    return bulk_u64(len(x.encode())) + x.encode()
+
+
+def bulk_names(names):
+    return bulk_u64(len(names)) + b"".join(bulk_str(name) for name in names)
+
+
+def bulk_value(value):
+    if value is None:
+        return bulk_u64(0)
+    if isinstance(value, bool):
+        return bulk_u64(1) + bulk_u64(int(value))
+    if isinstance(value, int):
+        return bulk_u64(2) + struct.pack("<q", value)
+    if isinstance(value, float):
+        return bulk_u64(3) + struct.pack("<d", value)
+    if isinstance(value, str):
+        return bulk_u64(4) + bulk_str(value)
+    return bulk_u64(5) + bulk_u64(len(value)) + b"".join(bulk_value(v) for v in value)
+
+
+def bulk_nodes(labels, attrs, nodes):
+    payload = bulk_names(labels) + bulk_names(attrs) + bulk_u64(len(nodes))
+    for values in nodes:
+        payload += b"".join(bulk_value(v) for v in values)
+    return payload
+
+
+def bulk_relationships(type_name, attrs, relationships):
+    payload = bulk_str(type_name) + bulk_names(attrs) + bulk_u64(len(relationships))
+    for src, dest, *values in relationships:
+        payload += bulk_u64(src) + bulk_u64(dest)
+        payload += b"".join(bulk_value(v) for v in values)
+    return payload
+
+
+def test_bulk_insert():
+    conn = common.client.connection
+    persons = bulk_nodes(
+        ["Person"], ["name", "age"], [["Alice", 30], ["Bob", None], ["Carol", 25]]
+    )
+    cities = bulk_nodes(["City"], ["name", "tags"], [["Paris", ["fr", "eu"]]])
+    knows = bulk_relationships("KNOWS", ["since"], [[0, 1, 2020], [1, 2, None]])
+    lives = bulk_relationships("LIVES_IN", [], [[0, 3], [2, 3]])
+    res = conn.execute_command(
+        "GRAPH.BULK", common.g.name, "BEGIN", 4, 4, 2, 2, persons, cities, knows, lives
+    )
+    assert res == b"4 nodes created, 4 relations created"
+
+    res = query("MATCH (p:Person) RETURN id(p), p.name, p.age ORDER BY id(p)")
+    assert res.result_set == [[0, "Alice", 30], [1, "Bob", None], [2, "Carol", 25]]
+    res = query("MATCH (c:City) RETURN id(c), c.name, c.tags")
+    assert res.result_set == [[3, "Paris", ["fr", "eu"]]]
+    res = query(
+        "MATCH (a)-[r:KNOWS]->(b) RETURN a.name, r.since, b.name ORDER BY a.name"
+    )
+    assert res.result_set == [["Alice", 2020, "Bob"], ["Bob", None, "Carol"]]
+    res = query("MATCH (p)-[:LIVES_IN]->(c:City) RETURN p.name ORDER BY p.name")
+    assert res.result_set == [["Alice"], ["Carol"]]
+
+    more = bulk_nodes(["Person"], ["name"], [["Dave"]])
+    knows = bulk_relationships("KNOWS", [], [[4, 0]])
+    res = conn.execute_command("GRAPH.BULK", common.g.name, 1, 1, 1, 1, more, knows)
+    assert res == b"1 nodes created, 1 relations created"
+    res = query("MATCH (:Person {name: 'Dave'})-[:KNOWS]->(p) RETURN p.name")
+    assert res.result_set == [["Alice"]]
+
+    for args, message in [
+        (["BEGIN", 1, 0, 1, 0, more], "already exists"),
+        ([2, 0, 1, 0, more], "expected 2 and 0"),
+        ([0, 1, 0, 1, bulk_relationships("R", [], [[0, 100]])], "missing node 100"),
+        ([1, 0, 1, 0, more + b"x"], "Unexpected data after bulk batch"),
+    ]:
+        try:
+            conn.execute_command("GRAPH.BULK", common.g.name, *args)
+            assert False, "Expected an error"
+        except ResponseError as e:
+            assert message in str(e)
+    res = query("MATCH (n) RETURN count(n)")
+    assert res.result_set == [[5]]
+
+
+def test_var_length_traversal():
+    query(
+        """CREATE (a:P {n: 'a'})-[:K {w: 1}]->(b:P {n: 'b'})-[:K {w: 1}]->(c:P {n: 'c'}),
+                  (c)-[:K {w: 2}]->(d:P {n: 'd'})-[:K {w: 1}]->(a)""",
+        write=True,
+    )
+    for pattern, expected in [
+        ("-[:K*1..2]->", ["b", "c"]),
+        ("-[:K*2]->", ["c"]),
+        ("-[:K*0..1]->", ["a", "b"]),
+        ("-[:K*]->", ["a", "b", "c", "d"]),
+        ("-[:K*..3]->", ["b", "c", "d"]),
+        ("-[:K*2]-", ["c", "c"]),
+        ("-[:K*1.. {w: 1}]->", ["b", "c"]),
+    ]:
+        res = query(
+            f"MATCH (x:P){pattern}(y) WHERE x.n = 'a' RETURN y.n ORDER BY y.n"
+        )
+        assert [row[0] for row in res.result_set] == expected
+
+    res = query(
+        "MATCH (x:P)-[r:K*2]->(y) WHERE x.n = 'a' RETURN size(r), [rel IN r | rel.w]"
+    )
+    assert res.result_set == [[2, [1, 1]]]
+
+    res = query(
+        "MATCH p = (x:P)-[:K*3]->(y) WHERE x.n = 'a' RETURN [n IN nodes(p) | n.n], size(relationships(p))"
+    )
+    assert res.result_set == [[["a", "b", "c", "d"], 3]]
+    res = query(
+        "MATCH p = (y)<-[:K*2]-(x:P) WHERE x.n = 'a' RETURN [n IN nodes(p) | n.n]"
+    )
+    assert res.result_set == [[["c", "b", "a"]]]
+
+    query_exception(
+        "CREATE (a)-[:K*2]->(b)",
+        "Variable length relationships cannot be used in CREATE.",
+    )
+
+
+def test_var_length_traversal_limits():
+    # every ordering of the nodes of a complete graph is a path, too many to enumerate
+    query("UNWIND range(1, 12) AS x CREATE (:D {v: x})", write=True)
+    query("MATCH (a:D), (b:D) WHERE a.v <> b.v CREATE (a)-[:R]->(b)", write=True)
+
+    for run in [common.g.query, common.g.ro_query]:
+        with pytest.raises(ResponseError, match="Query timed out"):
+            run("MATCH (a:D {v: 1})-[:R*]->(b) RETURN count(b)", timeout=100)
+
+    common.client.connection.config_set("falkordb.QUERY_MEM_CAPACITY", 1024 * 1024)
+    try:
+        query_exception("MATCH (a:D {v: 1})-[:R*]->(b) RETURN b.v", "Query's mem consumption exceeded capacity")
+    finally:
+        common.client.connection.config_set("falkordb.QUERY_MEM_CAPACITY", 0)
+
+
+def test_shortest_path():
+    query(
+        """CREATE (a:P {n: 'a'})-[:K]->(b:P {n: 'b'})-[:K]->(c:P {n: 'c'}),
+                  (a)-[:K]->(e:P {n: 'e'})-[:K]->(c),
+                  (c)-[:K]->(d:P {n: 'd'})-[:K]->(a)""",
+        write=True,
+    )
+    res = query(
+        """MATCH (a:P {n: 'a'}), (c:P {n: 'c'}), p = shortestPath((a)-[:K*]->(c))
+           RETURN size(relationships(p)), nodes(p)[0].n, nodes(p)[2].n"""
+    )
+    assert res.result_set == [[2, "a", "c"]]
+
+    res = query(
+        """MATCH (a:P {n: 'a'}), (c:P {n: 'c'}), p = allShortestPaths((a)-[:K*]->(c))
+           RETURN nodes(p)[1].n ORDER BY nodes(p)[1].n"""
+    )
+    assert res.result_set == [["b"], ["e"]]
+
+    for pattern, expected in [
+        ("shortestPath((a)-[:K*]->(d))", [[3]]),
+        ("shortestPath((a)-[:K*]-(d))", [[1]]),
+        ("shortestPath((a)<-[:K*]-(d))", [[1]]),
+        ("shortestPath((a)-[:K*..2]->(d))", []),
+        ("shortestPath((a)-[:K]->(d))", []),
+        ("shortestPath((a)-[:OTHER*]-(d))", []),
+    ]:
+        res = query(
+            f"MATCH (a:P {{n: 'a'}}), (d:P {{n: 'd'}}), p = {pattern} RETURN size(relationships(p))"
+        )
+        assert res.result_set == expected
+
+    res = query(
+        """MATCH (a:P {n: 'a'}), p = shortestPath((a)-[:K*0..]->(a))
+           RETURN size(nodes(p)), size(relationships(p))"""
+    )
+    assert res.result_set == [[1, 0]]
+
+    query_exception(
+        "MATCH (a), (b), p = shortestPath((a)-[*2..]->(b)) RETURN p",
+        "shortestPath does not support a minimal length different from 0 or 1",
+    )
+    query_exception(
+        "MATCH (a), (b), p = allShortestPaths((a)-[*2..]->(b)) RETURN p",
+        "shortestPath does not support a minimal length different from 0 or 1",
+    )
+    query_exception(
+        "MATCH (a), (b), shortestPath((a)-[r*2..3]->(b)) RETURN r",
+        "shortestPath does not support a minimal length different from 0 or 1",
+    )
+    query_exception(
+        "MATCH (a), (b), p = shortestPath((a)-[]->()-[]->(b)) RETURN p",
+        "shortestPath requires a path containing a single relationship.",
+    )
+
+    # the path does not have to be named
+    res = query(
+        "MATCH (a:P {n: 'a'}), (d:P {n: 'd'}), shortestPath((a)-[r:K*]->(d)) RETURN size(r)"
+    )
+    assert res.result_set == [[3]]
+    query_exception(
+        "MATCH (a), (b), longestPath((a)-[*]->(b)) RETURN a",
+        "Unknown function 'longestPath'",
+    )
+
+
+def test_shortest_path_limits():
+    # 10 nodes on each of 9 layers, every node linked to the whole next layer
+    query("UNWIND range(0, 8) AS l UNWIND range(0, 9) AS i CREATE (:L {l: l, i: i})", write=True)
+    query("MATCH (a:L), (b:L) WHERE b.l = a.l + 1 CREATE (a)-[:R]->(b)", write=True)
+
+    # 10^7 shortest paths between the first and the last layer
+    for run in [common.g.query, common.g.ro_query]:
+        with pytest.raises(ResponseError, match="Query timed out"):
+            run(
+                """MATCH (a:L {l: 0, i: 0}), (b:L {l: 8, i: 0}), p = allShortestPaths((a)-[:R*]->(b))
+                   RETURN count(p)""",
+                timeout=100,
+            )
+
+
+def test_union():
+    res = query("RETURN 1 AS x UNION RETURN 2 AS x UNION RETURN 1 AS x")
+    assert res.result_set == [[1], [2]]
+
+    res = query("RETURN 1 AS x UNION ALL RETURN 2 AS x UNION ALL RETURN 1 AS x")
+    assert res.result_set == [[1], [2], [1]]
+
+    query("CREATE (:A {v: 1}), (:A {v: 2}), (:B {v: 2}), (:B {v: 3})", write=True)
+    res = query(
+        "MATCH (n:A) RETURN n.v AS v, 'a' AS l UNION MATCH (n:B) RETURN n.v AS v, 'b' AS l"
+    )
+    assert sorted(res.result_set) == [[1, "a"], [2, "a"], [2, "b"], [3, "b"]]
+    res = query("MATCH (n:A) RETURN n.v AS v UNION MATCH (m:B) RETURN m.v AS v")
+    assert sorted(res.result_set) == [[1], [2], [3]]
+    res = query("MATCH (n:A) RETURN n.v AS v UNION ALL MATCH (n:B) RETURN n.v AS v")
+    assert sorted(res.result_set) == [[1], [2], [2], [3]]
+
+    res = query(
+        "UNWIND [1, 2] AS x RETURN x UNION CREATE (n:C {v: 3}) RETURN n.v AS x",
+        write=True,
+    )
+    assert sorted(res.result_set) == [[1], [2], [3]]
+    assert res.nodes_created == 1
+
+    query_exception(
+        "RETURN 1 AS x UNION RETURN 2 AS y",
+        "All sub queries in an UNION must have the same column names.",
+    )
+    query_exception(
+        "RETURN 1 AS x UNION RETURN 2 AS x UNION ALL RETURN 3 AS x",
+        "Invalid combination of UNION and UNION ALL.",
+    )
+
+
+def test_foreach():
+    res = query("FOREACH (x IN [1, 2, 3] | CREATE (:F {v: x}))", write=True)
+    assert res.nodes_created == 3
+    assert res.properties_set == 3
+    res = query("MATCH (n:F) RETURN n.v ORDER BY n.v")
+    assert res.result_set == [[1], [2], [3]]
+
+    res = query(
+        "MATCH (n:F) FOREACH (_ IN CASE WHEN n.v > 1 THEN [1] ELSE [] END | SET n.big = true)",
+        write=True,
+    )
+    assert res.properties_set == 2
+    res = query("MATCH (n:F) RETURN n.v, n.big ORDER BY n.v")
+    assert res.result_set == [[1, None], [2, True], [3, True]]
+
+    res = query(
+        """MATCH (n:F {v: 1})
+           FOREACH (x IN [10, 20] | CREATE (n)-[:R]->(:G {v: x}))
+           RETURN n.v""",
+        write=True,
+    )
+    assert res.result_set == [[1]]
+    assert res.nodes_created == 2
+    assert res.relationships_created == 2
+
+    res = query(
+        "FOREACH (x IN [1, 2] | FOREACH (y IN [x, x * 10] | MERGE (:H {v: y})))",
+        write=True,
+    )
+    assert res.nodes_created == 4
+    res = query("MATCH (n:H) RETURN n.v ORDER BY n.v")
+    assert res.result_set == [[1], [2], [10], [20]]
+
+    res = query("FOREACH (x IN null | CREATE (:I))", write=True)
+    assert res.nodes_created == 0
+
+    query_exception(
+        "FOREACH (x IN 1 | CREATE (:I))",
+        "Type mismatch: expected List or Null but was Integer",
+    )
+    query_exception("FOREACH (x IN [1] | CREATE (n:I)) RETURN n", "'n' not defined")
+
+
+def test_call_subquery():
+    query(
+        """CREATE (a:S {n: 'a'}), (b:S {n: 'b'}), (c:S {n: 'c'}),
+                  (a)-[:R]->(b), (a)-[:R]->(c), (b)-[:R]->(c)""",
+        write=True,
+    )
+    res = query(
+        """MATCH (x:S)
+           CALL { WITH x MATCH (x)-[:R]->(y) RETURN count(y) AS c }
+           RETURN x.n, c ORDER BY x.n"""
+    )
+    assert res.result_set == [["a", 2], ["b", 1], ["c", 0]]
+
+    res = query(
+        """MATCH (x:S)
+           CALL { WITH x MATCH (x)-[:R]->(y) RETURN y.n AS m }
+           RETURN x.n, m ORDER BY x.n, m"""
+    )
+    assert res.result_set == [["a", "b"], ["a", "c"], ["b", "c"]]
+
+    res = query("UNWIND [1, 2] AS x CALL { RETURN 10 AS y } RETURN x, y ORDER BY x")
+    assert res.result_set == [[1, 10], [2, 10]]
+
+    res = query(
+        "MATCH (x:S) CALL { WITH x SET x.seen = true } RETURN count(x)",
+        write=True,
+    )
+    assert res.result_set == [[3]]
+    assert res.properties_set == 3
+
+    res = query(
+        "UNWIND range(1, 5) AS i CALL { WITH i CREATE (:T {i: i}) } IN TRANSACTIONS OF 2 ROWS",
+        write=True,
+    )
+    assert res.nodes_created == 5
+    res = query("MATCH (t:T) RETURN count(t)")
+    assert res.result_set == [[5]]
+
+    # an error keeps the batches committed before it, replicated to the AOF
+    conn = common.client.connection
+    conn.config_set("appendonly", "yes")
+    try:
+        wait_aof_rewrite()
+        query_exception(
+            "UNWIND [1, 2, 0, 4] AS i CALL { WITH i CREATE (:U {v: 10 / i}) } IN TRANSACTIONS OF 2 ROWS",
+            "Division by zero",
+        )
+        res = query("MATCH (u:U) RETURN u.v ORDER BY u.v")
+        assert res.result_set == [[5], [10]]
+        conn.execute_command("DEBUG", "LOADAOF")
+    finally:
+        conn.config_set("appendonly", "no")
+    res = query("MATCH (u:U) RETURN u.v ORDER BY u.v")
+    assert res.result_set == [[5], [10]]
+
+    # the timeout still applies after the first batch, the committed batches are kept
+    with pytest.raises(ResponseError, match="Query timed out"):
+        common.g.query(
+            "UNWIND range(1, 1000) AS i CALL { WITH i UNWIND range(1, 20000) AS x WITH i, count(x) AS c CREATE (:V {i: i}) } IN TRANSACTIONS OF 1 ROWS",
+            timeout=100,
+        )
+    res = query("MATCH (v:V) RETURN count(v)")
+    assert 0 < res.result_set[0][0] < 1000
+
+    query_exception(
+        "MATCH (x:S) CALL { MATCH (x)-[:R]->(y) RETURN x } RETURN y",
+        "Variable `x` already declared in outer scope",
+    )
+    query_exception(
+        "WITH 1 AS x CALL { RETURN x AS y } RETURN y",
+        "'x' not defined",
+    )
+    query_exception(
+        "WITH 1 AS x CALL { WITH x AS z RETURN z } RETURN z",
+        "Importing WITH should consist only of simple references to outside variables.",
+    )
+    query_exception(
+        "WITH 1 AS x CALL { WITH x RETURN x + 1 AS y }",
+        "Query cannot conclude with CALL",
+    )